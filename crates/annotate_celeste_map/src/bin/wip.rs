@@ -1,13 +1,17 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fmt::Write,
-    path::Path,
-    time::{Duration, Instant},
+    path::{Path, PathBuf},
+    time::Instant,
 };
 
-use annotate_celeste_map::LineSettings;
+use annotate_celeste_map::{
+    record::{self, RecordedTas, RecordingId, TasToRecord},
+    watch::{self, MapRenderCache},
+    LineSettings,
+};
 use anyhow::{bail, ensure, Context, Result};
-use celestedebugrc::DebugRC;
+use celestedebugrc::{DebugRC, PlayTasProgress};
 use celesteloader::{
     cct_physics_inspector::PhysicsInspector, utils::list_dir_extension, CelesteInstallation,
 };
@@ -43,6 +47,11 @@ struct App {
     )]
     record: Option<Vec<String>>,
 
+    #[clap(long = "watch", num_args = 0..=1, help =
+        r#"Re-run TASes whenever they are saved and re-render their maps (--watch alone will watch the current directory)"#,
+    )]
+    watch: Option<Option<PathBuf>>,
+
     #[clap(long = "open", help = "Open file after annotating")]
     open: bool,
 
@@ -74,62 +83,179 @@ fn main() {
     }
 }
 
-fn record_folder(folder: impl AsRef<Path>) -> Result<()> {
+fn record_folder(
+    folder: impl AsRef<Path>,
+    physics_inspector: &PhysicsInspector,
+) -> Result<Vec<RecordingId>> {
     let folder = folder.as_ref();
     let debugrc = DebugRC::new();
 
     let mut tas_files = Vec::new();
 
     if folder.is_file() {
-        tas_files.push((
-            folder.to_path_buf(),
-            folder.file_name().unwrap().to_str().unwrap().to_owned(),
-            ("".to_owned(), "".to_owned()),
-        ));
+        tas_files.push(TasToRecord::new(folder));
     } else {
-        let mut empty = true;
         list_dir_extension::<_, anyhow::Error>(folder, "tas", |tas| {
-            empty = false;
-            tas_files.push((
-                tas.to_path_buf(),
-                tas.file_name().unwrap().to_str().unwrap().to_owned(),
-                ("".into(), "".into()),
-            ));
-
+            tas_files.push(TasToRecord::new(tas));
             Ok(())
         })?;
-        ensure!(!empty, "No TAS files found in folder {}", folder.display());
+        ensure!(
+            !tas_files.is_empty(),
+            "No TAS files found in folder {}",
+            folder.display()
+        );
     }
 
-    let run_as_merged = false;
-    debugrc.run_tases_fastforward(&tas_files, 500.0, run_as_merged, |status| {
-        if let Some(origin) = status.origin {
-            eprintln!("{origin}: {}/{}", status.current_frame, status.total_frames);
-        } else {
-            eprintln!("{}/{}", status.current_frame, status.total_frames);
+    let recorded = record::record_tases(
+        &debugrc,
+        physics_inspector,
+        &tas_files,
+        500.0,
+        print_progress,
+    )?;
+
+    let mut recordings = Vec::new();
+    for tas in recorded {
+        recordings.extend(tas.recordings?);
+    }
+
+    Ok(recordings)
+}
+
+fn print_progress(status: PlayTasProgress) {
+    let mut line = String::new();
+    if let Some(origin) = status.origin {
+        let _ = write!(&mut line, "{origin}: ");
+    }
+    let _ = write!(
+        &mut line,
+        "{}/{}",
+        status.current_frame, status.total_frames
+    );
+    if let Some(room) = &status.info.room {
+        let _ = write!(&mut line, " [{room}]");
+    }
+    if let Some(time) = status.info.chapter_time {
+        let _ = write!(&mut line, " {time}");
+    }
+    eprintln!("{line}");
+}
+
+fn watch_folder(
+    folder: &Path,
+    celeste: &CelesteInstallation,
+    physics_inspector: &PhysicsInspector,
+    args: &App,
+) -> Result<()> {
+    let debugrc = DebugRC::new();
+    let asset_db = AssetDb::new(ModLookup::all_mods(celeste)?);
+    let mut render_data = CelesteRenderData::base(celeste)?;
+    let mut cache = MapRenderCache::new();
+    let mut opened = HashSet::new();
+
+    eprintln!("Watching {} for changes", folder.display());
+
+    let mut on_recorded = |tas: RecordedTas| -> Result<()> {
+        let recordings = record::resolve_recordings(physics_inspector, &tas.recordings?)?;
+
+        let mut map_bins: HashMap<String, Vec<u32>> = HashMap::new();
+        for i in recordings {
+            match physics_inspector.room_layout(i)?.map_bin {
+                Some(map_bin) => map_bins.entry(map_bin).or_default().push(i),
+                None => eprintln!("Recording {i} of {} has no map bin, skipping", tas.name),
+            }
         }
-    })?;
 
-    Ok(())
+        for (map_bin, recordings) in map_bins {
+            let a = Instant::now();
+            let cached = cache.get_or_render(celeste, &mut render_data, &asset_db, &map_bin)?;
+
+            let mut image = cached.image.clone();
+            annotate_celeste_map::annotate_cct_recording_skia(
+                &mut image,
+                physics_inspector,
+                recordings.into_iter(),
+                cached.bounds,
+                line_settings(&args.ui, cached.density),
+            )?;
+
+            let out_path = format!("{}.png", map_bin.replace(['/'], "_"));
+            image.save_png(&out_path)?;
+            println!(
+                "{}: rendered {map_bin} in {:.2}ms",
+                tas.name,
+                a.elapsed().as_millis()
+            );
+
+            if args.open && opened.insert(out_path.clone()) {
+                opener::open(&out_path)?;
+            }
+        }
+
+        Ok(())
+    };
+
+    watch::watch(
+        folder,
+        &debugrc,
+        physics_inspector,
+        500.0,
+        print_progress,
+        |tas| {
+            if let Err(e) = on_recorded(tas) {
+                eprintln!("{e:?}");
+            }
+            Ok(())
+        },
+    )
+}
+
+fn line_settings(ui: &UiArgs, density: f32) -> LineSettings {
+    LineSettings {
+        width: ui.width.unwrap_or(if density > 0.5 { 8.0 } else { 3.0 }),
+        color_mode: ui.color.into(),
+        ..Default::default()
+    }
 }
 
 fn run(args: App) -> Result<()> {
     let celeste = CelesteInstallation::detect()?;
     let physics_inspector = PhysicsInspector::new(&celeste);
 
-    if let Some(args) = args.record {
-        if args.is_empty() {
-            let path = std::env::current_dir()?;
-            record_folder(path)?;
+    if let Some(folder) = &args.watch {
+        let folder = match folder {
+            Some(folder) => folder.clone(),
+            None => std::env::current_dir()?,
+        };
+        return watch_folder(&folder, &celeste, &physics_inspector, &args);
+    }
+
+    let recorded = match args.record {
+        Some(args) if args.is_empty() => {
+            Some(record_folder(std::env::current_dir()?, &physics_inspector)?)
         }
-        for arg in args {
-            record_folder(&arg)?;
+        Some(args) => {
+            let mut recordings = Vec::new();
+            for arg in args {
+                recordings.extend(record_folder(&arg, &physics_inspector)?);
+            }
+            Some(recordings)
         }
-        std::thread::sleep(Duration::from_millis(500));
-    }
+        None => None,
+    };
+    // only now that everything is recorded, the indices stay the same
+    let recorded = recorded
+        .map(|recorded| record::resolve_recordings(&physics_inspector, &recorded))
+        .transpose()?;
 
     let mut map_bins: HashMap<String, Vec<u32>> = HashMap::new();
     for (i, layout) in physics_inspector.recent_recordings()? {
+        if recorded
+            .as_ref()
+            .is_some_and(|recorded| !recorded.contains(&i))
+        {
+            continue;
+        }
         if !matches_filter(i, &layout.chapter_name, args.filter.as_deref()) {
             continue;
         }
@@ -148,7 +274,7 @@ fn run(args: App) -> Result<()> {
         bail!("no physics recordings found");
     }
 
-    let mut asset_db = AssetDb::new(ModLookup::all_mods(&celeste)?);
+    let asset_db = AssetDb::new(ModLookup::all_mods(&celeste)?);
     let mut render_data = CelesteRenderData::base(&celeste)?;
 
     for (map_bin, recordings) in map_bins {
@@ -156,7 +282,7 @@ fn run(args: App) -> Result<()> {
         let (mut result, map) = celesterender::render_map_bin(
             &celeste,
             &mut render_data,
-            &mut asset_db,
+            &asset_db,
             &map_bin,
             RenderMapSettings::default(),
         )
@@ -184,21 +310,12 @@ fn run(args: App) -> Result<()> {
         let density = size_filled / size;
 
         for recording in recordings {
-            let width = args
-                .ui
-                .width
-                .unwrap_or(if density > 0.5 { 8.0 } else { 3.0 });
-
             annotate_celeste_map::annotate_cct_recording_skia(
                 &mut result.image,
                 &physics_inspector,
                 [recording].into_iter(),
                 result.bounds,
-                LineSettings {
-                    width,
-                    color_mode: args.ui.color.into(),
-                    ..Default::default()
-                },
+                line_settings(&args.ui, density),
             )?;
         }
 
@@ -14,6 +14,9 @@ use tiny_skia::{
     Transform,
 };
 
+pub mod record;
+pub mod watch;
+
 const CONNECTION_COLOR_ANITIALIASING: bool = false;
 const CONNECTION_COLOR_TRANSPARENCY: u8 = 100;
 
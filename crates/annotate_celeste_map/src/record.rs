@@ -0,0 +1,213 @@
+//! Play TASes and collect the physics inspector recordings they produce.
+
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context, Result};
+use celestedebugrc::{DebugRC, PlayTasProgress, TasRunResult};
+use celesteloader::cct_physics_inspector::{CCTRoomLayout, PhysicsInspector};
+
+/// How long to wait for recordings to show up after a TAS finished
+const APPEAR_TIMEOUT: Duration = Duration::from_secs(3);
+/// How long to wait for recordings to be fully written
+const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub struct TasToRecord {
+    pub path: PathBuf,
+    pub name: String,
+    /// TAS text to run before and after the file
+    pub decorate: (String, String),
+}
+
+impl TasToRecord {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        TasToRecord {
+            path,
+            name,
+            decorate: Default::default(),
+        }
+    }
+}
+
+/// A physics inspector recording, identified by the time it was started.
+///
+/// The physics inspector shifts the indices in `recent-recordings` whenever a new recording starts,
+/// so they are only looked up with [`resolve_recordings`] once all TASes were recorded.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RecordingId(pub String);
+
+impl std::fmt::Display for RecordingId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "recording started at {}", self.0)
+    }
+}
+
+#[derive(Debug)]
+pub struct RecordedTas {
+    pub path: PathBuf,
+    pub name: String,
+    pub result: TasRunResult,
+    /// The recordings produced by this TAS, or an error if none showed up after it finished
+    pub recordings: Result<Vec<RecordingId>>,
+}
+
+/// Plays each TAS and waits until the physics inspector recordings it produced are fully written.
+///
+/// TASes are run one by one, so that every recording can be tied to the TAS it came from.
+pub fn record_tases(
+    debugrc: &DebugRC,
+    physics_inspector: &PhysicsInspector,
+    tases: &[TasToRecord],
+    speedup: f32,
+    mut progress: impl FnMut(PlayTasProgress),
+) -> Result<Vec<RecordedTas>> {
+    let mut results = Vec::with_capacity(tases.len());
+
+    for (i, tas) in tases.iter().enumerate() {
+        let before = scan_recordings(&physics_inspector.recent_recordings)?
+            .into_values()
+            .map(|recording| recording.recording_started)
+            .collect::<HashSet<_>>();
+
+        let mut run = debugrc.run_tases_fastforward(
+            &[(&tas.path, tas.name.clone(), tas.decorate.clone())],
+            speedup,
+            false,
+            |p| {
+                progress(PlayTasProgress {
+                    current_file: i,
+                    total_files: tases.len(),
+                    ..p
+                })
+            },
+        )?;
+        let result = run.pop().unwrap_or_default();
+
+        let recordings = wait_for_new_recordings(&physics_inspector.recent_recordings, &before)
+            .with_context(|| format!("failed to collect recordings of {}", tas.name))?;
+        let recordings = match recordings.is_empty() {
+            true => Err(anyhow!(
+                "{} did not produce a physics recording within {APPEAR_TIMEOUT:?}",
+                tas.name
+            )),
+            false => Ok(recordings),
+        };
+
+        results.push(RecordedTas {
+            path: tas.path.clone(),
+            name: tas.name.clone(),
+            result,
+            recordings,
+        });
+    }
+
+    Ok(results)
+}
+
+#[derive(PartialEq, Eq)]
+struct Recording {
+    recording_started: String,
+    sizes: (u64, u64),
+}
+
+/// Looks up the current indices of `recordings` in `recent-recordings`
+pub fn resolve_recordings(
+    physics_inspector: &PhysicsInspector,
+    recordings: &[RecordingId],
+) -> Result<Vec<u32>> {
+    let indices = scan_recordings(&physics_inspector.recent_recordings)?
+        .into_iter()
+        .map(|(i, recording)| (recording.recording_started, i))
+        .collect::<HashMap<_, _>>();
+
+    recordings
+        .iter()
+        .map(|id| {
+            indices
+                .get(&id.0)
+                .copied()
+                .ok_or_else(|| anyhow!("{id} is no longer in the recent recordings"))
+        })
+        .collect()
+}
+
+/// Finds recordings which are not in `before` and waits until their files stop changing
+fn wait_for_new_recordings(dir: &Path, before: &HashSet<String>) -> Result<Vec<RecordingId>> {
+    let start = Instant::now();
+    let mut previous: Option<HashMap<u32, Recording>> = None;
+
+    loop {
+        let mut new = scan_recordings(dir)?;
+        new.retain(|_, recording| !before.contains(&recording.recording_started));
+
+        let elapsed = start.elapsed();
+        if new.is_empty() && elapsed > APPEAR_TIMEOUT {
+            return Ok(Vec::new());
+        }
+        if !new.is_empty() && previous.as_ref() == Some(&new) {
+            let mut recordings = new.into_iter().collect::<Vec<_>>();
+            recordings.sort_by_key(|&(i, _)| i);
+            return Ok(recordings
+                .into_iter()
+                .map(|(_, recording)| RecordingId(recording.recording_started))
+                .collect());
+        }
+        if elapsed > WRITE_TIMEOUT {
+            anyhow::bail!("recordings were still being written after {WRITE_TIMEOUT:?}");
+        }
+
+        previous = Some(new);
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Lists recordings with both files present and a room layout which can be parsed.
+/// Incomplete recordings are skipped instead of failing.
+fn scan_recordings(dir: &Path) -> Result<HashMap<u32, Recording>> {
+    let mut recordings = HashMap::new();
+
+    let entries = match dir.read_dir() {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(recordings),
+        Err(e) => return Err(e).context("failed to read recent physics inspector logs"),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        let Some(i) = path
+            .file_name()
+            .and_then(OsStr::to_str)
+            .and_then(|name| name.strip_suffix("_room-layout.json"))
+            .and_then(|i| i.parse::<u32>().ok())
+        else {
+            continue;
+        };
+
+        let position_log = dir.join(format!("{i}_position-log.txt"));
+        let (Ok(layout_meta), Ok(log_meta)) = (path.metadata(), position_log.metadata()) else {
+            continue;
+        };
+        let Ok(layout) = CCTRoomLayout::from_file(&path) else {
+            continue;
+        };
+
+        recordings.insert(
+            i,
+            Recording {
+                recording_started: layout.recording_started,
+                sizes: (layout_meta.len(), log_meta.len()),
+            },
+        );
+    }
+
+    Ok(recordings)
+}
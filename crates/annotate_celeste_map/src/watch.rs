@@ -0,0 +1,208 @@
+//! Re-run TASes when they or the files they `Read` are saved and collect their new recordings.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use celestedebugrc::{DebugRC, PlayTasProgress};
+use celesteloader::{
+    cct_physics_inspector::PhysicsInspector, map::Bounds, tas::resolve::Resolver,
+    utils::list_dir_extension, CelesteInstallation,
+};
+use celesterender::{
+    asset::{AssetDb, LookupAsset},
+    CelesteRenderData, RenderMapSettings,
+};
+use tiny_skia::Pixmap;
+
+use crate::record::{self, RecordedTas, TasToRecord};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+/// Editors may save a file in multiple steps, so wait a bit before running it
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Detects created or modified `.tas` files in a folder by polling their modification times.
+///
+/// A file also counts as changed when one of the files it pulls in with `Read` changes.
+pub struct TasFolderWatcher {
+    folder: PathBuf,
+    mtimes: HashMap<PathBuf, SystemTime>,
+    /// Files read by each `.tas` file of the folder, keyed by canonical path
+    dependencies: HashMap<PathBuf, Vec<PathBuf>>,
+}
+
+impl TasFolderWatcher {
+    /// Files already present are only reported once they change.
+    pub fn new(folder: impl Into<PathBuf>) -> Result<Self> {
+        let mut watcher = TasFolderWatcher {
+            folder: folder.into(),
+            mtimes: HashMap::new(),
+            dependencies: HashMap::new(),
+        };
+        watcher.changed()?;
+        Ok(watcher)
+    }
+
+    /// Returns the files which were created or modified since the last call, or read a file that was
+    pub fn changed(&mut self) -> Result<Vec<PathBuf>> {
+        let files = list_dir_extension::<_, std::io::Error>(&self.folder, "tas", |path| {
+            Ok((path.to_owned(), std::fs::canonicalize(path)?))
+        })
+        .with_context(|| format!("failed to list {}", self.folder.display()))?;
+
+        let present = files
+            .iter()
+            .map(|(_, canonical)| canonical.clone())
+            .collect::<HashSet<_>>();
+        let watched = files
+            .iter()
+            .map(|(_, canonical)| canonical)
+            .chain(self.dependencies.values().flatten())
+            .collect::<HashSet<_>>();
+        let mut modified = HashSet::new();
+        for path in watched {
+            // deleted dependencies show up as errors when running the TAS
+            let Ok(mtime) = path.metadata().and_then(|meta| meta.modified()) else {
+                continue;
+            };
+            if self.mtimes.insert(path.clone(), mtime) != Some(mtime) {
+                modified.insert(path.clone());
+            }
+        }
+
+        let mut changed = files
+            .into_iter()
+            .filter(|(_, canonical)| {
+                modified.contains(canonical)
+                    || self
+                        .dependencies
+                        .get(canonical)
+                        .is_some_and(|dependencies| {
+                            dependencies
+                                .iter()
+                                .any(|dependency| modified.contains(dependency))
+                        })
+            })
+            .collect::<Vec<_>>();
+        changed.sort();
+
+        // a change may have added or removed `Read`s
+        let mut resolver = Resolver::new();
+        for (_, canonical) in &changed {
+            let dependencies = resolver.dependencies(canonical).unwrap_or_else(|e| {
+                eprintln!("{e}");
+                Vec::new()
+            });
+            for dependency in &dependencies {
+                if let Ok(mtime) = dependency.metadata().and_then(|meta| meta.modified()) {
+                    self.mtimes.entry(dependency.clone()).or_insert(mtime);
+                }
+            }
+            self.dependencies.insert(canonical.clone(), dependencies);
+        }
+        self.dependencies.retain(|path, _| present.contains(path));
+
+        let changed = changed.into_iter().map(|(path, _)| path).collect();
+        Ok(changed)
+    }
+}
+
+/// Watches `folder` and records every TAS when it changes, until the `debugrc` is cancelled.
+///
+/// Errors while running a TAS are reported and skipped, so a broken TAS doesn't end the session.
+pub fn watch(
+    folder: &Path,
+    debugrc: &DebugRC,
+    physics_inspector: &PhysicsInspector,
+    speedup: f32,
+    mut progress: impl FnMut(PlayTasProgress),
+    mut on_recorded: impl FnMut(RecordedTas) -> Result<()>,
+) -> Result<()> {
+    let mut watcher = TasFolderWatcher::new(folder)?;
+    let cancel = debugrc.cancel_handle();
+
+    while !cancel.is_cancelled() {
+        let mut changed = watcher.changed()?;
+        if changed.is_empty() {
+            std::thread::sleep(POLL_INTERVAL);
+            continue;
+        }
+
+        std::thread::sleep(DEBOUNCE);
+        changed.extend(watcher.changed()?);
+        changed.sort();
+        changed.dedup();
+
+        let tases = changed
+            .into_iter()
+            .map(TasToRecord::new)
+            .collect::<Vec<_>>();
+        match record::record_tases(debugrc, physics_inspector, &tases, speedup, &mut progress) {
+            Ok(recorded) => recorded.into_iter().try_for_each(&mut on_recorded)?,
+            Err(_) if cancel.is_cancelled() => break,
+            Err(e) => eprintln!("{e:?}"),
+        }
+    }
+
+    Ok(())
+}
+
+pub struct CachedMap {
+    pub image: Pixmap,
+    pub bounds: Bounds,
+    /// Fraction of the map area covered by rooms
+    pub density: f32,
+}
+
+/// Keeps map renders around, so that annotating a new recording doesn't re-render the map.
+#[derive(Default)]
+pub struct MapRenderCache {
+    maps: HashMap<String, CachedMap>,
+}
+
+impl MapRenderCache {
+    pub fn new() -> Self {
+        MapRenderCache::default()
+    }
+
+    pub fn get_or_render(
+        &mut self,
+        celeste: &CelesteInstallation,
+        render_data: &mut CelesteRenderData,
+        asset_db: &AssetDb<impl LookupAsset>,
+        map_bin: &str,
+    ) -> Result<&CachedMap> {
+        if !self.maps.contains_key(map_bin) {
+            let (result, map) = celesterender::render_map_bin(
+                celeste,
+                render_data,
+                asset_db,
+                map_bin,
+                RenderMapSettings::default(),
+            )
+            .with_context(|| format!("error rendering {map_bin}"))?;
+
+            let size_filled = map.rooms.iter().map(|room| room.bounds.area()).sum::<f32>();
+            let density = size_filled / result.bounds.area();
+
+            self.maps.insert(
+                map_bin.to_owned(),
+                CachedMap {
+                    image: result.image,
+                    bounds: result.bounds,
+                    density,
+                },
+            );
+        }
+
+        Ok(&self.maps[map_bin])
+    }
+
+    /// Forgets the render of `map_bin`, e.g. after the map itself was changed
+    pub fn invalidate(&mut self, map_bin: &str) {
+        self.maps.remove(map_bin);
+    }
+}
@@ -0,0 +1,30 @@
+//! mock_debugrc [PORT]
+//!
+//! Runs a fake DebugRC server, by default on the regular DebugRC port, and logs every request it receives.
+
+use std::time::Duration;
+
+use celestedebugrc::{mock::MockServer, DEFAULT_PORT};
+
+fn main() -> std::io::Result<()> {
+    let port = match std::env::args().nth(1) {
+        Some(port) => port
+            .parse()
+            .map_err(|_| std::io::Error::other(format!("invalid port: {port}")))?,
+        None => DEFAULT_PORT,
+    };
+
+    let server = MockServer::bind(&format!("127.0.0.1:{port}"))?;
+    eprintln!("listening on {}", server.address());
+
+    let mut seen = 0;
+    loop {
+        let requests = server.requests();
+        for request in &requests[seen..] {
+            eprintln!("/{} {:?}", request.path, request.query);
+        }
+        seen = requests.len();
+
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
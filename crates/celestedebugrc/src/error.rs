@@ -0,0 +1,139 @@
+use std::time::Duration;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Nothing is listening on the DebugRC address
+    GameNotRunning {
+        address: String,
+    },
+    /// The game is running, but nothing handles the endpoint, e.g. because CelesteTAS is not installed
+    EndpointMissing {
+        path: String,
+    },
+    /// The endpoint answered with something unexpected
+    BadResponse {
+        path: String,
+        message: String,
+    },
+    /// The game did not answer in time. It may be loading or frozen.
+    Timeout {
+        path: String,
+        timeout: Duration,
+    },
+    /// The game did not become ready in [`DebugRC::wait_until_ready`](crate::DebugRC::wait_until_ready)
+    NotReady {
+        waited: Duration,
+    },
+    /// The TAS was sent to the game, but did not start running
+    TasDidNotStart {
+        waited: Duration,
+    },
+    /// The run was cancelled through a [`CancelHandle`](crate::CancelHandle)
+    Cancelled,
+    NoTasFiles,
+    /// The game is sent file paths as text, so they need to be valid UTF-8
+    NonUtf8Path(std::path::PathBuf),
+    InvalidTas(celesteloader::tas::Error),
+    Io(std::io::Error),
+    Http(ureq::Error),
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::InvalidTas(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::Http(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::GameNotRunning { address } => write!(
+                f,
+                "could not connect to DebugRC at {address}. Is Celeste running with debug mode enabled?"
+            ),
+            Error::EndpointMissing { path } => write!(
+                f,
+                "DebugRC endpoint `{path}` does not exist. Is CelesteTAS installed and up to date?"
+            ),
+            Error::BadResponse { path, message } => {
+                write!(f, "unexpected response from `{path}`: {message}")
+            }
+            Error::Timeout { path, timeout } => {
+                write!(f, "`{path}` did not respond within {timeout:?}")
+            }
+            Error::NotReady { waited } => {
+                write!(f, "Celeste did not become ready within {waited:?}")
+            }
+            Error::TasDidNotStart { waited } => {
+                write!(f, "TAS did not start running within {waited:?}")
+            }
+            Error::Cancelled => write!(f, "cancelled"),
+            Error::NoTasFiles => write!(f, "tried to run zero TAS files"),
+            Error::NonUtf8Path(path) => write!(f, "non-UTF8 path: {}", path.display()),
+            Error::InvalidTas(e) => write!(f, "invalid TAS: {e}"),
+            Error::Io(e) => e.fmt(f),
+            Error::Http(e) => e.fmt(f),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Error::Io(value)
+    }
+}
+impl From<celesteloader::tas::Error> for Error {
+    fn from(value: celesteloader::tas::Error) -> Self {
+        Error::InvalidTas(value)
+    }
+}
+
+impl Error {
+    pub(crate) fn from_ureq(
+        error: ureq::Error,
+        address: &str,
+        path: &str,
+        timeout: Duration,
+    ) -> Self {
+        match error {
+            ureq::Error::StatusCode(404) => Error::EndpointMissing {
+                path: path.to_owned(),
+            },
+            ureq::Error::StatusCode(status) => Error::BadResponse {
+                path: path.to_owned(),
+                message: format!("status code {status}"),
+            },
+            ureq::Error::Timeout(_) => Error::Timeout {
+                path: path.to_owned(),
+                timeout,
+            },
+            ureq::Error::ConnectionFailed | ureq::Error::HostNotFound => Error::GameNotRunning {
+                address: address.to_owned(),
+            },
+            ureq::Error::Io(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::ConnectionRefused
+                        | std::io::ErrorKind::ConnectionReset
+                        | std::io::ErrorKind::ConnectionAborted
+                        | std::io::ErrorKind::NotConnected
+                ) =>
+            {
+                Error::GameNotRunning {
+                    address: address.to_owned(),
+                }
+            }
+            ureq::Error::Io(e) if e.kind() == std::io::ErrorKind::TimedOut => Error::Timeout {
+                path: path.to_owned(),
+                timeout,
+            },
+            other => Error::Http(other),
+        }
+    }
+}
@@ -0,0 +1,271 @@
+//! Parsing of the `tas/info` endpoint.
+//!
+//! The response is a small HTML page of `Key: value<br>` lines followed by the info HUD text.
+//! Key names and the HUD layout have changed between CelesteTAS versions, so everything except `Running` is optional
+//! and the raw values are kept in [`TasInfo::fields`].
+
+use crate::{Error, Result};
+use celesteloader::tas::TasTime;
+use std::{iter::Peekable, str::SplitWhitespace};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TasInfo {
+    pub running: bool,
+    /// e.g. `["Enable", "FrameStep"]`
+    pub state: Vec<String>,
+    pub current_frame: Option<u32>,
+    pub total_frames: Option<u32>,
+    pub current_file: Option<String>,
+    /// 1-based line in [`TasInfo::current_file`]
+    pub current_line: Option<u32>,
+    pub room: Option<String>,
+    pub chapter_time: Option<TasTime>,
+    pub player: Option<PlayerInfo>,
+    /// The raw info HUD text
+    pub game_info: String,
+    /// All known `Key: value` pairs of the response, with keys lowercased and stripped of non-alphanumerics
+    pub fields: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PlayerInfo {
+    pub position: Option<(f32, f32)>,
+    pub speed: Option<(f32, f32)>,
+    /// e.g. `StNormal`
+    pub state: Option<String>,
+}
+
+impl TasInfo {
+    pub fn parse(response: &str) -> Result<TasInfo> {
+        let text = strip_html(response);
+
+        let mut info = TasInfo::default();
+        let mut running = None;
+        let mut game_info = Vec::new();
+        let mut in_game_info = false;
+
+        for line in text.lines() {
+            let trimmed = line.trim();
+
+            let key_value = trimmed
+                .split_once(':')
+                .map(|(key, value)| (normalize_key(key), value.trim()))
+                .filter(|(key, _)| is_known_key(key));
+
+            let Some((key, value)) = key_value else {
+                if in_game_info {
+                    game_info.push(line.trim_end());
+                }
+                continue;
+            };
+            in_game_info = false;
+
+            match key.as_str() {
+                "running" | "tasrunning" => running = parse_bool(value),
+                "state" | "tasstate" => {
+                    info.state = value
+                        .split([',', '|'])
+                        .map(str::trim)
+                        .filter(|state| !state.is_empty())
+                        .map(ToOwned::to_owned)
+                        .collect();
+                    running = running.or_else(|| {
+                        info.state
+                            .iter()
+                            .any(|state| state.eq_ignore_ascii_case("enable"))
+                            .then_some(true)
+                    });
+                }
+                "currentframe" | "currentframeinmovie" | "currentframeintas" => {
+                    info.current_frame = value.parse().ok()
+                }
+                "totalframes" | "totalframe" | "framecount" => {
+                    info.total_frames = value.parse().ok()
+                }
+                "currentfile" | "filepath" | "tasfile" => {
+                    info.current_file = Some(value.to_owned()).filter(|file| !file.is_empty())
+                }
+                "currentline" | "line" => info.current_line = parse_line(value),
+                "levelname" | "room" | "level" => {
+                    info.room = Some(value.trim_matches(['[', ']']).to_owned())
+                        .filter(|room| !room.is_empty())
+                }
+                "chaptertime" => info.chapter_time = parse_time(value),
+                "gameinfo" | "exactgameinfo" | "studioinfo" => {
+                    in_game_info = true;
+                    if !value.is_empty() {
+                        game_info.push(value);
+                    }
+                }
+                _ => {}
+            }
+            info.fields.push((key, value.to_owned()));
+        }
+
+        info.running = running.ok_or_else(|| Error::BadResponse {
+            path: "tas/info".into(),
+            message: "could not find `Running` state".into(),
+        })?;
+        info.game_info = game_info.join("\n").trim().to_owned();
+        parse_game_info(&mut info);
+
+        Ok(info)
+    }
+
+    /// Whether the TAS is running, but paused in frame step mode, e.g. because it hit a breakpoint
+    pub fn is_paused(&self) -> bool {
+        self.running
+            && self
+                .state
+                .iter()
+                .any(|state| state.eq_ignore_ascii_case("FrameStep"))
+    }
+}
+
+fn parse_game_info(info: &mut TasInfo) {
+    let mut player = PlayerInfo::default();
+
+    for line in info.game_info.lines() {
+        let line = line.trim();
+
+        if let Some(room) = line.strip_prefix('[').and_then(|line| line.split_once(']')) {
+            info.room.get_or_insert_with(|| room.0.to_owned());
+        }
+
+        let mut words = line.split_whitespace().peekable();
+        while let Some(word) = words.next() {
+            let Some(label) = word.strip_suffix(':') else {
+                if word.starts_with("St") && word[2..].starts_with(char::is_uppercase) {
+                    player.state = Some(word.to_owned());
+                }
+                continue;
+            };
+
+            match label.to_ascii_lowercase().as_str() {
+                "pos" | "position" => player.position = parse_vec2(&take_vec2(&mut words)),
+                "speed" => player.speed = parse_vec2(&take_vec2(&mut words)),
+                "timer" | "chaptertime" => {
+                    if let Some(time) = words.next().and_then(parse_time) {
+                        info.chapter_time.get_or_insert(time);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if player != PlayerInfo::default() {
+        info.player = Some(player);
+    }
+}
+
+/// Joins `12.00, -34.50` back together after splitting on whitespace
+fn take_vec2(words: &mut Peekable<SplitWhitespace>) -> String {
+    let mut value = String::new();
+    while let Some(word) = words.next_if(|word| !word.ends_with(':')) {
+        value.push_str(word);
+        if value.contains(',') && !value.ends_with(',') {
+            break;
+        }
+    }
+    value
+}
+
+fn is_known_key(key: &str) -> bool {
+    matches!(
+        key,
+        "running"
+            | "tasrunning"
+            | "state"
+            | "tasstate"
+            | "currentframe"
+            | "currentframeinmovie"
+            | "currentframeintas"
+            | "totalframes"
+            | "totalframe"
+            | "framecount"
+            | "currentfile"
+            | "filepath"
+            | "tasfile"
+            | "currentline"
+            | "line"
+            | "currentlinesuffix"
+            | "savestateline"
+            | "levelname"
+            | "room"
+            | "level"
+            | "chaptertime"
+            | "gameinfo"
+            | "exactgameinfo"
+            | "studioinfo"
+    )
+}
+
+fn normalize_key(key: &str) -> String {
+    key.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    if value.eq_ignore_ascii_case("true") {
+        Some(true)
+    } else if value.eq_ignore_ascii_case("false") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// `12`, or `12[3]` when the line is part of a `Read`
+fn parse_line(value: &str) -> Option<u32> {
+    let end = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    value[..end].parse().ok()
+}
+
+fn parse_time(value: &str) -> Option<TasTime> {
+    value.trim().parse().ok()
+}
+
+fn parse_vec2(value: &str) -> Option<(f32, f32)> {
+    let (x, y) = value.split_once(',')?;
+    Some((x.trim().parse().ok()?, y.trim().parse().ok()?))
+}
+
+/// Turns `<br>`s and block tags into newlines, drops all other tags and decodes entities
+fn strip_html(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        out.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('>') else {
+            rest = &rest[start..];
+            break;
+        };
+        let tag = rest[start + 1..start + end]
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        if matches!(
+            tag.as_str(),
+            "br" | "p" | "div" | "pre" | "h1" | "h2" | "h3" | "li" | "tr"
+        ) {
+            out.push('\n');
+        }
+        rest = &rest[start + end + 1..];
+    }
+    out.push_str(rest);
+
+    out.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
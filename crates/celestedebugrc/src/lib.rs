@@ -1,12 +1,38 @@
-use anyhow::{ensure, Context, Result};
-use std::{fmt::Write, path::Path, thread::sleep, time::Duration};
+use celesteloader::tas::{Breakpoint, CommandKind, Line, TasFile, TasTime};
+use std::{
+    io::Write,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::sleep,
+    time::{Duration, Instant},
+};
 use ureq::{typestate::WithoutBody, RequestBuilder};
 
-const PORT: u16 = 32270;
+mod error;
+pub mod info;
+#[cfg(feature = "mock-server")]
+pub mod mock;
+pub use error::{Error, Result};
+pub use info::TasInfo;
+
+pub const DEFAULT_HOST: &str = "localhost";
+pub const DEFAULT_PORT: u16 = 32270;
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Overrides the host, e.g. when running Celeste in a VM
+pub const HOST_ENV: &str = "CELESTE_DEBUGRC_HOST";
+pub const PORT_ENV: &str = "CELESTE_DEBUGRC_PORT";
 
 #[derive(Clone)]
 pub struct DebugRC {
     agent: ureq::Agent,
+    address: String,
+    timeout: Duration,
+    tas_start_timeout: Duration,
+    cancel: Arc<AtomicBool>,
 }
 
 impl Default for DebugRC {
@@ -15,133 +41,309 @@ impl Default for DebugRC {
     }
 }
 
-impl DebugRC {
-    pub fn new() -> Self {
+/// Configures the address and timeouts of a [`DebugRC`].
+///
+/// Host and port default to the [`HOST_ENV`] and [`PORT_ENV`] environment variables, falling back to `localhost:32270`.
+#[derive(Clone, Debug)]
+pub struct DebugRCBuilder {
+    host: Option<String>,
+    port: Option<u16>,
+    timeout: Duration,
+    connect_timeout: Duration,
+    tas_start_timeout: Duration,
+}
+
+impl Default for DebugRCBuilder {
+    fn default() -> Self {
+        DebugRCBuilder {
+            host: None,
+            port: None,
+            timeout: DEFAULT_TIMEOUT,
+            connect_timeout: Duration::from_secs(1),
+            tas_start_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+impl DebugRCBuilder {
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.host = Some(host.into());
+        self
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Maximum duration of a single request
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// How long [`DebugRC::play_tas_sync`] waits for the TAS to start running
+    pub fn tas_start_timeout(mut self, timeout: Duration) -> Self {
+        self.tas_start_timeout = timeout;
+        self
+    }
+
+    pub fn build(self) -> DebugRC {
+        let host = self
+            .host
+            .or_else(|| std::env::var(HOST_ENV).ok().filter(|host| !host.is_empty()))
+            .unwrap_or_else(|| DEFAULT_HOST.to_owned());
+        let port = self
+            .port
+            .or_else(|| std::env::var(PORT_ENV).ok()?.parse().ok())
+            .unwrap_or(DEFAULT_PORT);
+
+        let agent = ureq::Agent::config_builder()
+            .timeout_global(Some(self.timeout))
+            .timeout_connect(Some(self.connect_timeout))
+            .build()
+            .into();
+
         DebugRC {
-            agent: ureq::agent(),
+            agent,
+            address: format!("{host}:{port}"),
+            timeout: self.timeout,
+            tas_start_timeout: self.tas_start_timeout,
+            cancel: Arc::default(),
         }
     }
+}
+
+impl DebugRC {
+    pub fn new() -> Self {
+        DebugRC::builder().build()
+    }
+
+    pub fn builder() -> DebugRCBuilder {
+        DebugRCBuilder::default()
+    }
+
+    /// `host:port` of the DebugRC server
+    pub fn address(&self) -> &str {
+        &self.address
+    }
 
     fn get_request(&self, path: &str) -> RequestBuilder<WithoutBody> {
-        let url = format!("http://localhost:{PORT}/{path}");
+        let url = format!("http://{}/{path}", self.address);
         self.agent.get(&url)
     }
 
+    fn call(&self, path: &str, request: RequestBuilder<WithoutBody>) -> Result<String> {
+        let map_err = |e| Error::from_ureq(e, &self.address, path, self.timeout);
+        request
+            .call()
+            .map_err(map_err)?
+            .body_mut()
+            .read_to_string()
+            .map_err(map_err)
+    }
+
     pub fn get(&self, path: &str) -> Result<()> {
-        self.get_request(path).call()?;
+        self.call(path, self.get_request(path))?;
         Ok(())
     }
 
+    /// Returns `Ok(())` if the game is running and responds to requests
+    pub fn health_check(&self) -> Result<()> {
+        self.get("")
+    }
+
+    /// Polls the game with exponential backoff until it responds or `timeout` is reached.
+    ///
+    /// Useful while the game is still starting up or loading a map.
+    pub fn wait_until_ready(&self, timeout: Duration) -> Result<()> {
+        let start = Instant::now();
+        let mut backoff = Duration::from_millis(50);
+
+        loop {
+            match self.health_check() {
+                Ok(()) => return Ok(()),
+                Err(Error::GameNotRunning { .. } | Error::Timeout { .. }) => {}
+                Err(e) => return Err(e),
+            }
+
+            let waited = start.elapsed();
+            if waited >= timeout {
+                return Err(Error::NotReady { waited });
+            }
+            sleep(backoff.min(timeout - waited));
+            backoff = (backoff * 2).min(Duration::from_secs(1));
+        }
+    }
+
     pub fn list_mods(&self) -> Result<Vec<String>> {
-        let result = self
-            .get_request("list")
-            .query("type", "mods")
-            .call()?
-            .body_mut()
-            .read_to_string()?;
+        let result = self.call("list", self.get_request("list").query("type", "mods"))?;
 
         Ok(result.lines().map(ToOwned::to_owned).collect())
     }
 
     pub fn respawn(&self) -> Result<()> {
-        self.get_request("respawn").call()?;
-        Ok(())
+        self.get("respawn")
     }
 
     pub fn play_tas(&self, file: impl AsRef<Path>) -> Result<()> {
-        let file = file.as_ref().to_str().unwrap();
-        self.get_request("tas/playtas")
-            .query("filePath", file)
-            .call()?
-            .body_mut()
-            .read_to_string()?;
+        let file = path_str(file.as_ref())?;
+        self.call(
+            "tas/playtas",
+            self.get_request("tas/playtas").query("filePath", file),
+        )?;
         Ok(())
     }
 
     pub fn console(&self, command: &str) -> Result<()> {
-        self.get_request("console")
-            .query("command", command)
-            .call()?;
+        self.call(
+            "console",
+            self.get_request("console").query("command", command),
+        )?;
         Ok(())
     }
 
+    /// Plays the TAS and blocks until it is finished, paused on a breakpoint, or cancelled via [`DebugRC::cancel_handle`].
     pub fn play_tas_sync(
         &self,
         file: impl AsRef<Path>,
-        mut progress: impl FnMut(&str),
-    ) -> Result<()> {
+        progress: impl FnMut(&TasInfo),
+    ) -> Result<TasRunResult> {
+        self.cancel_handle().reset();
+        self.play_tas_until_done(file.as_ref(), progress)
+    }
+
+    fn play_tas_until_done(
+        &self,
+        file: &Path,
+        mut progress: impl FnMut(&TasInfo),
+    ) -> Result<TasRunResult> {
+        let poll_interval = Duration::from_millis(100);
+
+        let before = self.tas_info()?;
         self.play_tas(file)?;
 
-        let start_timeout = Duration::from_secs_f32(0.1);
-        let run_timeout = Duration::from_secs_f32(0.1);
+        let start = Instant::now();
+        let mut info = loop {
+            let info = self.tas_info()?;
+            if tas_started(&before, &info, file) {
+                break info;
+            }
+            self.check_cancelled()?;
 
-        std::thread::sleep(start_timeout);
-        /*let mut i_start = 0;
-        loop {
-            if self.tas_running(&mut |_| {})? {
-                break;
+            let waited = start.elapsed();
+            if waited >= self.tas_start_timeout {
+                return Err(Error::TasDidNotStart { waited });
             }
+            sleep(Duration::from_millis(20));
+        };
 
-            sleep(start_timeout);
-            i_start += 1;
+        let mut result = TasRunResult::default();
+        loop {
+            progress(&info);
+            result.frames_played = result.frames_played.max(info.current_frame.unwrap_or(0));
+            result.chapter_time = info.chapter_time.or(result.chapter_time);
 
-            if i_start > 10 {
-                bail!("didn't start tas in {:?}", start_timeout * i_start);
+            if !info.running {
+                break;
+            }
+            if info.is_paused() {
+                result.ended_on_breakpoint = true;
+                self.stop_tas()?;
+                break;
+            }
+            if self.cancel.load(Ordering::Relaxed) {
+                self.stop_tas()?;
+                return Err(Error::Cancelled);
             }
-        }*/
-
-        let mut _i_run = 0;
-        while self.tas_running(&mut progress)? {
-            sleep(run_timeout);
 
-            _i_run += 1;
+            sleep(poll_interval);
+            info = self.tas_info()?;
         }
 
-        Ok(())
+        Ok(result)
     }
 
-    fn tas_running(&self, progress: &mut impl FnMut(&str)) -> Result<bool> {
-        let status = self
-            .get_request("tas/info")
-            .call()?
-            .body_mut()
-            .read_to_string()?;
-        progress(&status);
-        if status.contains("Running: False") {
-            return Ok(false);
+    /// Stops the currently running TAS, like pressing the start/stop hotkey
+    pub fn stop_tas(&self) -> Result<()> {
+        let info = self.tas_info()?;
+        if info.running {
+            self.send_tas_keybind("Start")?;
         }
-        if status.contains("Running: True") {
-            return Ok(true);
+        Ok(())
+    }
+
+    /// A handle which can cancel TASes played by this `DebugRC` (and its clones) from another thread.
+    pub fn cancel_handle(&self) -> CancelHandle {
+        CancelHandle(Arc::clone(&self.cancel))
+    }
+
+    fn check_cancelled(&self) -> Result<()> {
+        match self.cancel.load(Ordering::Relaxed) {
+            true => Err(Error::Cancelled),
+            false => Ok(()),
         }
+    }
 
-        Err(anyhow::Error::msg("could not understand tas/info response"))
+    pub fn tas_info_raw(&self) -> Result<String> {
+        self.call("tas/info", self.get_request("tas/info"))
     }
 
-    pub fn tas_info(&self) -> Result<String> {
-        let status = self
-            .get_request("tas/info")
-            .call()?
-            .body_mut()
-            .read_to_string()?;
-        Ok(status)
+    pub fn tas_info(&self) -> Result<TasInfo> {
+        TasInfo::parse(&self.tas_info_raw()?)
     }
 
     pub fn send_tas_keybind(&self, id: &str) -> Result<String> {
-        let status = self
-            .get_request("tas/sendhotkey")
-            .query("action", "press")
-            .query("id", id)
-            .call()?
-            .body_mut()
-            .read_to_string()?;
-        Ok(status)
+        self.call(
+            "tas/sendhotkey",
+            self.get_request("tas/sendhotkey")
+                .query("action", "press")
+                .query("id", id),
+        )
     }
 }
 
+/// Cancels TASes started by [`DebugRC::play_tas_sync`] and [`DebugRC::run_tases_fastforward`].
+///
+/// The flag is cleared whenever one of them starts a new run, so cancelling only affects the current one.
+#[derive(Clone, Debug)]
+pub struct CancelHandle(Arc<AtomicBool>);
+
+impl CancelHandle {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TasRunResult {
+    /// The name of the file, or `None` if all files were run merged
+    pub origin: Option<String>,
+    pub frames_played: u32,
+    /// Chapter time when the TAS ended
+    pub chapter_time: Option<TasTime>,
+    /// Whether the TAS stopped on a breakpoint instead of running to the end
+    pub ended_on_breakpoint: bool,
+}
+
 pub struct PlayTasProgress<'a> {
     pub origin: Option<&'a str>,
-    pub current_frame: &'a str,
-    pub total_frames: &'a str,
+    pub current_frame: u32,
+    pub total_frames: u32,
+    pub info: &'a TasInfo,
     pub current_file: usize,
     pub total_files: usize,
 }
@@ -152,13 +354,24 @@ impl DebugRC {
         speedup: f32,
         mut run_as_merged_file: bool,
         mut progress: impl FnMut(PlayTasProgress),
-    ) -> Result<()> {
-        ensure!(!tas_files.is_empty(), "Tried to run zero TAS files");
+    ) -> Result<Vec<TasRunResult>> {
+        if tas_files.is_empty() {
+            return Err(Error::NoTasFiles);
+        }
 
         if run_as_merged_file {
-            let enforce_legal = tas_files.iter().fold(false, |acc, (file, _, _)| {
+            let enforce_legal = tas_files.iter().any(|(file, _, _)| {
                 let content = std::fs::read_to_string(file).unwrap_or_default();
-                acc || content.contains("EnforceLegal") || content.contains("EnforceMaingame")
+                TasFile::parse_lenient(&content)
+                    .0
+                    .commands()
+                    .any(|command| match command {
+                        CommandKind::EnforceLegal => true,
+                        CommandKind::Other { name, .. } => {
+                            name.eq_ignore_ascii_case("EnforceMaingame")
+                        }
+                        _ => false,
+                    })
             });
 
             if enforce_legal {
@@ -166,62 +379,99 @@ impl DebugRC {
                 run_as_merged_file = false;
             }
         }
+
+        let read_decorated = |tas: &mut TasFile,
+                              path: &Path,
+                              (decorate_begin, decorate_end): &(String, String)|
+         -> Result<()> {
+            tas.lines
+                .extend(TasFile::parse_lenient(decorate_begin).0.lines);
+            tas.push(CommandKind::Read {
+                file: path_str(path)?.to_owned(),
+                start: None,
+                end: None,
+            });
+            tas.lines
+                .extend(TasFile::parse_lenient(decorate_end).0.lines);
+            Ok(())
+        };
+        let breakpoint = Breakpoint {
+            speed: Some(speedup),
+            ..Default::default()
+        };
+
         let tmp_files = if run_as_merged_file {
-            let mut temp_content = tas_files.iter().fold(
-                String::new(),
-                |mut acc, (path, _, (decorate_begin, decorate_end))| {
-                    let _ = writeln!(
-                        &mut acc,
-                        "{decorate_begin}\nRead,{}\n{decorate_end}\n",
-                        path.as_ref().to_str().unwrap()
-                    );
-                    acc
-                },
-            );
-            temp_content.push_str("\n***");
-            temp_content.push_str(&speedup.to_string());
-            vec![(temp_content, None)]
+            let mut tas = TasFile::default();
+            for (path, _, decorate) in tas_files {
+                read_decorated(&mut tas, path.as_ref(), decorate)?;
+                tas.push(Line::Empty);
+            }
+            tas.push(Line::Empty).push(breakpoint);
+            vec![(tas.to_string(), None)]
         } else {
             tas_files
                 .iter()
-                .map(|(file, name, (decorate_begin, decorate_end))| {
-                    let file = file.as_ref().to_str().unwrap();
-                    (
-                        format!("{decorate_begin}\nRead,{file}\n{decorate_end}\n***{speedup}"),
-                        Some(name.as_str()),
-                    )
+                .map(|(path, name, decorate)| {
+                    let mut tas = TasFile::default();
+                    read_decorated(&mut tas, path.as_ref(), decorate)?;
+                    tas.push(breakpoint.clone());
+                    Ok((tas.to_string(), Some(name.as_str())))
                 })
-                .collect()
+                .collect::<Result<Vec<_>>>()?
         };
 
+        self.cancel_handle().reset();
+
         let total_files = tmp_files.len();
+        let mut results = Vec::with_capacity(total_files);
         for (i, (content, origin)) in tmp_files.into_iter().enumerate() {
-            let path = std::env::temp_dir().join("tmp.tas");
+            self.check_cancelled()?;
 
-            std::fs::write(&path, content)?;
-            self.play_tas_sync(&path, |info| {
-                let current_frame = find_info(info, "CurrentFrame: ");
-                let total_frames = find_info(info, "TotalFrames: ");
+            let mut file = tempfile::Builder::new()
+                .prefix("celestedebugrc-")
+                .suffix(".tas")
+                .tempfile()?;
+            file.write_all(content.as_bytes())?;
+            file.flush()?;
+
+            let result = self.play_tas_until_done(file.path(), |info| {
                 progress(PlayTasProgress {
                     origin,
-                    current_frame,
-                    total_frames,
+                    current_frame: info.current_frame.unwrap_or(0),
+                    total_frames: info.total_frames.unwrap_or(0),
+                    info,
                     total_files,
                     current_file: i,
                 });
-            })
-            .context("Could not play TAS. Is Celeste running?")?;
-            std::fs::remove_file(&path)?;
+            })?;
+            results.push(TasRunResult {
+                origin: origin.map(ToOwned::to_owned),
+                ..result
+            });
         }
 
-        Ok(())
+        Ok(results)
     }
 }
 
-fn find_info<'a>(str: &'a str, prop: &str) -> &'a str {
-    let Some(i) = str.find(prop) else { return "" };
-    let str = &str[i + prop.len()..];
+/// Whether `info`, polled after playing `file`, shows that the TAS started.
+///
+/// Short fast-forwarded TASes can finish before the first poll, so progress or a finished run of `file`
+/// count as well as the TAS currently running.
+fn tas_started(before: &TasInfo, info: &TasInfo, file: &Path) -> bool {
+    let frames = |info: &TasInfo| (info.current_frame, info.total_frames);
+    let progressed =
+        info.current_frame.is_some_and(|frame| frame > 0) && frames(info) != frames(before);
+    let finished_file = !info.running
+        && info.current_frame.is_some()
+        && info.current_frame == info.total_frames
+        && info.current_file != before.current_file
+        && info.current_file.as_deref().map(Path::new) == Some(file);
+
+    info.running || progressed || finished_file
+}
 
-    let idx_newline = str.find("<br").unwrap_or(str.len());
-    &str[..idx_newline]
+fn path_str(path: &Path) -> Result<&str> {
+    path.to_str()
+        .ok_or_else(|| Error::NonUtf8Path(path.to_owned()))
 }
@@ -0,0 +1,396 @@
+//! A fake DebugRC server for testing TAS orchestration without running the game.
+//!
+//! Playback is simulated from the static frame count of the TAS file:
+//! every `tas/info` request advances the TAS by [`MockServer::set_frames_per_poll`] frames until it is done,
+//! or pauses at the first breakpoint followed by more inputs.
+//!
+//! ```no_run
+//! # fn main() -> std::io::Result<()> {
+//! let server = celestedebugrc::mock::MockServer::start()?;
+//! let debugrc = server.client();
+//! let result = debugrc.play_tas_sync("test.tas", |_| {}).unwrap();
+//! assert!(!result.ended_on_breakpoint);
+//! assert_eq!(server.requests_to("tas/playtas").len(), 1);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use celesteloader::tas::{resolve::Resolver, Line, TasFile, TasTime};
+
+use crate::DebugRC;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockRequest {
+    /// Path without the leading slash, e.g. `tas/playtas`
+    pub path: String,
+    pub query: Vec<(String, String)>,
+}
+
+impl MockRequest {
+    pub fn query(&self, key: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Playback {
+    file: Option<PathBuf>,
+    running: bool,
+    /// `tas/info` requests left until the TAS starts running
+    start_delay: u32,
+    current_frame: u32,
+    total_frames: u32,
+    /// Frame of the first breakpoint which is followed by more inputs
+    breakpoint: Option<u32>,
+}
+
+impl Playback {
+    fn paused(&self) -> bool {
+        self.running && self.breakpoint == Some(self.current_frame)
+    }
+}
+
+#[derive(Debug)]
+struct MockState {
+    requests: Vec<MockRequest>,
+    mods: Vec<String>,
+    frames_per_poll: u32,
+    start_delay: u32,
+    plays_instantly: bool,
+    response_delay: Duration,
+    playback: Playback,
+}
+
+/// A DebugRC server listening on a random local port, running until it is dropped.
+pub struct MockServer {
+    address: SocketAddr,
+    state: Arc<Mutex<MockState>>,
+    shutdown: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MockServer {
+    pub fn start() -> std::io::Result<MockServer> {
+        MockServer::bind("127.0.0.1:0")
+    }
+
+    pub fn bind(address: &str) -> std::io::Result<MockServer> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        let address = listener.local_addr()?;
+
+        let state = Arc::new(Mutex::new(MockState {
+            requests: Vec::new(),
+            mods: vec!["Everest".into(), "CelesteTAS".into()],
+            frames_per_poll: 100,
+            start_delay: 0,
+            plays_instantly: false,
+            response_delay: Duration::ZERO,
+            playback: Playback::default(),
+        }));
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let thread = std::thread::spawn({
+            let state = Arc::clone(&state);
+            let shutdown = Arc::clone(&shutdown);
+            move || {
+                while !shutdown.load(Ordering::Relaxed) {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            if let Err(e) = handle_connection(stream, &state) {
+                                eprintln!("mock debugrc: {e}");
+                            }
+                        }
+                        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                            std::thread::sleep(Duration::from_millis(2));
+                        }
+                        Err(e) => eprintln!("mock debugrc: {e}"),
+                    }
+                }
+            }
+        });
+
+        Ok(MockServer {
+            address,
+            state,
+            shutdown,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// A [`DebugRC`] pointed at this server
+    pub fn client(&self) -> DebugRC {
+        DebugRC::builder()
+            .host(self.address.ip().to_string())
+            .port(self.address.port())
+            .build()
+    }
+
+    fn state(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// All requests received so far, in order
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.state().requests.clone()
+    }
+
+    pub fn requests_to(&self, path: &str) -> Vec<MockRequest> {
+        self.state()
+            .requests
+            .iter()
+            .filter(|request| request.path == path)
+            .cloned()
+            .collect()
+    }
+
+    pub fn clear_requests(&self) {
+        self.state().requests.clear();
+    }
+
+    /// Mods returned by `list?type=mods`
+    pub fn set_mods(&self, mods: impl IntoIterator<Item = impl Into<String>>) {
+        self.state().mods = mods.into_iter().map(Into::into).collect();
+    }
+
+    /// How many frames the TAS advances per `tas/info` request
+    pub fn set_frames_per_poll(&self, frames: u32) {
+        self.state().frames_per_poll = frames.max(1);
+    }
+
+    /// How many `tas/info` requests report the TAS as not running after it was started.
+    /// `u32::MAX` means the TAS never starts.
+    pub fn set_start_delay(&self, polls: u32) {
+        self.state().start_delay = polls;
+    }
+
+    /// Makes TASes run up to their end or first breakpoint as soon as they are played,
+    /// like short fast-forwarded TASes finishing before the first `tas/info` request
+    pub fn set_plays_instantly(&self, instantly: bool) {
+        self.state().plays_instantly = instantly;
+    }
+
+    /// How long every response is held back, e.g. to simulate a frozen game
+    pub fn set_response_delay(&self, delay: Duration) {
+        self.state().response_delay = delay;
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn handle_connection(stream: TcpStream, state: &Mutex<MockState>) -> std::io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    let target = request_line.split_whitespace().nth(1).unwrap_or("/");
+    let request = parse_target(target);
+
+    let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+    state.requests.push(request.clone());
+    let (status, body) = respond(&mut state, &request);
+    let response_delay = state.response_delay;
+    drop(state);
+    std::thread::sleep(response_delay);
+
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
+
+fn respond(state: &mut MockState, request: &MockRequest) -> (&'static str, String) {
+    const OK: &str = "200 OK";
+
+    match request.path.as_str() {
+        "" => (
+            OK,
+            "<html><body>Everest DebugRC (mock)</body></html>".into(),
+        ),
+        "list" => (OK, state.mods.join("\n")),
+        "console" | "respawn" => (OK, "OK".into()),
+        "tas/playtas" => {
+            let Some(file) = request.query("filePath") else {
+                return ("400 Bad Request", "missing filePath".into());
+            };
+            let file = Path::new(file);
+            match Resolver::new().expand(file) {
+                Ok(tas) => {
+                    state.playback = Playback {
+                        file: Some(file.to_owned()),
+                        running: state.start_delay == 0,
+                        start_delay: state.start_delay,
+                        current_frame: 0,
+                        total_frames: tas.input_frames(),
+                        breakpoint: breakpoint_frame(&tas),
+                    };
+                    if state.plays_instantly {
+                        let playback = &mut state.playback;
+                        playback.current_frame =
+                            playback.breakpoint.unwrap_or(playback.total_frames);
+                        playback.running = playback.breakpoint.is_some();
+                    }
+                    (OK, "OK".into())
+                }
+                Err(e) => ("500 Internal Server Error", e.to_string()),
+            }
+        }
+        "tas/info" => {
+            let playback = &mut state.playback;
+            if playback.start_delay > 0 && playback.file.is_some() {
+                if playback.start_delay != u32::MAX {
+                    playback.start_delay -= 1;
+                }
+                playback.running = playback.start_delay == 0;
+                return (OK, tas_info(&Playback::default()));
+            }
+
+            let info = tas_info(playback);
+            if playback.running && !playback.paused() {
+                let target = playback.breakpoint.unwrap_or(playback.total_frames);
+                playback.current_frame =
+                    (playback.current_frame + state.frames_per_poll).min(target);
+                if playback.current_frame >= playback.total_frames {
+                    playback.running = false;
+                }
+            }
+            (OK, info)
+        }
+        "tas/sendhotkey" => {
+            if request.query("id") == Some("Start") {
+                state.playback.running = false;
+            }
+            (OK, "OK".into())
+        }
+        _ => ("404 Not Found", "not found".into()),
+    }
+}
+
+fn tas_info(playback: &Playback) -> String {
+    let time = TasTime::from_frames(playback.current_frame);
+    let file = playback
+        .file
+        .as_deref()
+        .map(|file| file.display().to_string())
+        .unwrap_or_default();
+    let running = if playback.running { "True" } else { "False" };
+    let state = match (playback.running, playback.paused()) {
+        (true, true) => "Enable, FrameStep",
+        (true, false) => "Enable",
+        (false, _) => "None",
+    };
+
+    format!(
+        "<html><body><h2>CelesteTAS Info</h2>\
+         Running: {running}<br/>\
+         State: {state}<br/>\
+         CurrentFrame: {}<br/>\
+         TotalFrames: {}<br/>\
+         CurrentFile: {file}<br/>\
+         ChapterTime: {time}<br/>\
+         GameInfo: <br/><pre>Pos:   0.00, 0.00\nSpeed:   0.00, 0.00\nTimer: {time}\nStNormal\n[mock]</pre>\
+         </body></html>",
+        playback.current_frame, playback.total_frames,
+    )
+}
+
+fn breakpoint_frame(tas: &TasFile) -> Option<u32> {
+    let index = tas
+        .lines
+        .iter()
+        .position(|line| matches!(line, Line::Breakpoint(_)))?;
+    let inputs_after = tas.lines[index..]
+        .iter()
+        .any(|line| matches!(line, Line::Input(_)));
+
+    inputs_after.then(|| {
+        TasFile {
+            lines: tas.lines[..index].to_vec(),
+        }
+        .input_frames()
+    })
+}
+
+fn parse_target(target: &str) -> MockRequest {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect();
+
+    MockRequest {
+        path: percent_decode(path.trim_start_matches('/')),
+        query,
+    }
+}
+
+fn percent_decode(s: &str) -> String {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(b) = iter.next() {
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = [iter.next(), iter.next()];
+                let decoded = match hex {
+                    [Some(hi), Some(lo)] => std::str::from_utf8(&[hi, lo])
+                        .ok()
+                        .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+                    _ => None,
+                };
+                bytes.extend(decoded.map_or_else(
+                    || {
+                        std::iter::once(b'%')
+                            .chain(hex.into_iter().flatten())
+                            .collect()
+                    },
+                    |b| vec![b],
+                ));
+            }
+            b => bytes.push(b),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
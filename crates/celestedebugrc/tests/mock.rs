@@ -0,0 +1,342 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use celestedebugrc::{mock::MockServer, DebugRC, Error};
+
+fn write_tas(dir: &Path, name: &str, content: &str) -> PathBuf {
+    let path = dir.join(name);
+    std::fs::write(&path, content).unwrap();
+    path
+}
+
+#[test]
+fn console_command_is_encoded() {
+    let server = MockServer::start().unwrap();
+    let debugrc = server.client();
+
+    debugrc
+        .console("load Celeste/1-ForsakenCity & more")
+        .unwrap();
+    let console = server.requests_to("console");
+    assert_eq!(console.len(), 1);
+    assert_eq!(
+        console[0].query("command"),
+        Some("load Celeste/1-ForsakenCity & more")
+    );
+}
+
+#[test]
+fn play_tas_sync_runs_to_completion() {
+    let dir = tempfile::tempdir().unwrap();
+    let tas = write_tas(
+        dir.path(),
+        "test.tas",
+        "console load Celeste/1-ForsakenCity\n   1\n\nRepeat 3\n 100,R\nEndRepeat\n  49,R,J\n",
+    );
+
+    let server = MockServer::start().unwrap();
+    server.set_frames_per_poll(60);
+    let debugrc = server.client();
+
+    let mut frames = Vec::new();
+    debugrc
+        .play_tas_sync(&tas, |info| {
+            frames.push((info.current_frame.unwrap(), info.total_frames.unwrap()))
+        })
+        .unwrap();
+
+    assert!(frames.windows(2).all(|w| w[0].0 <= w[1].0));
+    assert_eq!(frames.last(), Some(&(350, 350)));
+
+    let playtas = server.requests_to("tas/playtas");
+    assert_eq!(playtas.len(), 1);
+    assert_eq!(playtas[0].query("filePath"), tas.to_str());
+}
+
+#[test]
+fn run_tases_fastforward_one_by_one() {
+    let dir = tempfile::tempdir().unwrap();
+    let files = [("a.tas", 30), ("b.tas", 250)].map(|(name, frames)| {
+        let path = write_tas(dir.path(), name, &format!("{frames:>4}\n"));
+        (path, name.to_owned(), (String::new(), String::new()))
+    });
+
+    let server = MockServer::start().unwrap();
+    let debugrc = server.client();
+
+    let mut last_progress = Vec::new();
+    debugrc
+        .run_tases_fastforward(&files, 500.0, false, |progress| {
+            let origin = progress.origin.unwrap().to_owned();
+            match last_progress.last_mut() {
+                Some((last_origin, frames, _)) if *last_origin == origin => {
+                    *frames = progress.current_frame
+                }
+                _ => last_progress.push((origin, progress.current_frame, progress.total_files)),
+            }
+        })
+        .unwrap();
+
+    assert_eq!(
+        last_progress,
+        [("a.tas".to_owned(), 30, 2), ("b.tas".to_owned(), 250, 2)]
+    );
+    assert_eq!(server.requests_to("tas/playtas").len(), 2);
+}
+
+#[test]
+fn run_tases_fastforward_merged() {
+    let dir = tempfile::tempdir().unwrap();
+    let files = ["a.tas", "b.tas", "c.tas"].map(|name| {
+        let path = write_tas(dir.path(), name, "  10\n");
+        (
+            path,
+            name.to_owned(),
+            ("   1".to_owned(), "   2".to_owned()),
+        )
+    });
+
+    let server = MockServer::start().unwrap();
+    let debugrc = server.client();
+
+    let mut total_frames = 0;
+    debugrc
+        .run_tases_fastforward(&files, 500.0, true, |progress| {
+            assert_eq!(progress.origin, None);
+            total_frames = progress.total_frames;
+        })
+        .unwrap();
+
+    assert_eq!(server.requests_to("tas/playtas").len(), 1);
+    assert_eq!(total_frames, 3 * (1 + 10 + 2));
+}
+
+#[test]
+fn run_zero_tases() {
+    let server = MockServer::start().unwrap();
+    let files: [(PathBuf, String, (String, String)); 0] = [];
+    let result = server
+        .client()
+        .run_tases_fastforward(&files, 500.0, false, |_| {});
+    assert!(matches!(result, Err(Error::NoTasFiles)));
+}
+
+#[test]
+fn missing_endpoint() {
+    let server = MockServer::start().unwrap();
+    let result = server.client().get("does/not/exist");
+    assert!(matches!(result, Err(Error::EndpointMissing { .. })));
+}
+
+#[test]
+fn request_timeout() {
+    let server = MockServer::start().unwrap();
+    server.set_response_delay(Duration::from_millis(500));
+    let address = server.address();
+    let debugrc = DebugRC::builder()
+        .host(address.ip().to_string())
+        .port(address.port())
+        .timeout(Duration::from_millis(100))
+        .build();
+
+    assert!(matches!(
+        debugrc.tas_info(),
+        Err(Error::Timeout { path, .. }) if path == "tas/info"
+    ));
+}
+
+#[test]
+fn wait_until_ready_gives_up() {
+    let server = MockServer::start().unwrap();
+    let address = server.address();
+    drop(server);
+
+    let debugrc = DebugRC::builder()
+        .host(address.ip().to_string())
+        .port(address.port())
+        .build();
+    let result = debugrc.wait_until_ready(Duration::from_millis(200));
+    assert!(matches!(result, Err(Error::NotReady { .. })));
+}
+
+#[test]
+fn merged_run_falls_back_for_enforce_legal() {
+    let dir = tempfile::tempdir().unwrap();
+    let files = [("a.tas", "  10\n"), ("b.tas", "EnforceLegal\n  10\n")].map(|(name, content)| {
+        let path = write_tas(dir.path(), name, content);
+        (path, name.to_owned(), (String::new(), String::new()))
+    });
+
+    let server = MockServer::start().unwrap();
+    let results = server
+        .client()
+        .run_tases_fastforward(&files, 500.0, true, |_| {})
+        .unwrap();
+
+    assert_eq!(server.requests_to("tas/playtas").len(), 2);
+    assert_eq!(results.len(), 2);
+}
+
+#[cfg(unix)]
+#[test]
+fn non_utf8_path() {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    let server = MockServer::start().unwrap();
+    let path = Path::new(OsStr::from_bytes(b"invalid-\xff.tas"));
+    let result = server.client().play_tas(path);
+    assert!(matches!(result, Err(Error::NonUtf8Path(_))));
+    assert!(server.requests_to("tas/playtas").is_empty());
+}
+
+#[test]
+fn game_not_running() {
+    let server = MockServer::start().unwrap();
+    let address = server.address();
+    drop(server);
+
+    let debugrc = DebugRC::builder()
+        .host(address.ip().to_string())
+        .port(address.port())
+        .build();
+    assert!(matches!(
+        debugrc.tas_info(),
+        Err(Error::GameNotRunning { .. })
+    ));
+}
+
+#[test]
+fn run_results_and_unique_temp_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let files = [("a.tas", 30), ("b.tas", 250)].map(|(name, frames)| {
+        let path = write_tas(
+            dir.path(),
+            name,
+            &format!("{frames:>4}\nChapterTime: 0:00.510(30)\n"),
+        );
+        (path, name.to_owned(), (String::new(), String::new()))
+    });
+
+    let server = MockServer::start().unwrap();
+    let results = server
+        .client()
+        .run_tases_fastforward(&files, 500.0, false, |_| {})
+        .unwrap();
+
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].origin.as_deref(), Some("a.tas"));
+    assert_eq!(results[0].frames_played, 30);
+    assert_eq!(results[1].frames_played, 250);
+    assert!(results.iter().all(|result| !result.ended_on_breakpoint));
+    assert_eq!(results[1].chapter_time.unwrap().frames, 250);
+
+    let played = server
+        .requests_to("tas/playtas")
+        .into_iter()
+        .map(|request| PathBuf::from(request.query("filePath").unwrap()))
+        .collect::<Vec<_>>();
+    assert_ne!(played[0], played[1]);
+    assert!(played.iter().all(|path| !path.exists()));
+}
+
+#[test]
+fn waits_for_tas_to_start() {
+    let dir = tempfile::tempdir().unwrap();
+    let tas = write_tas(dir.path(), "test.tas", "  20\n");
+
+    let server = MockServer::start().unwrap();
+    server.set_start_delay(3);
+    let result = server.client().play_tas_sync(&tas, |_| {}).unwrap();
+    assert_eq!(result.frames_played, 20);
+}
+
+#[test]
+fn tas_did_not_start() {
+    let dir = tempfile::tempdir().unwrap();
+    let tas = write_tas(dir.path(), "test.tas", "  20\n");
+
+    let server = MockServer::start().unwrap();
+    server.set_start_delay(u32::MAX);
+    let address = server.address();
+    let debugrc = DebugRC::builder()
+        .host(address.ip().to_string())
+        .port(address.port())
+        .tas_start_timeout(Duration::from_millis(200))
+        .build();
+
+    let result = debugrc.play_tas_sync(&tas, |_| {});
+    assert!(matches!(result, Err(Error::TasDidNotStart { .. })));
+}
+
+#[test]
+fn stops_on_breakpoint() {
+    let dir = tempfile::tempdir().unwrap();
+    let tas = write_tas(dir.path(), "test.tas", "  40\n***\n 500\n");
+
+    let server = MockServer::start().unwrap();
+    let result = server.client().play_tas_sync(&tas, |_| {}).unwrap();
+
+    assert!(result.ended_on_breakpoint);
+    assert_eq!(result.frames_played, 40);
+    let hotkeys = server.requests_to("tas/sendhotkey");
+    assert_eq!(hotkeys.len(), 1);
+    assert_eq!(hotkeys[0].query("id"), Some("Start"));
+}
+
+#[test]
+fn cancel() {
+    let dir = tempfile::tempdir().unwrap();
+    let tas = write_tas(dir.path(), "test.tas", "10000\n");
+
+    let server = MockServer::start().unwrap();
+    server.set_frames_per_poll(1);
+    let debugrc = server.client();
+    let cancel = debugrc.cancel_handle();
+
+    let mut polls = 0;
+    let result = debugrc.play_tas_sync(&tas, |_| {
+        polls += 1;
+        if polls == 3 {
+            cancel.cancel();
+        }
+    });
+    assert!(matches!(result, Err(Error::Cancelled)));
+    assert_eq!(server.requests_to("tas/sendhotkey").len(), 1);
+    assert!(cancel.is_cancelled());
+
+    // the next run clears the flag
+    server.set_frames_per_poll(10000);
+    debugrc.play_tas_sync(&tas, |_| {}).unwrap();
+    assert!(!cancel.is_cancelled());
+}
+
+#[test]
+fn tas_finishing_before_first_poll() {
+    let dir = tempfile::tempdir().unwrap();
+    let files = [("a.tas", 20), ("b.tas", 20)].map(|(name, frames)| {
+        let path = write_tas(dir.path(), name, &format!("{frames:>4}\n"));
+        (path, name.to_owned(), (String::new(), String::new()))
+    });
+
+    let server = MockServer::start().unwrap();
+    server.set_plays_instantly(true);
+    let results = server
+        .client()
+        .run_tases_fastforward(&files, 500.0, false, |_| {})
+        .unwrap();
+
+    assert_eq!(
+        results
+            .iter()
+            .map(|result| result.frames_played)
+            .collect::<Vec<_>>(),
+        [20, 20]
+    );
+
+    let tas = write_tas(dir.path(), "breakpoint.tas", "  30\n***\n 100\n");
+    let result = server.client().play_tas_sync(&tas, |_| {}).unwrap();
+    assert!(result.ended_on_breakpoint);
+    assert_eq!(result.frames_played, 30);
+}
@@ -57,6 +57,12 @@ impl From<crate::map::Error> for Error {
     }
 }
 
+/// SID of the map file at `path`, e.g. `Maps/Collab/1-Beginner/map.bin` -> `Collab/1-Beginner/map`
+pub fn map_sid(path: &str) -> &str {
+    let path = path.strip_prefix("Maps/").unwrap_or(path);
+    path.strip_suffix(".bin").unwrap_or(path)
+}
+
 pub struct ModArchive<R = BufReader<File>> {
     archive: ZipArchive<R>,
 }
@@ -182,6 +188,14 @@ impl<R: std::io::Read + std::io::Seek> ModArchive<R> {
         self.archive.by_name("CollabUtils2CollabID.txt").is_ok()
     }
 
+    /// Contents of `CollabUtils2CollabID.txt`, the level set of the collab
+    pub fn collab_id(&mut self) -> Result<Option<String>> {
+        let Some(data) = self.try_read_file("CollabUtils2CollabID.txt")? else {
+            return Ok(None);
+        };
+        Ok(Some(String::from_utf8_lossy(&data).trim().to_owned()))
+    }
+
     pub fn map_fgtiles_bgtiles(&mut self, map: &Map) -> Result<(Option<String>, Option<String>)> {
         let fgtiles = map
             .meta
@@ -33,7 +33,7 @@ impl Element<'_> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Value<'a> {
     Bool(bool),
     U8(u8),
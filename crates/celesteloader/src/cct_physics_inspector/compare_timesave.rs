@@ -1,7 +1,7 @@
 use std::{fmt::Write, ops::Range};
 
 use super::PhysicsInspector;
-use crate::map::Map;
+use crate::{map::Map, tas::TasTime};
 use anyhow::Result;
 
 pub fn compare_timesave(
@@ -37,8 +37,8 @@ fn render_sync_segment_improvement(
         if time_diff > 0 { "+" } else { "" },
         time_diff,
         map_name,
-        frames_to_finaltime(slow_time),
-        frames_to_finaltime(fast_time)
+        TasTime::from_frames(slow_time),
+        TasTime::from_frames(fast_time)
     );
 
     for new_segment in new_segments {
@@ -144,11 +144,3 @@ impl SyncSegment<'_> {
         }
     }
 }
-
-fn frames_to_finaltime(frames: u32) -> String {
-    let ms = frames * 17;
-    let s = ms / 1000;
-    let min = s / 60;
-
-    format!("{}:{:0>2}.{:0>3}({frames})", min, s % 60, ms % 1000)
-}
@@ -0,0 +1,425 @@
+//! Structure of a CollabUtils2 collab.
+//!
+//! A collab with the ID `Collab` (from `CollabUtils2CollabID.txt`) is laid out as
+//! - `Maps/Collab/0-Lobbies/<n>-<Lobby>.bin`: the lobbies, plus the prologue and epilogue
+//! - `Maps/Collab/<n>-<Lobby>/*.bin`: the maps entered from that lobby, `ZZ-HeartSide.bin` being its heart side
+//! - `Maps/Collab/0-Gyms/*.bin`: gyms teaching the techniques used in the maps
+
+use crate::{
+    archive::{map_sid, ModArchive, Result},
+    dialog::Dialog,
+    lobby::{self, Lobby},
+    map::{self, decode::Element},
+};
+
+const HEART_SIDE: &str = "ZZ-HeartSide";
+
+#[derive(Debug, Clone)]
+pub struct Collab {
+    /// Level set of the collab, e.g. `StrawberryJam2021`
+    pub id: String,
+    pub lobbies: Vec<CollabLobby>,
+    /// SIDs of the gym maps
+    pub gyms: Vec<String>,
+    pub prologue: Option<String>,
+    pub epilogue: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CollabLobby {
+    /// Folder of the lobby's maps, e.g. `1-Beginner`
+    pub folder: String,
+    /// Entrances, benches and heart door of the lobby map
+    pub lobby: Lobby,
+    /// Maps of the lobby folder, in the order of their entrances, followed by maps without one
+    pub maps: Vec<CollabMap>,
+    pub heart_side: Option<CollabMap>,
+}
+
+impl CollabLobby {
+    /// Mini hearts needed to open the door to the heart side
+    pub fn required_hearts(&self) -> Option<i32> {
+        self.lobby.heart_door.as_ref()?.requires
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CollabMap {
+    pub sid: String,
+    /// Name of the map from the dialog file
+    pub name: Option<String>,
+    pub data: CollabMapData,
+}
+
+impl CollabMap {
+    /// Dialog name if known, otherwise the SID
+    pub fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.sid)
+    }
+}
+
+/// What CollabUtils2's `CollabMapDataProcessor` collects about a map
+#[derive(Debug, Clone, Default)]
+pub struct CollabMapData {
+    /// Chapter panel icon from the map metadata, relative to `Graphics/Atlases/Gui`
+    pub icon: Option<String>,
+    /// Whether the icon is part of the mod, as opposed to a vanilla or missing one
+    pub icon_in_mod: bool,
+    /// Credits shown on the chapter panel, from the `<sid>_collabcredits` dialog key
+    pub credits: Option<String>,
+    /// Tags shown next to the credits, e.g. the difficulty, from `<sid>_collabcreditstags`
+    pub credit_tags: Option<String>,
+    pub silver_berry: bool,
+    pub speed_berry: Option<SpeedBerryTimes>,
+}
+
+/// Medal times of a speed berry, in seconds. Times the map doesn't set are `None`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpeedBerryTimes {
+    pub gold: Option<f32>,
+    pub silver: Option<f32>,
+    pub bronze: Option<f32>,
+}
+
+/// Reads the structure of the collab, or `None` if the mod isn't a collab
+pub fn read_collab<R: std::io::Read + std::io::Seek>(
+    archive: &mut ModArchive<R>,
+    dialog: Option<&Dialog>,
+) -> Result<Option<Collab>> {
+    let Some(id) = archive.collab_id()? else {
+        return Ok(None);
+    };
+    let maps_dir = format!("Maps/{id}/");
+
+    let mut map_files = archive
+        .list_files()
+        .filter(|path| path.starts_with(&maps_dir) && path.ends_with(".bin"))
+        .map(String::from)
+        .collect::<Vec<_>>();
+    map_files.sort();
+
+    let mut collab = Collab {
+        id,
+        lobbies: Vec::new(),
+        gyms: Vec::new(),
+        prologue: None,
+        epilogue: None,
+    };
+
+    for path in &map_files {
+        let relative = &path[maps_dir.len()..];
+        let Some((folder, name)) = relative.split_once('/') else {
+            continue;
+        };
+        let name = name.strip_suffix(".bin").unwrap_or(name);
+
+        match folder {
+            "0-Gyms" => collab.gyms.push(map_sid(path).to_owned()),
+            "0-Lobbies" if name.to_ascii_lowercase().contains("prologue") => {
+                collab.prologue = Some(map_sid(path).to_owned());
+            }
+            "0-Lobbies" if name.to_ascii_lowercase().contains("epilogue") => {
+                collab.epilogue = Some(map_sid(path).to_owned());
+            }
+            "0-Lobbies" => {
+                let lobby = lobby::read_lobby(archive, path, dialog)?;
+                collab.lobbies.push(CollabLobby {
+                    folder: name.to_owned(),
+                    lobby,
+                    maps: Vec::new(),
+                    heart_side: None,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    for lobby in &mut collab.lobbies {
+        let folder = format!("{maps_dir}{}/", lobby.folder);
+        let mut maps = Vec::new();
+        for path in map_files.iter().filter(|path| path.starts_with(&folder)) {
+            let map = read_collab_map(archive, path, dialog)?;
+            if map.sid.ends_with(&format!("/{HEART_SIDE}")) {
+                lobby.heart_side = Some(map);
+            } else {
+                maps.push(map);
+            }
+        }
+
+        let entrance_index = |map: &CollabMap| {
+            lobby
+                .lobby
+                .map_sids()
+                .position(|sid| sid == map.sid)
+                .unwrap_or(usize::MAX)
+        };
+        maps.sort_by_key(entrance_index);
+        lobby.maps = maps;
+    }
+
+    Ok(Some(collab))
+}
+
+fn read_collab_map<R: std::io::Read + std::io::Seek>(
+    archive: &mut ModArchive<R>,
+    path: &str,
+    dialog: Option<&Dialog>,
+) -> Result<CollabMap> {
+    let sid = map_sid(path);
+    let data = archive.read_file(path)?;
+    let map = map::decode::decode_map(&data).map_err(map::Error::Decode)?;
+
+    let mut map_data = CollabMapData::from_map(&map)?;
+    if let Some(icon) = &map_data.icon {
+        let icon_path = format!("Graphics/Atlases/Gui/{icon}.png");
+        map_data.icon_in_mod = archive.list_files().any(|file| file == icon_path);
+    }
+    if let Some(dialog) = dialog {
+        map_data.credits = dialog
+            .get(&format!("{sid}_collabcredits"))
+            .map(String::from);
+        map_data.credit_tags = dialog
+            .get(&format!("{sid}_collabcreditstags"))
+            .map(String::from);
+    }
+
+    Ok(CollabMap {
+        sid: sid.to_owned(),
+        name: dialog.and_then(|dialog| dialog.get(sid)).map(String::from),
+        data: map_data,
+    })
+}
+
+impl CollabMapData {
+    /// Reads the icon and berries of a decoded map. Credits need the dialog and are left empty.
+    pub fn from_map(map: &Element<'_>) -> map::Result<CollabMapData> {
+        let mut data = CollabMapData {
+            icon: match map.find_child_with_name("meta") {
+                Some(meta) => meta
+                    .attributes
+                    .get("Icon")
+                    .and_then(|icon| icon.get::<&str>())
+                    .map(|icon| icon.replace('\\', "/")),
+                None => None,
+            },
+            ..Default::default()
+        };
+
+        let rooms = map.child_with_name("levels")?;
+        let entities = rooms
+            .children
+            .iter()
+            .filter_map(|room| room.find_child_with_name("entities"))
+            .flat_map(|entities| &entities.children);
+        for entity in entities {
+            match entity.name {
+                "CollabUtils2/SilverBerry" => data.silver_berry = true,
+                "CollabUtils2/SpeedBerry" => {
+                    data.speed_berry = Some(SpeedBerryTimes {
+                        gold: entity.try_get_attr_num("goldTime")?,
+                        silver: entity.try_get_attr_num("silverTime")?,
+                        bronze: entity.try_get_attr_num("bronzeTime")?,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use crate::map::decode::{
+        tests::{element, encode_map},
+        Value,
+    };
+
+    use super::*;
+
+    fn room<'a>(
+        name: &'a str,
+        entities: Vec<Element<'a>>,
+        triggers: Vec<Element<'a>>,
+    ) -> Element<'a> {
+        element(
+            "level",
+            &[
+                ("name", Value::String(name.into())),
+                ("x", Value::I32(0)),
+                ("y", Value::I32(0)),
+                ("width", Value::I32(320)),
+                ("height", Value::I32(184)),
+            ],
+            vec![
+                element("entities", &[], entities),
+                element("triggers", &[], triggers),
+            ],
+        )
+    }
+
+    fn map<'a>(meta: Option<Element<'a>>, rooms: Vec<Element<'a>>) -> Element<'a> {
+        let mut children = vec![element("levels", &[], rooms)];
+        children.extend(meta);
+        element("Map", &[], children)
+    }
+
+    fn entrance(map: &str, x: i32) -> Element<'_> {
+        element(
+            "CollabUtils2/ChapterPanelTrigger",
+            &[
+                ("map", Value::String(map.into())),
+                ("x", Value::I32(x)),
+                ("y", Value::I32(100)),
+                ("width", Value::I32(16)),
+                ("height", Value::I32(16)),
+            ],
+            vec![],
+        )
+    }
+
+    fn mod_archive(files: &[(&str, Vec<u8>)]) -> ModArchive<Cursor<Vec<u8>>> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in files {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(data).unwrap();
+        }
+        ModArchive::new(Cursor::new(zip.finish().unwrap().into_inner())).unwrap()
+    }
+
+    #[test]
+    fn map_data() {
+        let meta = element(
+            "meta",
+            &[("Icon", Value::String(r"Collab\icons\a".into()))],
+            vec![],
+        );
+        let speed_berry = element(
+            "CollabUtils2/SpeedBerry",
+            &[
+                ("goldTime", Value::F32(12.5)),
+                ("bronzeTime", Value::I32(30)),
+            ],
+            vec![],
+        );
+        let silver_berry = element("CollabUtils2/SilverBerry", &[], vec![]);
+        let with_berries = map(
+            Some(meta),
+            vec![
+                room("a", vec![speed_berry], vec![]),
+                room("b", vec![silver_berry], vec![]),
+            ],
+        );
+
+        let data = CollabMapData::from_map(&with_berries).unwrap();
+        assert_eq!(data.icon.as_deref(), Some("Collab/icons/a"));
+        assert!(data.silver_berry);
+        assert_eq!(
+            data.speed_berry,
+            Some(SpeedBerryTimes {
+                gold: Some(12.5),
+                silver: None,
+                bronze: Some(30.0),
+            })
+        );
+
+        let plain = CollabMapData::from_map(&map(None, vec![room("a", vec![], vec![])])).unwrap();
+        assert_eq!(plain.icon, None);
+        assert!(!plain.silver_berry);
+        assert_eq!(plain.speed_berry, None);
+    }
+
+    #[test]
+    fn collab_structure() {
+        let empty = || encode_map("", &map(None, vec![room("a", vec![], vec![])]));
+        let lobby = encode_map(
+            "",
+            &map(
+                None,
+                vec![room(
+                    "lobby",
+                    vec![],
+                    vec![
+                        entrance("Collab/1-Beginner/b", 8),
+                        entrance("Collab/1-Beginner/a", 100),
+                    ],
+                )],
+            ),
+        );
+        let meta = element(
+            "meta",
+            &[("Icon", Value::String("Collab/icons/b".into()))],
+            vec![],
+        );
+        let with_icon = encode_map("", &map(Some(meta), vec![room("a", vec![], vec![])]));
+
+        let mut archive = mod_archive(&[
+            ("CollabUtils2CollabID.txt", b"Collab\n".to_vec()),
+            ("Maps/Collab/0-Lobbies/0-Prologue.bin", empty()),
+            ("Maps/Collab/0-Lobbies/1-Beginner.bin", lobby),
+            ("Maps/Collab/0-Lobbies/9-Epilogue.bin", empty()),
+            ("Maps/Collab/0-Gyms/dash.bin", empty()),
+            ("Maps/Collab/1-Beginner/a.bin", empty()),
+            ("Maps/Collab/1-Beginner/b.bin", with_icon),
+            ("Maps/Collab/1-Beginner/c.bin", empty()),
+            ("Maps/Collab/1-Beginner/ZZ-HeartSide.bin", empty()),
+            ("Graphics/Atlases/Gui/Collab/icons/b.png", Vec::new()),
+        ]);
+        let dialog = Dialog::from_txt(
+            "Collab_1_Beginner_a= Map A\nCollab_1_Beginner_a_collabcredits= by someone\n",
+        );
+
+        let collab = read_collab(&mut archive, Some(&dialog)).unwrap().unwrap();
+        assert_eq!(collab.id, "Collab");
+        assert_eq!(
+            collab.prologue.as_deref(),
+            Some("Collab/0-Lobbies/0-Prologue")
+        );
+        assert_eq!(
+            collab.epilogue.as_deref(),
+            Some("Collab/0-Lobbies/9-Epilogue")
+        );
+        assert_eq!(collab.gyms, ["Collab/0-Gyms/dash"]);
+
+        let [lobby] = &collab.lobbies[..] else {
+            panic!("expected one lobby, got {:?}", collab.lobbies);
+        };
+        assert_eq!(lobby.folder, "1-Beginner");
+        // entrance order, then maps without an entrance
+        let sids = lobby
+            .maps
+            .iter()
+            .map(|map| map.sid.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sids,
+            [
+                "Collab/1-Beginner/b",
+                "Collab/1-Beginner/a",
+                "Collab/1-Beginner/c"
+            ]
+        );
+        assert_eq!(
+            lobby.heart_side.as_ref().map(|map| map.sid.as_str()),
+            Some("Collab/1-Beginner/ZZ-HeartSide")
+        );
+
+        let (b, a) = (&lobby.maps[0], &lobby.maps[1]);
+        assert_eq!(a.display_name(), "Map A");
+        assert_eq!(a.data.credits.as_deref(), Some("by someone"));
+        assert_eq!(b.display_name(), "Collab/1-Beginner/b");
+        assert_eq!(b.data.credits, None);
+        assert!(b.data.icon_in_mod);
+        assert!(!a.data.icon_in_mod);
+    }
+
+    #[test]
+    fn not_a_collab() {
+        let mut archive = mod_archive(&[("Maps/map.bin", Vec::new())]);
+        assert!(read_collab(&mut archive, None).unwrap().is_none());
+    }
+}
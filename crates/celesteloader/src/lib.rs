@@ -14,9 +14,12 @@ pub mod archive;
 pub mod atlas;
 mod binaryreader;
 pub mod cct_physics_inspector;
+pub mod collab;
 pub mod dialog;
+pub mod lobby;
 pub mod map;
 pub mod save;
+pub mod tas;
 pub mod tileset;
 
 mod steam_locate;
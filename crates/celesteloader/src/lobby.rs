@@ -0,0 +1,423 @@
+//! CollabUtils2 lobbies of a collab mod.
+//!
+//! Every map in a `Maps/<collab>/0-Lobbies` folder is a lobby. Its `ChapterPanelTrigger`s are the entrances to the
+//! collab maps, `LobbyMapWarp`s are the benches and the `MiniHeartDoor` leads to the heart side.
+
+use crate::{
+    archive::{map_sid, ModArchive, Result},
+    dialog::Dialog,
+    map::{
+        self,
+        decode::{self, Element},
+        Bounds, Pos,
+    },
+};
+
+/// Entrance to a map of the lobby
+#[derive(Debug, Clone)]
+pub struct LobbyEntrance {
+    /// SID of the map, e.g. `StrawberryJam2021/1-Beginner/maya`
+    pub sid: String,
+    /// Name of the map from the dialog file
+    pub name: Option<String>,
+    pub room: String,
+    /// Bottom center of the chapter panel trigger
+    pub position: (i32, i32),
+}
+
+impl LobbyEntrance {
+    /// Dialog name if known, otherwise the SID
+    pub fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.sid)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LobbyBench {
+    pub warp_id: String,
+    /// `A`, `B`, ... for numeric warp ids, the warp id otherwise
+    pub name: String,
+    pub room: String,
+    pub position: (i32, i32),
+}
+
+#[derive(Debug, Clone)]
+pub struct LobbySpawn {
+    pub room: String,
+    pub position: (i32, i32),
+}
+
+#[derive(Debug, Clone)]
+pub struct HeartDoor {
+    pub room: String,
+    /// Bottom center of the door
+    pub position: (i32, i32),
+    /// Mini hearts needed to open the door
+    pub requires: Option<i32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LobbyRoom {
+    pub name: String,
+    pub bounds: Bounds,
+}
+
+#[derive(Debug, Clone)]
+pub struct Lobby {
+    /// SID of the lobby map, e.g. `StrawberryJam2021/0-Lobbies/1-Beginner`
+    pub sid: String,
+    /// Name of the lobby from the dialog file
+    pub name: Option<String>,
+    /// Default spawn, or the first spawn if there is none
+    pub spawn: Option<LobbySpawn>,
+    /// Sorted by room, then y and x
+    pub entrances: Vec<LobbyEntrance>,
+    /// Sorted by warp id
+    pub benches: Vec<LobbyBench>,
+    pub heart_door: Option<HeartDoor>,
+    pub rooms: Vec<LobbyRoom>,
+}
+
+/// Whether the map file at `path` is in a `0-Lobbies` folder
+pub fn is_lobby_path(path: &str) -> bool {
+    path.starts_with("Maps/")
+        && path.ends_with(".bin")
+        && path
+            .rfind('/')
+            .is_some_and(|idx| path[..idx].ends_with("0-Lobbies"))
+}
+
+/// Reads all lobbies of the collab, in the order of their map files
+pub fn read_lobbies<R: std::io::Read + std::io::Seek>(
+    archive: &mut ModArchive<R>,
+    dialog: Option<&Dialog>,
+) -> Result<Vec<Lobby>> {
+    let mut paths = archive
+        .list_files()
+        .filter(|path| is_lobby_path(path))
+        .map(String::from)
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    paths
+        .iter()
+        .map(|path| read_lobby(archive, path, dialog))
+        .collect()
+}
+
+/// Reads the lobby map at `path`, e.g. `Maps/StrawberryJam2021/0-Lobbies/1-Beginner.bin`
+pub fn read_lobby<R: std::io::Read + std::io::Seek>(
+    archive: &mut ModArchive<R>,
+    path: &str,
+    dialog: Option<&Dialog>,
+) -> Result<Lobby> {
+    let data = archive.read_file(path)?;
+    let map = decode::decode_map(&data).map_err(map::Error::Decode)?;
+    Ok(Lobby::from_map(map_sid(path), &map, dialog)?)
+}
+
+impl Lobby {
+    /// Reads the lobby from a decoded map. Names are looked up in `dialog` if given.
+    pub fn from_map(sid: &str, map: &Element<'_>, dialog: Option<&Dialog>) -> map::Result<Lobby> {
+        let rooms = map.child_with_name("levels")?;
+
+        let mut lobby = Lobby {
+            sid: sid.to_owned(),
+            name: dialog.and_then(|dialog| dialog.get(sid)).map(String::from),
+            spawn: None,
+            entrances: Vec::new(),
+            benches: Vec::new(),
+            heart_door: None,
+            rooms: Vec::new(),
+        };
+        let mut default_spawn = None;
+        let mut first_spawn = None;
+
+        for room in &rooms.children {
+            let room_pos = (room.get_attr_int("x")?, room.get_attr_int("y")?);
+            let room_size = (room.get_attr_int("width")?, room.get_attr_int("height")?);
+            let room_name = room.get_attr::<&str>("name")?;
+            lobby.rooms.push(LobbyRoom {
+                name: room_name.to_owned(),
+                bounds: Bounds {
+                    position: Pos {
+                        x: room_pos.0,
+                        y: room_pos.1,
+                    },
+                    size: (room_size.0 as u32, room_size.1 as u32),
+                },
+            });
+
+            let absolute = |(x, y): (i32, i32)| (room_pos.0 + x, room_pos.1 + y);
+
+            if let Some(entities) = room.find_child_with_name("entities") {
+                for entity in &entities.children {
+                    let pos = (entity.get_attr_int("x")?, entity.get_attr_int("y")?);
+                    match entity.name {
+                        "CollabUtils2/LobbyMapWarp" => {
+                            let warp_id = entity.get_attr::<&str>("warpId")?;
+                            lobby.benches.push(LobbyBench {
+                                warp_id: warp_id.to_owned(),
+                                name: bench_name(warp_id),
+                                room: room_name.to_owned(),
+                                position: absolute(pos),
+                            });
+                        }
+                        "CollabUtils2/MiniHeartDoor" => {
+                            let width = entity.get_attr_int_or("width", 0)?;
+                            let height = entity.get_attr_int_or("height", 0)?;
+                            lobby.heart_door = Some(HeartDoor {
+                                room: room_name.to_owned(),
+                                position: absolute((pos.0 + width / 2, pos.1 + height)),
+                                requires: entity
+                                    .attributes
+                                    .get("requires")
+                                    .and_then(|requires| requires.get_int()),
+                            });
+                        }
+                        "player" => {
+                            let spawn = || LobbySpawn {
+                                room: room_name.to_owned(),
+                                position: absolute(pos),
+                            };
+                            if first_spawn.is_none() {
+                                first_spawn = Some(spawn());
+                            }
+
+                            let is_default_spawn = entity.get_attr_or("isDefaultSpawn", false)?;
+                            if is_default_spawn && default_spawn.is_none() {
+                                default_spawn = Some(spawn());
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            let Some(triggers) = room.find_child_with_name("triggers") else {
+                continue;
+            };
+            for trigger in &triggers.children {
+                if trigger.name != "CollabUtils2/ChapterPanelTrigger" {
+                    continue;
+                }
+
+                let trigger_pos = (trigger.get_attr_int("x")?, trigger.get_attr_int("y")?);
+                let trigger_size = (
+                    trigger.get_attr_int("width")?,
+                    trigger.get_attr_int("height")?,
+                );
+                if trigger_pos.0 > room_size.0 || trigger_pos.1 > room_size.1 {
+                    continue;
+                }
+
+                let sid = trigger.get_attr::<&str>("map")?;
+                lobby.entrances.push(LobbyEntrance {
+                    sid: sid.to_owned(),
+                    name: dialog.and_then(|dialog| dialog.get(sid)).map(String::from),
+                    room: room_name.to_owned(),
+                    position: absolute((
+                        trigger_pos.0 + trigger_size.0 / 2,
+                        trigger_pos.1 + trigger_size.1,
+                    )),
+                });
+            }
+        }
+
+        lobby.entrances.sort_by(|a, b| {
+            (&a.room, a.position.1, a.position.0).cmp(&(&b.room, b.position.1, b.position.0))
+        });
+        lobby.benches.sort_by(|a, b| a.warp_id.cmp(&b.warp_id));
+        lobby.spawn = default_spawn.or(first_spawn);
+
+        Ok(lobby)
+    }
+
+    /// SIDs of the maps entered from this lobby
+    pub fn map_sids(&self) -> impl Iterator<Item = &str> {
+        self.entrances.iter().map(|entrance| entrance.sid.as_str())
+    }
+}
+
+fn bench_name(warp_id: &str) -> String {
+    match warp_id.parse::<u8>() {
+        Ok(warp_id) if warp_id < 26 => ((b'A' + warp_id) as char).to_string(),
+        _ => warp_id.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::decode::{tests::element, Value};
+
+    fn room<'a>(
+        name: &'a str,
+        x: i32,
+        entities: Vec<Element<'a>>,
+        triggers: Vec<Element<'a>>,
+    ) -> Element<'a> {
+        element(
+            "level",
+            &[
+                ("name", Value::String(name.into())),
+                ("x", Value::I32(x)),
+                ("y", Value::I32(0)),
+                ("width", Value::I32(320)),
+                ("height", Value::I32(184)),
+            ],
+            vec![
+                element("entities", &[], entities),
+                element("triggers", &[], triggers),
+            ],
+        )
+    }
+
+    fn entity<'a>(
+        name: &'a str,
+        pos: (i32, i32),
+        attributes: &[(&'a str, Value<'a>)],
+    ) -> Element<'a> {
+        let mut all = vec![("x", Value::I32(pos.0)), ("y", Value::I32(pos.1))];
+        all.extend_from_slice(attributes);
+        element(name, &all, vec![])
+    }
+
+    fn entrance(map: &str, pos: (i32, i32)) -> Element<'_> {
+        entity(
+            "CollabUtils2/ChapterPanelTrigger",
+            pos,
+            &[
+                ("map", Value::String(map.into())),
+                ("width", Value::I32(16)),
+                ("height", Value::I32(16)),
+            ],
+        )
+    }
+
+    fn warp(warp_id: &str, pos: (i32, i32)) -> Element<'_> {
+        entity(
+            "CollabUtils2/LobbyMapWarp",
+            pos,
+            &[("warpId", Value::String(warp_id.into()))],
+        )
+    }
+
+    fn lobby_map() -> Element<'static> {
+        let a = room(
+            "a",
+            0,
+            vec![
+                entity("player", (10, 100), &[]),
+                warp("1", (30, 100)),
+                warp("hub", (60, 100)),
+                entity(
+                    "CollabUtils2/MiniHeartDoor",
+                    (100, 50),
+                    &[
+                        ("width", Value::I32(16)),
+                        ("height", Value::I32(32)),
+                        ("requires", Value::I32(5)),
+                    ],
+                ),
+            ],
+            vec![
+                entrance("C/1-Beginner/x", (200, 100)),
+                entrance("C/1-Beginner/y", (40, 100)),
+                // outside of the room
+                entrance("C/1-Beginner/outside", (400, 100)),
+            ],
+        );
+        let b = room(
+            "b",
+            320,
+            vec![
+                entity(
+                    "player",
+                    (20, 100),
+                    &[("isDefaultSpawn", Value::Bool(true))],
+                ),
+                warp("0", (40, 100)),
+            ],
+            vec![entrance("C/1-Beginner/z", (0, 0))],
+        );
+        element("Map", &[], vec![element("levels", &[], vec![b, a])])
+    }
+
+    #[test]
+    fn lobby_from_map() {
+        let dialog =
+            Dialog::from_txt("C_0_Lobbies_1_Beginner= Beginner Lobby\nC_1_Beginner_x= Map X\n");
+        let map = lobby_map();
+        let lobby = Lobby::from_map("C/0-Lobbies/1-Beginner", &map, Some(&dialog)).unwrap();
+
+        assert_eq!(lobby.name.as_deref(), Some("Beginner Lobby"));
+
+        let rooms = lobby
+            .rooms
+            .iter()
+            .map(|room| (room.name.as_str(), room.bounds.position.x));
+        assert_eq!(rooms.collect::<Vec<_>>(), [("b", 320), ("a", 0)]);
+
+        let entrances = lobby
+            .entrances
+            .iter()
+            .map(|entrance| {
+                (
+                    entrance.sid.as_str(),
+                    entrance.room.as_str(),
+                    entrance.position,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entrances,
+            [
+                ("C/1-Beginner/y", "a", (48, 116)),
+                ("C/1-Beginner/x", "a", (208, 116)),
+                ("C/1-Beginner/z", "b", (328, 16)),
+            ]
+        );
+        assert_eq!(lobby.entrances[0].name, None);
+        assert_eq!(lobby.entrances[1].name.as_deref(), Some("Map X"));
+        assert_eq!(lobby.entrances[1].display_name(), "Map X");
+        assert_eq!(lobby.entrances[2].display_name(), "C/1-Beginner/z");
+
+        let benches = lobby
+            .benches
+            .iter()
+            .map(|bench| (bench.warp_id.as_str(), bench.name.as_str(), bench.position))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            benches,
+            [
+                ("0", "A", (360, 100)),
+                ("1", "B", (30, 100)),
+                ("hub", "hub", (60, 100))
+            ]
+        );
+
+        let spawn = lobby.spawn.unwrap();
+        assert_eq!((spawn.room.as_str(), spawn.position), ("b", (340, 100)));
+
+        let door = lobby.heart_door.unwrap();
+        assert_eq!((door.room.as_str(), door.position), ("a", (108, 82)));
+        assert_eq!(door.requires, Some(5));
+    }
+
+    #[test]
+    fn lobby_without_dialog() {
+        let map = lobby_map();
+        let lobby = Lobby::from_map("C/0-Lobbies/1-Beginner", &map, None).unwrap();
+
+        assert_eq!(lobby.name, None);
+        assert!(lobby
+            .entrances
+            .iter()
+            .all(|entrance| entrance.name.is_none()));
+        assert_eq!(
+            lobby.map_sids().collect::<Vec<_>>(),
+            ["C/1-Beginner/y", "C/1-Beginner/x", "C/1-Beginner/z"]
+        );
+    }
+}
@@ -29,3 +29,120 @@ pub fn decode_map(buffer: &[u8]) -> Result<Element<'_>> {
 
     Ok(res)
 }
+
+/// Building and encoding maps in tests
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn element<'a>(
+        name: &'a str,
+        attributes: &[(&'a str, Value<'a>)],
+        children: Vec<Element<'a>>,
+    ) -> Element<'a> {
+        Element {
+            name,
+            attributes: attributes.iter().cloned().collect(),
+            children,
+        }
+    }
+
+    /// Encodes `map` in the `.bin` format read by [`decode_map`], with strings stored inline
+    pub(crate) fn encode_map(package: &str, map: &Element) -> Vec<u8> {
+        fn string(out: &mut Vec<u8>, string: &str) {
+            let mut len = string.len();
+            loop {
+                let byte = (len & 127) as u8;
+                len >>= 7;
+                if len == 0 {
+                    out.push(byte);
+                    break;
+                }
+                out.push(byte | 128);
+            }
+            out.extend_from_slice(string.as_bytes());
+        }
+        fn collect_names<'a>(element: &Element<'a>, lookup: &mut Vec<&'a str>) {
+            lookup.push(element.name);
+            lookup.extend(element.attributes.keys());
+            for child in &element.children {
+                collect_names(child, lookup);
+            }
+        }
+        fn encode(out: &mut Vec<u8>, element: &Element, lookup: &[&str]) {
+            let index = |name: &str| lookup.iter().position(|&n| n == name).unwrap() as u16;
+            out.extend(index(element.name).to_le_bytes());
+            out.push(element.attributes.len() as u8);
+            for (key, value) in &element.attributes {
+                out.extend(index(key).to_le_bytes());
+                match value {
+                    Value::Bool(val) => out.extend([0, *val as u8]),
+                    Value::U8(val) => out.extend([1, *val]),
+                    Value::I16(val) => {
+                        out.push(2);
+                        out.extend(val.to_le_bytes());
+                    }
+                    Value::I32(val) => {
+                        out.push(3);
+                        out.extend(val.to_le_bytes());
+                    }
+                    Value::F32(val) => {
+                        out.push(4);
+                        out.extend(val.to_le_bytes());
+                    }
+                    Value::String(val) => {
+                        out.push(6);
+                        string(out, val);
+                    }
+                }
+            }
+            out.extend((element.children.len() as u16).to_le_bytes());
+            for child in &element.children {
+                encode(out, child, lookup);
+            }
+        }
+
+        let mut lookup = Vec::new();
+        collect_names(map, &mut lookup);
+        lookup.sort_unstable();
+        lookup.dedup();
+
+        let mut out = Vec::new();
+        string(&mut out, "CELESTE MAP");
+        string(&mut out, package);
+        out.extend((lookup.len() as i16).to_le_bytes());
+        for name in &lookup {
+            string(&mut out, name);
+        }
+        encode(&mut out, map, &lookup);
+        out
+    }
+
+    #[test]
+    fn encode_roundtrip() {
+        let map = element(
+            "Map",
+            &[],
+            vec![element(
+                "levels",
+                &[("flag", Value::Bool(true)), ("x", Value::I32(-300))],
+                vec![element(
+                    "level",
+                    &[("name", Value::String("a-00".into()))],
+                    vec![],
+                )],
+            )],
+        );
+        let data = encode_map("Test", &map);
+        let decoded = decode_map(&data).unwrap();
+
+        assert_eq!(decoded.attributes["package"].get::<&str>(), Some("Test"));
+        let levels = decoded.find_child_with_name("levels").unwrap();
+        assert_eq!(levels.attributes["x"].get_int(), Some(-300));
+        assert_eq!(levels.attributes["flag"].get::<bool>(), Some(true));
+        assert_eq!(
+            levels.children[0].attributes["name"].get::<&str>(),
+            Some("a-00")
+        );
+    }
+}
@@ -1,4 +1,5 @@
 pub mod decode;
+pub mod style;
 pub mod utils;
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -163,6 +164,19 @@ impl<'a> Element<'a> {
             got: value.type_name(),
         })
     }
+    pub fn try_get_attr_num(&'a self, name: &'static str) -> Result<Option<f32>> {
+        let Some(value) = self.attributes.get(name) else {
+            return Ok(None);
+        };
+        value
+            .get_number()
+            .ok_or(Error::InvalidAttributeType {
+                attribute: name,
+                expected: "number",
+                got: value.type_name(),
+            })
+            .map(Some)
+    }
 }
 
 impl ElementOwned {
@@ -246,7 +260,7 @@ pub struct Map {
     pub package: String,
     pub rooms: Vec<Room>,
     pub fillers: Vec<Filler>,
-    // TODO style
+    pub style: style::Style,
     pub meta: Metadata,
 }
 
@@ -337,6 +351,7 @@ pub struct Trigger {
     pub position: (f32, f32),
     pub extents: (i32, i32),
     pub name: String,
+    pub nodes: Vec<EntityNode>,
 }
 
 #[derive(Debug)]
@@ -347,6 +362,8 @@ pub struct Decal {
     pub scale_y: f32,
     pub rotation: f32,
     pub texture: String,
+    /// Overrides the default depth of the decal layer
+    pub depth: Option<i32>,
 }
 
 pub fn load_map(data: &[u8]) -> Result<Map> {
@@ -357,9 +374,10 @@ pub fn load_map(data: &[u8]) -> Result<Map> {
 pub fn load_map_from_element(map: &Element<'_>) -> Result<Map> {
     let rooms = map.child_with_name("levels")?;
     let fillers = map.find_child_with_name("Filler");
-    let _style = map.child_with_name("Style")?;
-    let _fgstyle = map.find_child_with_name("Foregrounds");
-    let _bgstyle = map.find_child_with_name("Backgrounds");
+    let style = map
+        .find_child_with_name("Style")
+        .map(style::load_style)
+        .unwrap_or_default();
 
     let fillers = fillers
         .map(|fillers| {
@@ -393,6 +411,7 @@ pub fn load_map_from_element(map: &Element<'_>) -> Result<Map> {
         package: map.get_attr::<&str>("package")?.to_string(),
         rooms,
         fillers,
+        style,
         meta,
     })
 }
@@ -465,19 +484,7 @@ fn load_room(room: &Element) -> Result<Room> {
                     owned.attributes.remove("originX");
                     owned.attributes.remove("originY");
 
-                    let nodes = entity
-                        .children
-                        .iter()
-                        .map(|node| {
-                            if node.name != "node" {
-                                return Err(Error::MissingElement("node"));
-                            }
-                            let x = node.get_attr_num("x")?;
-                            let y = node.get_attr_num("y")?;
-
-                            Ok(EntityNode { position: (x, y) })
-                        })
-                        .collect::<Result<Vec<_>, _>>()?;
+                    let nodes = parse_nodes(entity)?;
 
                     Ok(Entity {
                         id,
@@ -504,11 +511,14 @@ fn load_room(room: &Element) -> Result<Room> {
                     let width = trigger.get_attr_int("width")?;
                     let height = trigger.get_attr_int("height")?;
 
+                    let nodes = parse_nodes(trigger)?;
+
                     Ok(Trigger {
                         id,
                         position: (x, y),
                         extents: (width, height),
                         name: trigger.name.to_owned(),
+                        nodes,
                     })
                 })
                 .collect::<Result<Vec<_>>>()
@@ -572,6 +582,22 @@ fn load_room(room: &Element) -> Result<Room> {
     })
 }
 
+fn parse_nodes(element: &Element) -> Result<Vec<EntityNode>> {
+    element
+        .children
+        .iter()
+        .map(|node| {
+            if node.name != "node" {
+                return Err(Error::MissingElement("node"));
+            }
+            let x = node.get_attr_num("x")?;
+            let y = node.get_attr_num("y")?;
+
+            Ok(EntityNode { position: (x, y) })
+        })
+        .collect()
+}
+
 fn load_decal(decal: &Element) -> Result<Decal> {
     if decal.attributes.contains_key("jx") {
         eprintln!("unsupported: decal with jx")
@@ -579,9 +605,6 @@ fn load_decal(decal: &Element) -> Result<Decal> {
     if decal.attributes.contains_key("justificationX") {
         eprintln!("unsupported: decal with justificationX")
     }
-    if decal.attributes.contains_key("depth") {
-        eprintln!("unsupported: decal with depth")
-    }
 
     Ok(Decal {
         x: decal.get_attr_num("x")?,
@@ -590,6 +613,10 @@ fn load_decal(decal: &Element) -> Result<Decal> {
         scale_y: decal.get_attr_num("scaleY")?,
         rotation: decal.get_attr_num_or("rotation", 0.0)?,
         texture: decal.get_attr::<&str>("texture")?.replace('\\', "/"),
+        depth: decal
+            .attributes
+            .get("depth")
+            .and_then(|depth| depth.get_int()),
     })
 }
 
@@ -655,6 +682,27 @@ impl Bounds {
         }
     }
 
+    /// The part covered by both bounds, if they overlap
+    pub fn intersection(self, other: Bounds) -> Option<Self> {
+        let x = self.position.x.max(other.position.x);
+        let y = self.position.y.max(other.position.y);
+        let r = self.r().min(other.r());
+        let b = self.b().min(other.b());
+
+        (r > x && b > y).then_some(Bounds {
+            position: Pos { x, y },
+            size: ((r - x) as u32, (b - y) as u32),
+        })
+    }
+
+    /// Extends the bounds by `amount` on every side
+    pub fn grow(self, amount: u32) -> Self {
+        Bounds {
+            position: self.position.offset(-(amount as i32), -(amount as i32)),
+            size: (self.size.0 + 2 * amount, self.size.1 + 2 * amount),
+        }
+    }
+
     pub fn area(&self) -> f32 {
         self.size.0 as f32 * self.size.1 as f32
     }
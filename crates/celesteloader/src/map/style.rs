@@ -0,0 +1,289 @@
+//! Stylegrounds from the `Style` element of a map.
+//!
+//! Stylegrounds inside `apply` groups inherit the attributes of the group, so they are flattened here.
+//! Malformed stylegrounds are skipped with a warning, so they don't prevent loading the map.
+
+use super::{
+    decode::{Element, ElementOwned},
+    Error, Result,
+};
+
+#[derive(Debug, Default)]
+pub struct Style {
+    /// Hex color the screen is cleared to before drawing the backgrounds
+    pub color: Option<String>,
+    pub foregrounds: Vec<Styleground>,
+    pub backgrounds: Vec<Styleground>,
+}
+
+#[derive(Debug)]
+pub enum StylegroundKind {
+    /// Texture from the gameplay atlas
+    Parallax { texture: String, additive: bool },
+    /// Any other backdrop, e.g. `stars` or `snowBg`
+    Effect { name: String },
+}
+
+#[derive(Debug)]
+pub struct Styleground {
+    pub kind: StylegroundKind,
+    /// Comma separated room names in which the styleground is visible, `*` matching anything
+    pub only: Option<String>,
+    /// Comma separated room names in which the styleground is hidden
+    pub exclude: Option<String>,
+    pub flag: Option<String>,
+    pub not_flag: Option<String>,
+    pub position: (f32, f32),
+    pub scroll: (f32, f32),
+    pub speed: (f32, f32),
+    pub loop_x: bool,
+    pub loop_y: bool,
+    pub flip_x: bool,
+    pub flip_y: bool,
+    pub color: Option<String>,
+    pub alpha: f32,
+    /// All attributes, including those inherited from `apply` groups
+    pub raw: ElementOwned,
+}
+
+impl Styleground {
+    /// Whether the styleground is shown in the room named `room`, ignoring flags
+    pub fn visible_in(&self, room: &str) -> bool {
+        let matches = |list: &str| {
+            list.split(',')
+                .map(str::trim)
+                .any(|pattern| glob_match(pattern, room))
+        };
+
+        if self.only.as_deref().is_some_and(|only| !matches(only)) {
+            return false;
+        }
+        if self.exclude.as_deref().is_some_and(matches) {
+            return false;
+        }
+        true
+    }
+}
+
+/// Matches `name` against `pattern`, where `*` matches any sequence of characters
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let Some((prefix, rest)) = pattern.split_once('*') else {
+        return pattern == name;
+    };
+    let Some(mut name) = name.strip_prefix(prefix) else {
+        return false;
+    };
+
+    let mut parts = rest.split('*').peekable();
+    while let Some(part) = parts.next() {
+        if parts.peek().is_none() {
+            return name.ends_with(part);
+        }
+        match name.find(part) {
+            Some(index) => name = &name[index + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+pub(super) fn load_style(style: &Element) -> Style {
+    let mut loaded = Style {
+        color: style
+            .attributes
+            .get("color")
+            .and_then(|color| color.get::<&str>())
+            .map(ToOwned::to_owned),
+        ..Default::default()
+    };
+    if let Some(foregrounds) = style.find_child_with_name("Foregrounds") {
+        load_stylegrounds(foregrounds, None, &mut loaded.foregrounds);
+    }
+    if let Some(backgrounds) = style.find_child_with_name("Backgrounds") {
+        load_stylegrounds(backgrounds, None, &mut loaded.backgrounds);
+    }
+    loaded
+}
+
+fn load_stylegrounds(parent: &Element, apply: Option<&ElementOwned>, out: &mut Vec<Styleground>) {
+    for child in &parent.children {
+        let mut raw = child.to_owned();
+        raw.children.clear();
+        if let Some(apply) = apply {
+            for (key, value) in &apply.attributes {
+                raw.attributes
+                    .entry(key.clone())
+                    .or_insert_with(|| value.clone());
+            }
+        }
+
+        if child.name == "apply" {
+            load_stylegrounds(child, Some(&raw), out);
+        } else {
+            let name = raw.name.clone();
+            match load_styleground(raw) {
+                Ok(styleground) => out.push(styleground),
+                Err(e) => eprintln!("skipping styleground {name}: {e}"),
+            }
+        }
+    }
+}
+
+fn load_styleground(raw: ElementOwned) -> Result<Styleground> {
+    let string = |name: &'static str| -> Result<Option<String>> {
+        Ok(raw
+            .try_get_attr::<&str>(name)?
+            .filter(|val| !val.is_empty())
+            .map(ToOwned::to_owned))
+    };
+    let number = |name: &'static str, default: f32| -> Result<f32> {
+        Ok(raw.try_get_attr_num(name)?.unwrap_or(default))
+    };
+    let bool = |name: &'static str, default: bool| -> Result<bool> {
+        Ok(raw.try_get_attr::<bool>(name)?.unwrap_or(default))
+    };
+
+    let kind = if raw.name == "parallax" {
+        let texture = string("texture")?.ok_or_else(|| Error::MissingAttribute {
+            attribute: "texture",
+            element_name: raw.name.clone(),
+        })?;
+        StylegroundKind::Parallax {
+            texture: texture.replace('\\', "/"),
+            additive: string("blendmode")?.as_deref() == Some("additive"),
+        }
+    } else {
+        StylegroundKind::Effect {
+            name: raw.name.clone(),
+        }
+    };
+
+    Ok(Styleground {
+        kind,
+        only: string("only")?,
+        exclude: string("exclude")?,
+        flag: string("flag")?,
+        not_flag: string("notflag")?,
+        position: (number("x", 0.0)?, number("y", 0.0)?),
+        scroll: (number("scrollx", 1.0)?, number("scrolly", 1.0)?),
+        speed: (number("speedx", 0.0)?, number("speedy", 0.0)?),
+        loop_x: bool("loopx", true)?,
+        loop_y: bool("loopy", true)?,
+        flip_x: bool("flipx", false)?,
+        flip_y: bool("flipy", false)?,
+        color: string("color")?,
+        alpha: number("alpha", 1.0)?,
+        raw,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::decode::{tests::element, Value};
+
+    #[test]
+    fn glob() {
+        assert!(glob_match("a-01", "a-01"));
+        assert!(!glob_match("a-01", "a-010"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a-*", "a-01"));
+        assert!(!glob_match("a-*", "b-01"));
+        assert!(glob_match("*-01", "b-01"));
+        assert!(glob_match("a*b*c", "abc"));
+        assert!(glob_match("a*b*c", "a-b-b-c"));
+        assert!(!glob_match("a*b*c", "a-c-b"));
+        // the prefix and suffix don't overlap
+        assert!(!glob_match("ab*ba", "aba"));
+    }
+
+    #[test]
+    fn visible_in() {
+        let styleground = |only: Option<&str>, exclude: Option<&str>| {
+            let mut attributes = Vec::new();
+            attributes.extend(only.map(|only| ("only", Value::String(only.into()))));
+            attributes.extend(exclude.map(|exclude| ("exclude", Value::String(exclude.into()))));
+            load_styleground(element("stars", &attributes, vec![]).to_owned()).unwrap()
+        };
+
+        assert!(styleground(None, None).visible_in("a-00"));
+
+        let only = styleground(Some("a-00, b-*,c-01"), None);
+        assert!(only.visible_in("a-00"));
+        assert!(only.visible_in("b-12"));
+        assert!(only.visible_in("c-01"));
+        assert!(!only.visible_in("c-02"));
+
+        let exclude = styleground(None, Some("a-*, b-00"));
+        assert!(!exclude.visible_in("a-01"));
+        assert!(!exclude.visible_in("b-00"));
+        assert!(exclude.visible_in("b-01"));
+
+        let both = styleground(Some("*"), Some("*-secret"));
+        assert!(both.visible_in("a-00"));
+        assert!(!both.visible_in("a-secret"));
+    }
+
+    #[test]
+    fn nested_apply() {
+        let parallax = element(
+            "parallax",
+            &[
+                ("texture", Value::String(r"bgs\clouds".into())),
+                ("scrollx", Value::F32(0.1)),
+            ],
+            vec![],
+        );
+        let inner = element(
+            "apply",
+            &[("alpha", Value::F32(0.8)), ("scrollx", Value::F32(0.2))],
+            vec![parallax, element("stars", &[], vec![])],
+        );
+        let outer = element(
+            "apply",
+            &[
+                ("only", Value::String("a-*".into())),
+                ("alpha", Value::F32(0.5)),
+            ],
+            vec![
+                inner,
+                // skipped, as parallax stylegrounds need a texture
+                element("parallax", &[], vec![]),
+            ],
+        );
+        let style = element(
+            "Style",
+            &[],
+            vec![element(
+                "Backgrounds",
+                &[],
+                vec![outer, element("snowBg", &[], vec![])],
+            )],
+        );
+
+        let style = load_style(&style);
+        assert!(style.foregrounds.is_empty());
+        let [parallax, stars, snow] = &style.backgrounds[..] else {
+            panic!("expected three backgrounds, got {:?}", style.backgrounds);
+        };
+
+        let StylegroundKind::Parallax { texture, additive } = &parallax.kind else {
+            panic!("expected a parallax, got {:?}", parallax.kind);
+        };
+        assert_eq!(texture, "bgs/clouds");
+        assert!(!additive);
+        assert_eq!(parallax.only.as_deref(), Some("a-*"));
+        assert_eq!(parallax.alpha, 0.8);
+        assert_eq!(parallax.scroll, (0.1, 1.0));
+
+        assert!(matches!(&stars.kind, StylegroundKind::Effect { name } if name == "stars"));
+        assert_eq!(stars.only.as_deref(), Some("a-*"));
+        assert_eq!(stars.alpha, 0.8);
+        assert_eq!(stars.scroll, (0.2, 1.0));
+        assert!(stars.raw.attributes.contains_key("alpha"));
+
+        assert!(matches!(&snow.kind, StylegroundKind::Effect { name } if name == "snowBg"));
+        assert_eq!(snow.only, None);
+        assert_eq!(snow.alpha, 1.0);
+    }
+}
@@ -0,0 +1,856 @@
+//! Parser and formatter for CelesteTAS `.tas` files.
+//!
+//! ```text
+//! RecordCount: 1
+//! console load Celeste/1-ForsakenCity
+//!    1
+//!
+//! #lvl_1
+//!   14,R,J
+//!    5,R,X
+//! ***500
+//! ```
+
+use std::{fmt::Write, str::FromStr};
+
+pub mod resolve;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug)]
+pub struct Error {
+    /// 1-based line number
+    pub line: usize,
+    pub kind: ErrorKind,
+}
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    InvalidFrameCount(String),
+    InvalidAction(String),
+    InvalidCommandArgument {
+        command: &'static str,
+        argument: String,
+    },
+    MissingCommandArgument(&'static str),
+    InvalidTime(String),
+}
+
+impl std::error::Error for Error {}
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            ErrorKind::InvalidFrameCount(frames) => write!(f, "invalid frame count `{frames}`"),
+            ErrorKind::InvalidAction(action) => write!(f, "unknown action `{action}`"),
+            ErrorKind::InvalidCommandArgument { command, argument } => {
+                write!(f, "invalid argument `{argument}` for `{command}`")
+            }
+            ErrorKind::MissingCommandArgument(command) => {
+                write!(f, "missing argument for `{command}`")
+            }
+            ErrorKind::InvalidTime(time) => write!(f, "invalid time `{time}`"),
+        }
+    }
+}
+
+/// A parsed `.tas` file.
+///
+/// `TasFile::parse(text)?.to_string()` reproduces `text` as long as it is formatted the way Studio formats it.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TasFile {
+    pub lines: Vec<Line>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Line {
+    Empty,
+    Input(Input),
+    Command(Command),
+    /// `#lvl_1`, usable as a target for `Read`
+    Label(String),
+    /// `# some comment`, including the leading whitespace
+    Comment(String),
+    /// `#0:02.873(169)`
+    TimeComment(TasTime),
+    /// `***`, `***S`, `***500`
+    Breakpoint(Breakpoint),
+    /// A line [`TasFile::parse_lenient`] could not make sense of, kept as is
+    Unparsed(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Input {
+    pub frames: u32,
+    pub actions: Vec<Action>,
+    /// Feather aim, only valid together with [`Action::Feather`]
+    pub angle: Option<f32>,
+    pub magnitude: Option<f32>,
+}
+
+impl Input {
+    pub fn new(frames: u32, actions: impl Into<Vec<Action>>) -> Self {
+        Input {
+            frames,
+            actions: actions.into(),
+            angle: None,
+            magnitude: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Left,
+    Right,
+    Up,
+    Down,
+    Jump,
+    Jump2,
+    Dash,
+    Dash2,
+    DemoDash,
+    DemoDash2,
+    Grab,
+    Grab2,
+    Start,
+    Restart,
+    Feather,
+    Journal,
+    Confirm,
+    DashOnly,
+    MoveOnly,
+    CustomBinding,
+}
+
+impl Action {
+    const ALL: [(char, Action); 20] = [
+        ('L', Action::Left),
+        ('R', Action::Right),
+        ('U', Action::Up),
+        ('D', Action::Down),
+        ('J', Action::Jump),
+        ('K', Action::Jump2),
+        ('X', Action::Dash),
+        ('C', Action::Dash2),
+        ('Z', Action::DemoDash),
+        ('V', Action::DemoDash2),
+        ('G', Action::Grab),
+        ('H', Action::Grab2),
+        ('S', Action::Start),
+        ('Q', Action::Restart),
+        ('F', Action::Feather),
+        ('N', Action::Journal),
+        ('O', Action::Confirm),
+        ('A', Action::DashOnly),
+        ('M', Action::MoveOnly),
+        ('P', Action::CustomBinding),
+    ];
+
+    pub fn from_char(c: char) -> Option<Action> {
+        let c = c.to_ascii_uppercase();
+        Action::ALL
+            .iter()
+            .find(|&&(char, _)| char == c)
+            .map(|&(_, action)| action)
+    }
+
+    pub fn as_char(self) -> char {
+        Action::ALL
+            .iter()
+            .find(|&&(_, action)| action == self)
+            .map(|&(char, _)| char)
+            .unwrap()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Command {
+    pub kind: CommandKind,
+    pub separator: Separator,
+}
+
+impl Command {
+    pub fn new(kind: CommandKind) -> Self {
+        let separator = match kind {
+            CommandKind::Console(_) | CommandKind::Repeat(_) => Separator::Space,
+            _ => Separator::Comma,
+        };
+        Command { kind, separator }
+    }
+}
+
+impl From<CommandKind> for Command {
+    fn from(kind: CommandKind) -> Self {
+        Command::new(kind)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandKind {
+    /// `Read,file,start,end`, where start and end are labels or line numbers
+    Read {
+        file: String,
+        start: Option<String>,
+        end: Option<String>,
+    },
+    /// `console load Celeste/1-ForsakenCity`
+    Console(Vec<String>),
+    RecordCount(Option<u32>),
+    ChapterTime(Option<TasTime>),
+    EnforceLegal,
+    Set {
+        key: String,
+        value: String,
+    },
+    Repeat(u32),
+    EndRepeat,
+    Other {
+        name: String,
+        args: Vec<String>,
+    },
+}
+
+impl CommandKind {
+    /// The map loaded by a `console load` command
+    pub fn console_load(&self) -> Option<&str> {
+        match self {
+            CommandKind::Console(args) => match args.as_slice() {
+                [load, map, ..] if load.eq_ignore_ascii_case("load") => Some(map),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// How the command arguments were separated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Separator {
+    /// `Read,file`
+    Comma,
+    /// `Read, file`
+    CommaSpace,
+    /// `Read file`
+    Space,
+}
+
+impl Separator {
+    fn as_str(self) -> &'static str {
+        match self {
+            Separator::Comma => ",",
+            Separator::CommaSpace => ", ",
+            Separator::Space => " ",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Breakpoint {
+    /// `***S`
+    pub save_state: bool,
+    /// `***!`
+    pub force: bool,
+    /// `***500`, fast forward with the given speed until the breakpoint is hit
+    pub speed: Option<f32>,
+    /// The flags as they were written, e.g. `!S` or `s`.
+    /// Used for formatting as long as they still agree with `save_state` and `force`.
+    pub flags: String,
+}
+
+/// An ingame time, measured in frames. Formatted like `0:02.873(169)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct TasTime {
+    pub frames: u32,
+}
+
+impl TasTime {
+    pub fn from_frames(frames: u32) -> Self {
+        TasTime { frames }
+    }
+
+    pub fn millis(self) -> u64 {
+        self.frames as u64 * 17
+    }
+}
+
+impl std::fmt::Display for TasTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ms = self.millis();
+        let s = ms / 1000;
+        let min = s / 60;
+        let hours = min / 60;
+
+        if hours > 0 {
+            write!(f, "{hours}:{:0>2}:", min % 60)?;
+        } else {
+            write!(f, "{min}:")?;
+        }
+        write!(f, "{:0>2}.{:0>3}({})", s % 60, ms % 1000, self.frames)
+    }
+}
+
+impl FromStr for TasTime {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        if let Some((time, frames)) = s.strip_suffix(')').and_then(|s| s.rsplit_once('(')) {
+            let frames = frames.parse().map_err(|_| ())?;
+            // validate the time part even though the frames are authoritative
+            parse_millis(time).ok_or(())?;
+            return Ok(TasTime { frames });
+        }
+
+        let millis = parse_millis(s).ok_or(())?;
+        Ok(TasTime {
+            frames: (millis / 17) as u32,
+        })
+    }
+}
+
+fn parse_millis(time: &str) -> Option<u64> {
+    let (rest, fraction) = time.split_once('.')?;
+    if fraction.len() != 3 {
+        return None;
+    }
+    let ms: u64 = fraction.parse().ok()?;
+
+    let mut seconds = 0u64;
+    let mut n_parts = 0;
+    for part in rest.split(':') {
+        if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        seconds = seconds * 60 + part.parse::<u64>().ok()?;
+        n_parts += 1;
+    }
+    if !(2..=3).contains(&n_parts) {
+        return None;
+    }
+
+    Some(seconds * 1000 + ms)
+}
+
+impl TasFile {
+    pub fn parse(text: &str) -> Result<TasFile> {
+        let lines = text
+            .lines()
+            .enumerate()
+            .map(|(i, line)| parse_line(line).map_err(|kind| Error { line: i + 1, kind }))
+            .collect::<Result<_>>()?;
+        Ok(TasFile { lines })
+    }
+
+    /// Like [`TasFile::parse`], but keeps lines that fail to parse as [`Line::Unparsed`] and returns their errors
+    pub fn parse_lenient(text: &str) -> (TasFile, Vec<Error>) {
+        let mut errors = Vec::new();
+        let lines = text
+            .lines()
+            .enumerate()
+            .map(|(i, line)| {
+                parse_line(line).unwrap_or_else(|kind| {
+                    errors.push(Error { line: i + 1, kind });
+                    Line::Unparsed(line.to_owned())
+                })
+            })
+            .collect();
+        (TasFile { lines }, errors)
+    }
+
+    pub fn read(path: impl AsRef<std::path::Path>) -> anyhow::Result<TasFile> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        let file = TasFile::parse(&text)
+            .map_err(|e| anyhow::anyhow!("failed to parse {}: {e}", path.display()))?;
+        Ok(file)
+    }
+
+    pub fn inputs(&self) -> impl Iterator<Item = &Input> {
+        self.lines.iter().filter_map(|line| match line {
+            Line::Input(input) => Some(input),
+            _ => None,
+        })
+    }
+
+    pub fn commands(&self) -> impl Iterator<Item = &CommandKind> {
+        self.lines.iter().filter_map(|line| match line {
+            Line::Command(command) => Some(&command.kind),
+            _ => None,
+        })
+    }
+
+    /// Total number of input frames, with `Repeat` blocks expanded. `Read` commands are not followed.
+    pub fn input_frames(&self) -> u32 {
+        count_frames(&self.lines)
+    }
+
+    /// Input frames after the last `console load`, not counting the first input following it, which is spent loading.
+    ///
+    /// This is what the chapter time of a file starting with `console load` should add up to.
+    /// Returns `None` if the file does not load a map.
+    pub fn frames_since_load(&self) -> Option<u32> {
+        let load = self.lines.iter().rposition(
+            |line| matches!(line, Line::Command(command) if command.kind.console_load().is_some()),
+        )?;
+        let after_load = &self.lines[load + 1..];
+        let first_input = after_load
+            .iter()
+            .position(|line| matches!(line, Line::Input(_)))
+            .map_or(after_load.len(), |i| i + 1);
+        Some(count_frames(&after_load[first_input..]))
+    }
+
+    /// Line index of the `#label`
+    pub fn find_label(&self, label: &str) -> Option<usize> {
+        self.lines
+            .iter()
+            .position(|line| matches!(line, Line::Label(name) if name.trim() == label.trim()))
+    }
+
+    /// The last `#0:02.873(169)` comment in the file
+    pub fn time_comment(&self) -> Option<TasTime> {
+        self.lines.iter().rev().find_map(|line| match line {
+            Line::TimeComment(time) => Some(*time),
+            _ => None,
+        })
+    }
+
+    /// The argument of the last `ChapterTime:` command
+    pub fn chapter_time(&self) -> Option<TasTime> {
+        self.commands()
+            .filter_map(|command| match command {
+                CommandKind::ChapterTime(time) => *time,
+                _ => None,
+            })
+            .last()
+    }
+
+    pub fn has_breakpoint(&self) -> bool {
+        self.lines
+            .iter()
+            .any(|line| matches!(line, Line::Breakpoint(_)))
+    }
+
+    pub fn push(&mut self, line: impl Into<Line>) -> &mut Self {
+        self.lines.push(line.into());
+        self
+    }
+}
+
+/// Sums up input frames, expanding `Repeat` blocks.
+fn count_frames(lines: &[Line]) -> u32 {
+    // (frames in the current block, repeat count of the current block)
+    let mut stack = vec![(0u32, 1u32)];
+
+    for line in lines {
+        match line {
+            Line::Input(input) => {
+                let block = &mut stack.last_mut().unwrap().0;
+                *block = block.saturating_add(input.frames);
+            }
+            Line::Command(Command { kind, .. }) => match kind {
+                CommandKind::Repeat(count) => stack.push((0, *count)),
+                CommandKind::EndRepeat if stack.len() > 1 => {
+                    let (frames, count) = stack.pop().unwrap();
+                    let block = &mut stack.last_mut().unwrap().0;
+                    *block = block.saturating_add(frames.saturating_mul(count));
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    // unclosed repeats are repeated until the end of the file
+    while stack.len() > 1 {
+        let (frames, count) = stack.pop().unwrap();
+        let block = &mut stack.last_mut().unwrap().0;
+        *block = block.saturating_add(frames.saturating_mul(count));
+    }
+    stack[0].0
+}
+
+impl From<Input> for Line {
+    fn from(input: Input) -> Self {
+        Line::Input(input)
+    }
+}
+impl From<Command> for Line {
+    fn from(command: Command) -> Self {
+        Line::Command(command)
+    }
+}
+impl From<CommandKind> for Line {
+    fn from(command: CommandKind) -> Self {
+        Line::Command(Command::new(command))
+    }
+}
+impl From<Breakpoint> for Line {
+    fn from(breakpoint: Breakpoint) -> Self {
+        Line::Breakpoint(breakpoint)
+    }
+}
+
+impl FromStr for TasFile {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TasFile::parse(s)
+    }
+}
+
+fn parse_line(line: &str) -> Result<Line, ErrorKind> {
+    let trimmed = line.trim();
+
+    if trimmed.is_empty() {
+        return Ok(Line::Empty);
+    }
+    if let Some(rest) = trimmed.strip_prefix("***") {
+        return Ok(Line::Breakpoint(parse_breakpoint(rest)?));
+    }
+    if let Some(comment) = trimmed.strip_prefix('#') {
+        if let Ok(time) = comment.parse::<TasTime>() {
+            return Ok(Line::TimeComment(time));
+        }
+        if comment.is_empty() || comment.starts_with([' ', '\t', '#']) {
+            return Ok(Line::Comment(comment.to_owned()));
+        }
+        return Ok(Line::Label(comment.to_owned()));
+    }
+    if trimmed.starts_with(|c: char| c.is_ascii_digit()) {
+        return parse_input(trimmed).map(Line::Input);
+    }
+
+    parse_command(trimmed).map(Line::Command)
+}
+
+fn parse_breakpoint(rest: &str) -> Result<Breakpoint, ErrorKind> {
+    let mut breakpoint = Breakpoint::default();
+
+    let rest = rest.trim();
+    let flags_end = rest
+        .find(|c| !matches!(c, 'S' | 's' | '!'))
+        .unwrap_or(rest.len());
+    let (flags, rest) = rest.split_at(flags_end);
+    breakpoint.save_state = flags.contains(['S', 's']);
+    breakpoint.force = flags.contains('!');
+    breakpoint.flags = flags.to_owned();
+
+    let rest = rest.trim();
+    if !rest.is_empty() {
+        let speed = rest
+            .parse()
+            .map_err(|_| ErrorKind::InvalidFrameCount(rest.to_owned()))?;
+        breakpoint.speed = Some(speed);
+    }
+
+    Ok(breakpoint)
+}
+
+fn parse_input(line: &str) -> Result<Input, ErrorKind> {
+    let mut fields = line.split(',').map(str::trim);
+    let frames = fields.next().unwrap();
+    let frames = frames
+        .parse()
+        .map_err(|_| ErrorKind::InvalidFrameCount(frames.to_owned()))?;
+
+    let mut input = Input::new(frames, Vec::new());
+
+    for field in fields {
+        if field.is_empty() {
+            continue;
+        }
+
+        if input.actions.contains(&Action::Feather) && input.magnitude.is_none() {
+            if let Ok(value) = field.parse::<f32>() {
+                match input.angle {
+                    None => input.angle = Some(value),
+                    Some(_) => input.magnitude = Some(value),
+                }
+                continue;
+            }
+        }
+
+        for c in field.chars() {
+            let action =
+                Action::from_char(c).ok_or_else(|| ErrorKind::InvalidAction(field.to_owned()))?;
+            input.actions.push(action);
+        }
+    }
+
+    Ok(input)
+}
+
+fn parse_command(line: &str) -> Result<Command, ErrorKind> {
+    let name_end = line.find([',', ' ', '\t', '|']).unwrap_or(line.len());
+    let (name, rest) = line.split_at(name_end);
+    let name = name.trim_end_matches(':');
+
+    let separator = if rest.starts_with(", ") {
+        Separator::CommaSpace
+    } else if rest.starts_with(',') {
+        Separator::Comma
+    } else {
+        Separator::Space
+    };
+
+    let args: Vec<String> = rest
+        .split([',', '|'])
+        .flat_map(|arg| match separator {
+            Separator::Space => arg.split_whitespace().collect::<Vec<_>>(),
+            _ => vec![arg.trim()],
+        })
+        .filter(|arg| !arg.is_empty())
+        .map(ToOwned::to_owned)
+        .collect();
+
+    let kind = if name.eq_ignore_ascii_case("read") {
+        let mut args = args.into_iter();
+        CommandKind::Read {
+            file: args
+                .next()
+                .ok_or(ErrorKind::MissingCommandArgument("Read"))?,
+            start: args.next(),
+            end: args.next(),
+        }
+    } else if name.eq_ignore_ascii_case("console") {
+        CommandKind::Console(args)
+    } else if name.eq_ignore_ascii_case("recordcount") {
+        let count = args
+            .first()
+            .map(|arg| {
+                arg.parse().map_err(|_| ErrorKind::InvalidCommandArgument {
+                    command: "RecordCount",
+                    argument: arg.clone(),
+                })
+            })
+            .transpose()?;
+        CommandKind::RecordCount(count)
+    } else if name.eq_ignore_ascii_case("chaptertime") {
+        let time = args
+            .first()
+            .map(|arg| arg.parse().map_err(|_| ErrorKind::InvalidTime(arg.clone())))
+            .transpose()?;
+        CommandKind::ChapterTime(time)
+    } else if name.eq_ignore_ascii_case("enforcelegal") {
+        CommandKind::EnforceLegal
+    } else if name.eq_ignore_ascii_case("set") {
+        let mut args = args.into_iter();
+        let key = args
+            .next()
+            .ok_or(ErrorKind::MissingCommandArgument("Set"))?;
+        let value = args.collect::<Vec<_>>().join(separator.as_str());
+        CommandKind::Set { key, value }
+    } else if name.eq_ignore_ascii_case("repeat") {
+        let count = args
+            .first()
+            .ok_or(ErrorKind::MissingCommandArgument("Repeat"))?;
+        let count = count
+            .parse()
+            .map_err(|_| ErrorKind::InvalidCommandArgument {
+                command: "Repeat",
+                argument: count.clone(),
+            })?;
+        CommandKind::Repeat(count)
+    } else if name.eq_ignore_ascii_case("endrepeat") {
+        CommandKind::EndRepeat
+    } else {
+        CommandKind::Other {
+            name: name.to_owned(),
+            args,
+        }
+    };
+
+    Ok(Command { kind, separator })
+}
+
+impl std::fmt::Display for TasFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{line}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Line {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Line::Empty => Ok(()),
+            Line::Input(input) => input.fmt(f),
+            Line::Command(command) => command.fmt(f),
+            Line::Label(label) => write!(f, "#{label}"),
+            Line::Comment(comment) => write!(f, "#{comment}"),
+            Line::TimeComment(time) => write!(f, "#{time}"),
+            Line::Breakpoint(breakpoint) => breakpoint.fmt(f),
+            Line::Unparsed(line) => f.write_str(line),
+        }
+    }
+}
+
+impl std::fmt::Display for Input {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:>4}", self.frames)?;
+        for action in &self.actions {
+            write!(f, ",{}", action.as_char())?;
+        }
+        if let Some(angle) = self.angle {
+            write!(f, ",{angle}")?;
+        }
+        if let Some(magnitude) = self.magnitude {
+            write!(f, ",{magnitude}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("***")?;
+        let flags_match = self.flags.contains(['S', 's']) == self.save_state
+            && self.flags.contains('!') == self.force;
+        if flags_match {
+            f.write_str(&self.flags)?;
+        } else {
+            if self.save_state {
+                f.write_char('S')?;
+            }
+            if self.force {
+                f.write_char('!')?;
+            }
+        }
+        if let Some(speed) = self.speed {
+            write!(f, "{speed}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sep = self.separator.as_str();
+        match &self.kind {
+            CommandKind::Read { file, start, end } => {
+                write!(f, "Read{sep}{file}")?;
+                for arg in [start, end].into_iter().flatten() {
+                    write!(f, "{sep}{arg}")?;
+                }
+                Ok(())
+            }
+            CommandKind::Console(args) => {
+                f.write_str("console")?;
+                for arg in args {
+                    write!(f, "{sep}{arg}")?;
+                }
+                Ok(())
+            }
+            CommandKind::RecordCount(None) => f.write_str("RecordCount:"),
+            CommandKind::RecordCount(Some(count)) => write!(f, "RecordCount: {count}"),
+            CommandKind::ChapterTime(None) => f.write_str("ChapterTime:"),
+            CommandKind::ChapterTime(Some(time)) => write!(f, "ChapterTime: {time}"),
+            CommandKind::EnforceLegal => f.write_str("EnforceLegal"),
+            CommandKind::Set { key, value } => write!(f, "Set{sep}{key}{sep}{value}"),
+            CommandKind::Repeat(count) => write!(f, "Repeat{sep}{count}"),
+            CommandKind::EndRepeat => f.write_str("EndRepeat"),
+            CommandKind::Other { name, args } => {
+                f.write_str(name)?;
+                for arg in args {
+                    write!(f, "{sep}{arg}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[track_caller]
+    fn assert_roundtrip(text: &str) {
+        let tas = TasFile::parse(text).unwrap();
+        assert_eq!(tas.to_string(), text);
+        assert_eq!(TasFile::parse(&tas.to_string()).unwrap(), tas);
+    }
+
+    #[test]
+    fn roundtrip_inputs() {
+        assert_roundtrip("   1\n  14,R,J\n   5,L,U,X\n 120\n");
+        assert_roundtrip("  10,F,90\n   3,F,45.5,0.8\n");
+    }
+
+    #[test]
+    fn roundtrip_commands() {
+        assert_roundtrip("RecordCount: 1\nconsole load Celeste/1-ForsakenCity\nEnforceLegal\n");
+        assert_roundtrip("ChapterTime: 0:02.873(169)\nChapterTime:\n");
+        assert_roundtrip("Read,1-Start,Start,lvl_2\nRead, other file\nSet,Player.Speed,0\n");
+        assert_roundtrip("AnalogMode,Circle\nStunPause\n");
+    }
+
+    #[test]
+    fn roundtrip_repeat() {
+        let text = "Repeat 5\n   3,R\n   2,J\nEndRepeat\n";
+        assert_roundtrip(text);
+        assert_eq!(TasFile::parse(text).unwrap().input_frames(), 25);
+    }
+
+    #[test]
+    fn roundtrip_labels_and_comments() {
+        let text = "#Start\n\n#lvl_1\n# some comment\n#0:02.873(169)\n";
+        assert_roundtrip(text);
+
+        let tas = TasFile::parse(text).unwrap();
+        assert_eq!(tas.find_label("lvl_1"), Some(2));
+        assert_eq!(tas.time_comment(), Some(TasTime::from_frames(169)));
+    }
+
+    #[test]
+    fn roundtrip_breakpoints() {
+        for breakpoint in [
+            "***", "***S", "***s", "***!", "***S!", "***!S", "***500", "***!S0.5",
+        ] {
+            assert_roundtrip(&format!("{breakpoint}\n"));
+        }
+
+        let Line::Breakpoint(breakpoint) = parse_line("***!s10").unwrap() else {
+            panic!("not a breakpoint");
+        };
+        assert!(breakpoint.save_state && breakpoint.force);
+        assert_eq!(breakpoint.speed, Some(10.0));
+    }
+
+    #[test]
+    fn breakpoint_flags_follow_fields() {
+        let mut breakpoint = Breakpoint {
+            speed: Some(500.0),
+            ..Default::default()
+        };
+        assert_eq!(breakpoint.to_string(), "***500");
+
+        breakpoint.save_state = true;
+        assert_eq!(breakpoint.to_string(), "***S500");
+    }
+
+    #[test]
+    fn parse_lenient_keeps_invalid_lines() {
+        let text = "  14,R,Y\nRepeat many\n#0:01.020(60)\n";
+        assert!(TasFile::parse(text).is_err());
+
+        let (tas, errors) = TasFile::parse_lenient(text);
+        assert_eq!(errors.iter().map(|e| e.line).collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(tas.time_comment(), Some(TasTime::from_frames(60)));
+        assert_eq!(tas.to_string(), text);
+    }
+
+    #[test]
+    fn input_frames_saturate() {
+        let tas = TasFile::parse("Repeat 4000000000\n  10\nEndRepeat\n  5\n").unwrap();
+        assert_eq!(tas.input_frames(), u32::MAX);
+
+        let tas = TasFile::parse("  3\nRepeat 2\n  10\nRepeat 3\n  1\n").unwrap();
+        assert_eq!(tas.input_frames(), 3 + 2 * (10 + 3));
+    }
+}
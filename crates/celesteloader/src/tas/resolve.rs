@@ -0,0 +1,337 @@
+//! Static expansion of `Read` commands.
+//!
+//! `Read,file,start,end` is resolved like CelesteTAS does it: `file` is relative to the directory of the reading file,
+//! may omit the `.tas` extension or only be a prefix of the file name.
+//! `start` and `end` are either labels or 1-based line numbers. Line numbers are included, the label lines themselves are not.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use super::{CommandKind, Line, TasFile};
+
+#[derive(Debug)]
+pub enum Error {
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        error: super::Error,
+    },
+    MissingFile {
+        from: PathBuf,
+        file: String,
+    },
+    MissingLabel {
+        path: PathBuf,
+        label: String,
+    },
+    /// The files in the cycle, starting and ending with the same file
+    Cycle(Vec<PathBuf>),
+}
+
+impl std::error::Error for Error {}
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io { path, error } => write!(f, "could not read {}: {error}", path.display()),
+            Error::Parse { path, error } => {
+                write!(f, "failed to parse {}: {error}", path.display())
+            }
+            Error::MissingFile { from, file } => {
+                write!(f, "{} reads missing file `{file}`", from.display())
+            }
+            Error::MissingLabel { path, label } => {
+                write!(f, "label `{label}` not found in {}", path.display())
+            }
+            Error::Cycle(files) => {
+                f.write_str("cyclic Read: ")?;
+                for (i, file) in files.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(" -> ")?;
+                    }
+                    write!(f, "{}", file.display())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Expands `Read` commands, caching every file that gets parsed along the way.
+#[derive(Default)]
+pub struct Resolver {
+    files: HashMap<PathBuf, TasFile>,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Resolver::default()
+    }
+
+    /// Parses the file at `path`, returning the cached version if it was parsed before.
+    pub fn load(&mut self, path: &Path) -> Result<&TasFile, Error> {
+        let path = normalize(path);
+        if !self.files.contains_key(&path) {
+            let text = std::fs::read_to_string(&path).map_err(|error| Error::Io {
+                path: path.clone(),
+                error,
+            })?;
+            let file = TasFile::parse(&text).map_err(|error| Error::Parse {
+                path: path.clone(),
+                error,
+            })?;
+            self.files.insert(path.clone(), file);
+        }
+        Ok(&self.files[&path])
+    }
+
+    /// Returns the contents of `path` with all `Read` commands replaced by the lines they refer to.
+    pub fn expand(&mut self, path: &Path) -> Result<TasFile, Error> {
+        let mut lines = Vec::new();
+        let mut stack = Vec::new();
+        self.expand_into(
+            &normalize(path),
+            None,
+            &mut stack,
+            &mut lines,
+            &mut Vec::new(),
+        )?;
+        Ok(TasFile { lines })
+    }
+
+    /// Files read by `path`, directly or through other files, not including `path` itself.
+    pub fn dependencies(&mut self, path: &Path) -> Result<Vec<PathBuf>, Error> {
+        let path = normalize(path);
+        let mut read = Vec::new();
+        self.expand_into(&path, None, &mut Vec::new(), &mut Vec::new(), &mut read)?;
+        read.sort();
+        read.dedup();
+        read.retain(|file| *file != path);
+        Ok(read)
+    }
+
+    /// Input frames of `path`, including everything it reads.
+    pub fn input_frames(&mut self, path: &Path) -> Result<u32, Error> {
+        Ok(self.expand(path)?.input_frames())
+    }
+
+    fn expand_into(
+        &mut self,
+        path: &Path,
+        range: Option<(Option<&str>, Option<&str>)>,
+        stack: &mut Vec<PathBuf>,
+        out: &mut Vec<Line>,
+        read: &mut Vec<PathBuf>,
+    ) -> Result<(), Error> {
+        if let Some(start) = stack.iter().position(|p| p == path) {
+            let mut cycle = stack[start..].to_vec();
+            cycle.push(path.to_owned());
+            return Err(Error::Cycle(cycle));
+        }
+
+        let file = self.load(path)?;
+        let (start, end) = match range {
+            Some((start, end)) => (
+                start
+                    .map(|start| line_index(file, path, start, true))
+                    .transpose()?
+                    .unwrap_or(0),
+                end.map(|end| line_index(file, path, end, false))
+                    .transpose()?
+                    .unwrap_or(file.lines.len()),
+            ),
+            None => (0, file.lines.len()),
+        };
+        let lines = file.lines[start.min(end)..end].to_vec();
+
+        stack.push(path.to_owned());
+        for line in lines {
+            match &line {
+                Line::Command(command) => match &command.kind {
+                    CommandKind::Read { file, start, end } => {
+                        let dir = path.parent().unwrap_or(Path::new(""));
+                        let target =
+                            find_read_target(dir, file).ok_or_else(|| Error::MissingFile {
+                                from: path.to_owned(),
+                                file: file.clone(),
+                            })?;
+                        read.push(target.clone());
+                        self.expand_into(
+                            &target,
+                            Some((start.as_deref(), end.as_deref())),
+                            stack,
+                            out,
+                            read,
+                        )?;
+                    }
+                    _ => out.push(line),
+                },
+                _ => out.push(line),
+            }
+        }
+        stack.pop();
+
+        Ok(())
+    }
+}
+
+/// Converts a `Read` bound to a line index. Line numbers are inclusive, labels exclusive.
+fn line_index(file: &TasFile, path: &Path, bound: &str, is_start: bool) -> Result<usize, Error> {
+    if let Ok(line) = bound.parse::<usize>() {
+        let index = if is_start {
+            line.saturating_sub(1)
+        } else {
+            line
+        };
+        return Ok(index.min(file.lines.len()));
+    }
+    let label = file.find_label(bound).ok_or_else(|| Error::MissingLabel {
+        path: path.to_owned(),
+        label: bound.to_owned(),
+    })?;
+    Ok(if is_start { label + 1 } else { label })
+}
+
+fn find_read_target(dir: &Path, file: &str) -> Option<PathBuf> {
+    let candidate = dir.join(file);
+    if candidate.is_file() {
+        return Some(normalize(&candidate));
+    }
+    let with_extension = dir.join(format!("{file}.tas"));
+    if with_extension.is_file() {
+        return Some(normalize(&with_extension));
+    }
+
+    // `Read,1A` may refer to `1A - Forsaken City.tas`
+    let candidate_dir = candidate.parent()?;
+    let prefix = candidate.file_name()?.to_str()?;
+    let mut matches = std::fs::read_dir(candidate_dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension().is_some_and(|ext| ext == "tas")
+                && path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with(prefix))
+        })
+        .collect::<Vec<_>>();
+    matches.sort();
+    matches.into_iter().next().map(|path| normalize(&path))
+}
+
+fn normalize(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_files(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, content) in files {
+            std::fs::write(dir.path().join(name), content).unwrap();
+        }
+        dir
+    }
+
+    fn lobby() -> tempfile::TempDir {
+        write_files(&[
+            ("main.tas", "Read,1A,start,end\n   5\nRead,lines.tas,2,3\n"),
+            (
+                "1A - Forsaken City.tas",
+                "   1\n#start\n   2,R\n   3,J\n#end\n 100\n",
+            ),
+            ("1A - Other.tas", "   1000\n"),
+            ("lines.tas", "  10\n  20\n  30\n  40\n"),
+            ("nested.tas", "Read,main\n   1\n"),
+        ])
+    }
+
+    #[test]
+    fn read_ranges() {
+        let dir = lobby();
+        let mut resolver = Resolver::new();
+
+        let expanded = resolver.expand(&dir.path().join("main.tas")).unwrap();
+        assert_eq!(expanded.to_string(), "   2,R\n   3,J\n   5\n  20\n  30\n");
+        assert_eq!(expanded.input_frames(), 2 + 3 + 5 + 20 + 30);
+    }
+
+    #[test]
+    fn read_target_prefix() {
+        let dir = lobby();
+        let find = |file| {
+            find_read_target(dir.path(), file).map(|path| path.file_name().unwrap().to_owned())
+        };
+
+        assert_eq!(find("lines").unwrap(), "lines.tas");
+        assert_eq!(find("lines.tas").unwrap(), "lines.tas");
+        // the first match in sorted order
+        assert_eq!(find("1A").unwrap(), "1A - Forsaken City.tas");
+        assert_eq!(find("1A - O").unwrap(), "1A - Other.tas");
+        assert_eq!(find("2A"), None);
+    }
+
+    #[test]
+    fn nested_reads() {
+        let dir = lobby();
+        let mut resolver = Resolver::new();
+        let nested = dir.path().join("nested.tas");
+
+        assert_eq!(resolver.input_frames(&nested).unwrap(), 60 + 1);
+
+        let dependencies = resolver.dependencies(&nested).unwrap();
+        let names = dependencies
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["1A - Forsaken City.tas", "lines.tas", "main.tas"]);
+    }
+
+    #[test]
+    fn cycle() {
+        let dir = write_files(&[("a.tas", "Read,b\n"), ("b.tas", "   1\nRead,a\n")]);
+        let mut resolver = Resolver::new();
+
+        let Err(Error::Cycle(files)) = resolver.expand(&dir.path().join("a.tas")) else {
+            panic!("expected a cycle");
+        };
+        let names = files
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a.tas", "b.tas", "a.tas"]);
+    }
+
+    #[test]
+    fn missing_files() {
+        let dir = write_files(&[
+            ("a.tas", "   1\nRead,missing\n"),
+            ("b.tas", "Read,a,nolabel\n"),
+        ]);
+        let mut resolver = Resolver::new();
+
+        let Err(Error::MissingFile { from, file }) = resolver.expand(&dir.path().join("a.tas"))
+        else {
+            panic!("expected a missing file");
+        };
+        assert_eq!(from.file_name().unwrap(), "a.tas");
+        assert_eq!(file, "missing");
+
+        assert!(matches!(
+            resolver.expand(&dir.path().join("b.tas")),
+            Err(Error::MissingLabel { label, .. }) if label == "nolabel"
+        ));
+        assert!(matches!(
+            resolver.expand(&dir.path().join("c.tas")),
+            Err(Error::Io { .. })
+        ));
+    }
+}
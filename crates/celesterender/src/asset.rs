@@ -2,32 +2,49 @@ use anyhow::anyhow;
 use anyhow::Context;
 use anyhow::Result;
 use celesteloader::{archive::ModArchive, atlas::Sprite, CelesteInstallation};
+use elsa::sync::FrozenMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
 use std::{fs::File, io::BufReader};
 use tiny_skia::Pixmap;
 
 use crate::CelesteRenderData;
 
+/// Number of separately locked parts of the cache, so rooms rendering in parallel rarely wait on each other
+const CACHE_SHARDS: usize = 16;
+
+/// Sprites which aren't in the gameplay atlas, loaded once and shared between all threads rendering rooms
 pub struct AssetDb<L> {
-    pub(crate) lookup_asset: L,
-    pub(crate) lookup_cache: elsa::FrozenMap<String, Box<Pixmap>>,
+    pub(crate) lookup_asset: Mutex<L>,
+    pub(crate) lookup_cache: [FrozenMap<String, Box<Pixmap>>; CACHE_SHARDS],
 
-    from_cache: usize,
-    not_cached: usize,
+    from_cache: AtomicUsize,
+    not_cached: AtomicUsize,
 }
 impl<L> AssetDb<L> {
     pub fn new(lookup: L) -> Self {
         AssetDb {
-            lookup_asset: lookup,
+            lookup_asset: Mutex::new(lookup),
             lookup_cache: Default::default(),
 
-            from_cache: 0,
-            not_cached: 0,
+            from_cache: AtomicUsize::new(0),
+            not_cached: AtomicUsize::new(0),
         }
     }
 
     pub fn cache_stats(&self) -> (usize, usize) {
-        (self.from_cache, self.not_cached)
+        (
+            self.from_cache.load(Ordering::Relaxed),
+            self.not_cached.load(Ordering::Relaxed),
+        )
+    }
+
+    fn cache_shard(&self, path: &str) -> &FrozenMap<String, Box<Pixmap>> {
+        let mut hasher = DefaultHasher::new();
+        path.hash(&mut hasher);
+        &self.lookup_cache[hasher.finish() as usize % CACHE_SHARDS]
     }
 }
 impl AssetDb<NullLookup> {
@@ -44,12 +61,12 @@ impl<L: LookupAsset> AssetDb<L> {
     }*/
 
     pub fn has_cached(&self, val: &str) -> bool {
-        self.lookup_cache.get(val).is_some()
+        self.cache_shard(val).get(val).is_some()
     }
 
     #[cfg_attr(feature = "tracing_detailed", tracing::instrument(skip_all))]
     pub fn lookup_gameplay<'a: 'b, 'b>(
-        &'a mut self,
+        &'a self,
         cx: &'b CelesteRenderData,
         path: &str,
     ) -> Result<SpriteLocation<'b>> {
@@ -60,20 +77,28 @@ impl<L: LookupAsset> AssetDb<L> {
         if let Some(cached) = {
             #[cfg(feature = "tracing_detailed")]
             let _span = tracing::info_span!("lookup_cache").entered();
-            self.lookup_cache.get(path)
+            self.cache_shard(path).get(path)
         } {
-            self.from_cache += 1;
+            self.from_cache.fetch_add(1, Ordering::Relaxed);
             return Ok(SpriteLocation::Raw(cached));
         }
 
         {
             #[cfg(feature = "tracing_detailed")]
             let _span = tracing::info_span!("lookup_asset").entered();
-            if let Some(sprite) = self.lookup_asset.lookup_gameplay_png(path)? {
-                self.not_cached += 1;
+            let sprite = self
+                .lookup_asset
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .lookup_gameplay_png(path)?;
+            if let Some(sprite) = sprite {
+                self.not_cached.fetch_add(1, Ordering::Relaxed);
+                // decoded outside of the lock, if another thread was faster its pixmap is kept
                 let pixmap = Pixmap::decode_png(&sprite)
                     .with_context(|| anyhow!("failed to decode {} as png", path))?;
-                let a = self.lookup_cache.insert(path.to_owned(), Box::new(pixmap));
+                let a = self
+                    .cache_shard(path)
+                    .insert(path.to_owned(), Box::new(pixmap));
                 return Ok(SpriteLocation::Raw(a));
             }
         }
@@ -134,7 +159,7 @@ impl SpriteLocation<'_> {
     }
 }
 
-pub trait LookupAsset {
+pub trait LookupAsset: Send {
     fn lookup_exact(&mut self, path: &str) -> Result<Option<(Vec<u8>, Option<&mut ModArchive>)>>;
 
     fn lookup_gameplay_png(&mut self, path: &str) -> Result<Option<Vec<u8>>>;
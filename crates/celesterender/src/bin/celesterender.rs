@@ -19,7 +19,7 @@ use celesterender::{
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 
 fn render_map<L: LookupAsset>(
-    asset_db: &mut AssetDb<L>,
+    asset_db: &AssetDb<L>,
     zip: &mut ModArchive<BufReader<File>>,
     render_data: &mut CelesteRenderData,
     map: &str,
@@ -64,7 +64,7 @@ fn main() -> Result<()> {
 fn render_modded_maps() -> Result<()> {
     let celeste = CelesteInstallation::detect()?;
 
-    let mut asset_db = AssetDb::new(ModLookup::in_folder(Path::new("downloads"), &celeste)?);
+    let asset_db = AssetDb::new(ModLookup::in_folder(Path::new("downloads"), &celeste)?);
 
     let mut render_data = CelesteRenderData::base(&celeste)?;
     let vanilla_fgtiles_xml = celeste.read_to_string("Content/Graphics/ForegroundTiles.xml")?;
@@ -118,7 +118,7 @@ fn render_modded_maps() -> Result<()> {
             }
 
             let res = render_map(
-                &mut asset_db,
+                &asset_db,
                 &mut zip,
                 &mut render_data,
                 map_path,
@@ -185,15 +185,18 @@ fn render_vanilla_maps(celeste: &CelesteInstallation) -> Result<()> {
                 return Ok(());
             }
 
+            let sid = format!("Celeste/{}", map.package);
             let start = Instant::now();
             let mut result = celesterender::render(
                 &render_data,
-                &mut AssetDb::empty(),
+                &AssetDb::empty(),
                 map,
                 RenderMapSettings {
-                    layer: Layer::ALL,
+                    layer: Layer::DEFAULT,
                     include_room: &|room| room.name.starts_with(""),
-                    status_update: &|_,_|{}
+                    status_update: &|_,_|{},
+                    sid: Some(&sid),
+                    ..Default::default()
                 },
             )?;
             let encode_start = Instant::now();
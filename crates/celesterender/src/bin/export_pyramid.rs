@@ -0,0 +1,85 @@
+//! Exports a map as a zoomable tile pyramid with an `index.html` viewer, which works without network access.
+//!
+//! Usage: `export_pyramid [--output dir] [--triggers] <map bin, e.g. Celeste/1-ForsakenCity>`
+
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use celesteloader::CelesteInstallation;
+use celesterender::{
+    asset::{AssetDb, ModLookup},
+    CelesteRenderData, Layer, RenderMapSettings,
+};
+
+struct Args {
+    map_bin: String,
+    output: PathBuf,
+    triggers: bool,
+}
+
+fn parse_args() -> Result<Args> {
+    use lexopt::prelude::*;
+
+    let mut map_bin = None;
+    let mut output = PathBuf::from("out/pyramid");
+    let mut triggers = false;
+
+    let mut parser = lexopt::Parser::from_env();
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Long("output") | Short('o') => output = parser.value()?.parse()?,
+            Long("triggers") => triggers = true,
+            Long("help") | Short('h') => {
+                println!("Usage: export_pyramid [--output dir] [--triggers] <map bin>");
+                std::process::exit(0);
+            }
+            Value(val) if map_bin.is_none() => map_bin = Some(val.string()?),
+            _ => return Err(arg.unexpected().into()),
+        }
+    }
+
+    Ok(Args {
+        map_bin: map_bin.ok_or_else(|| anyhow!("missing map bin"))?,
+        output,
+        triggers,
+    })
+}
+
+fn main() -> Result<()> {
+    let args = parse_args()?;
+
+    let celeste = CelesteInstallation::detect()?;
+    let mut render_data = CelesteRenderData::base(&celeste)?;
+    let asset_db = AssetDb::new(ModLookup::all_mods(&celeste)?);
+
+    let map = celesterender::load_map_bin(&celeste, &mut render_data, &args.map_bin)?;
+
+    let layer = match args.triggers {
+        true => Layer::DEFAULT | Layer::TRIGGERS,
+        false => Layer::DEFAULT,
+    };
+    let result = celesterender::pyramid::export_pyramid(
+        &render_data,
+        &asset_db,
+        &map,
+        RenderMapSettings::default()
+            .layer(layer)
+            .sid(&args.map_bin)
+            .status_update(&|i, n| eprint!("\rRendered {i}/{n} rooms")),
+        &args.output,
+        png::Compression::Fast,
+    )?;
+    eprintln!();
+
+    println!(
+        "Wrote {} tiles over {} zoom levels to {}",
+        result.tiles,
+        result.max_zoom + 1,
+        args.output.join("index.html").display()
+    );
+    if !result.unknown_entities.is_empty() {
+        println!("{} unknown entities", result.unknown_entities.len());
+    }
+
+    Ok(())
+}
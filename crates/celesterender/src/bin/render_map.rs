@@ -44,10 +44,10 @@ fn main() -> Result<()> {
     let map = archive.read_map(&map_path)?;
 
     let render_data = CelesteRenderData::for_map(&celeste, &mut archive, &map)?;
-    let mut asset_db = AssetDb::new(ModLookup::all_mods(&celeste)?);
+    let asset_db = AssetDb::new(ModLookup::all_mods(&celeste)?);
     let mut result = celesterender::render(
         &render_data,
-        &mut asset_db,
+        &asset_db,
         &map,
         RenderMapSettings {
             ..Default::default()
@@ -3,22 +3,25 @@
 use anyhow::Result;
 
 pub mod asset;
+pub mod pyramid;
 mod rendering;
 
 use asset::{AssetDb, LookupAsset};
 use celesteloader::{map::Map, CelesteInstallation};
 pub use png::Compression;
 pub use rendering::{
-    render, CelesteRenderData, Layer, MapTileset, RenderMapSettings, RenderResult,
+    render, render_layers, render_layers_to_dir, render_openraster, render_png_streaming,
+    render_rooms, render_rooms_to_dir, render_tiled, render_tiles_to_dir, CelesteRenderData, Layer,
+    LayeredRenderResult, ManifestBounds, ManifestRoom, MapTileset, RenderLayer, RenderMapSettings,
+    RenderResult, RenderTile, RoomImage, RoomManifest, TiledRenderResult,
 };
 
-pub fn render_map_bin(
+/// Finds the map by its SID, e.g. `Celeste/1-ForsakenCity`, and loads its tilesets into `render_data`
+pub fn load_map_bin(
     celeste: &CelesteInstallation,
     render_data: &mut CelesteRenderData,
-    asset_db: &mut AssetDb<impl LookupAsset>,
     map_bin: &str,
-    settings: RenderMapSettings<'_>,
-) -> Result<(RenderResult, Map)> {
+) -> Result<Map> {
     let (map, mut archive) = celeste.find_map_by_map_bin(map_bin)?;
 
     if let Some(archive) = &mut archive {
@@ -27,6 +30,22 @@ pub fn render_map_bin(
         render_data.map_tileset = MapTileset::vanilla(celeste)?;
     }
 
+    Ok(map)
+}
+
+pub fn render_map_bin(
+    celeste: &CelesteInstallation,
+    render_data: &mut CelesteRenderData,
+    asset_db: &AssetDb<impl LookupAsset>,
+    map_bin: &str,
+    settings: RenderMapSettings<'_>,
+) -> Result<(RenderResult, Map)> {
+    let map = load_map_bin(celeste, render_data, map_bin)?;
+
+    let settings = RenderMapSettings {
+        sid: settings.sid.or(Some(map_bin)),
+        ..settings
+    };
     let image = render(render_data, asset_db, &map, settings)?;
 
     Ok((image, map))
@@ -0,0 +1,236 @@
+//! Export of a map as a pyramid of tiles (`{z}/{x}/{y}.png`) like online maps use,
+//! together with an `index.html` to browse it without any network access.
+//!
+//! The deepest zoom level shows the map at its original size and every level above halves it,
+//! until the whole map fits into the single tile of level 0. Tiles without any rooms are skipped.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+    io::BufWriter,
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use celesteloader::map::{Bounds, Map, Room};
+use rayon::prelude::*;
+use serde::Serialize;
+use tiny_skia::{Color, FilterQuality, Pixmap, PixmapPaint, Transform};
+
+use crate::{
+    asset::{AssetDb, LookupAsset},
+    render_tiled,
+    rendering::encode_png,
+    CelesteRenderData, RenderMapSettings,
+};
+
+pub const TILE_SIZE: u32 = 256;
+
+const VIEWER: &str = include_str!("viewer.html");
+
+pub struct PyramidResult {
+    /// Level at which the map is shown at its original size
+    pub max_zoom: u32,
+    /// Number of tiles written over all levels
+    pub tiles: usize,
+    pub unknown_entities: BTreeMap<String, u32>,
+}
+
+/// Renders the map into `out` as a tile pyramid with an `index.html` viewer
+pub fn export_pyramid<L: LookupAsset>(
+    render_data: &CelesteRenderData,
+    asset_db: &AssetDb<L>,
+    map: &Map,
+    settings: RenderMapSettings,
+    out: &Path,
+    compression: png::Compression,
+) -> Result<PyramidResult> {
+    let rooms = map
+        .rooms
+        .iter()
+        .filter(|room| (settings.include_room)(room))
+        .collect::<Vec<_>>();
+    let bounds = rooms
+        .iter()
+        .map(|room| room.bounds)
+        .reduce(Bounds::join)
+        .context("No rooms to render")?;
+
+    let name = settings.sid.unwrap_or(&map.package);
+    let background = settings.background;
+    let max_zoom = max_zoom(bounds);
+    let mut tiles = BTreeSet::new();
+
+    let result = render_tiled(
+        render_data,
+        asset_db,
+        map,
+        settings,
+        (TILE_SIZE, TILE_SIZE),
+        |tile| {
+            let image = if tile.image.width() == TILE_SIZE && tile.image.height() == TILE_SIZE {
+                tile.image
+            } else {
+                let mut padded = background_tile(background);
+                padded.draw_pixmap(
+                    0,
+                    0,
+                    tile.image.as_ref(),
+                    &PixmapPaint::default(),
+                    Transform::identity(),
+                    None,
+                );
+                padded
+            };
+            write_tile(out, max_zoom, (tile.column, tile.row), image, compression)?;
+            tiles.insert((tile.column, tile.row));
+            Ok(())
+        },
+    )?;
+
+    let mut written = tiles.len();
+    for zoom in (0..max_zoom).rev() {
+        let parents = tiles
+            .iter()
+            .map(|&(x, y)| (x / 2, y / 2))
+            .collect::<BTreeSet<_>>();
+        parents.par_iter().try_for_each(|&(x, y)| -> Result<()> {
+            let mut image = background_tile(background);
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let child = (x * 2 + dx, y * 2 + dy);
+                if !tiles.contains(&child) {
+                    continue;
+                }
+                let path = tile_path(out, zoom + 1, child);
+                let child = Pixmap::load_png(&path)
+                    .with_context(|| format!("failed to read back {}", path.display()))?;
+                image.draw_pixmap(
+                    (dx * TILE_SIZE) as i32,
+                    (dy * TILE_SIZE) as i32,
+                    child.as_ref(),
+                    &PixmapPaint {
+                        quality: FilterQuality::Bilinear,
+                        ..Default::default()
+                    },
+                    Transform::from_scale(0.5, 0.5),
+                    None,
+                );
+            }
+            write_tile(out, zoom, (x, y), image, compression)
+        })?;
+
+        written += parents.len();
+        tiles = parents;
+    }
+
+    let viewer = ViewerData::new(name, &rooms, bounds, max_zoom);
+    let json = serde_json::to_string(&viewer)?.replace("</", "<\\/");
+    let html = VIEWER.replace("/*MAP_DATA*/null", &json);
+    std::fs::write(out.join("index.html"), html)
+        .with_context(|| format!("failed to write {}", out.join("index.html").display()))?;
+
+    Ok(PyramidResult {
+        max_zoom,
+        tiles: written,
+        unknown_entities: result.unknown_entities,
+    })
+}
+
+/// Smallest level at which the map fits into tiles at its original size
+fn max_zoom(bounds: Bounds) -> u32 {
+    let largest = bounds.size.0.max(bounds.size.1).div_ceil(TILE_SIZE).max(1);
+    largest.next_power_of_two().trailing_zeros()
+}
+
+fn background_tile(background: Color) -> Pixmap {
+    let mut pixmap = Pixmap::new(TILE_SIZE, TILE_SIZE).unwrap();
+    pixmap.fill(background);
+    pixmap
+}
+
+fn tile_path(out: &Path, zoom: u32, (x, y): (u32, u32)) -> std::path::PathBuf {
+    out.join(zoom.to_string())
+        .join(x.to_string())
+        .join(format!("{y}.png"))
+}
+
+fn write_tile(
+    out: &Path,
+    zoom: u32,
+    pos: (u32, u32),
+    image: Pixmap,
+    compression: png::Compression,
+) -> Result<()> {
+    let path = tile_path(out, zoom, pos);
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    let file =
+        File::create(&path).with_context(|| format!("failed to create {}", path.display()))?;
+    encode_png(image, BufWriter::new(file), compression)?;
+    Ok(())
+}
+
+/// Everything `viewer.html` needs to know about the map
+#[derive(Serialize)]
+struct ViewerData<'a> {
+    name: &'a str,
+    tile_size: u32,
+    max_zoom: u32,
+    /// Map position of the top left of tile `0/0/0`
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    rooms: Vec<ViewerRoom<'a>>,
+}
+
+#[derive(Serialize)]
+struct ViewerRoom<'a> {
+    name: &'a str,
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    entities: Vec<ViewerEntity<'a>>,
+}
+
+#[derive(Serialize)]
+struct ViewerEntity<'a> {
+    name: &'a str,
+    id: Option<i32>,
+    x: f32,
+    y: f32,
+}
+
+impl<'a> ViewerData<'a> {
+    fn new(name: &'a str, rooms: &[&'a Room], bounds: Bounds, max_zoom: u32) -> Self {
+        ViewerData {
+            name,
+            tile_size: TILE_SIZE,
+            max_zoom,
+            x: bounds.position.x,
+            y: bounds.position.y,
+            width: bounds.size.0,
+            height: bounds.size.1,
+            rooms: rooms
+                .iter()
+                .map(|room| ViewerRoom {
+                    name: &room.name,
+                    x: room.bounds.position.x,
+                    y: room.bounds.position.y,
+                    width: room.bounds.size.0,
+                    height: room.bounds.size.1,
+                    entities: room
+                        .entities
+                        .iter()
+                        .map(|entity| ViewerEntity {
+                            name: &entity.name,
+                            id: entity.id,
+                            x: room.bounds.position.x as f32 + entity.position.0,
+                            y: room.bounds.position.y as f32 + entity.position.1,
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
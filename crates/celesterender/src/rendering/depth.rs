@@ -0,0 +1,51 @@
+//! Depths from Celeste's `Depths` class (the ones used here).
+//! Things with a higher depth are drawn first, i.e. further in the back.
+
+pub const BG_TERRAIN: i32 = 10000;
+pub const BG_DECALS: i32 = 9000;
+pub const BELOW: i32 = 2000;
+pub const NPCS: i32 = 1000;
+pub const THEO_CRYSTAL: i32 = 100;
+pub const PLAYER: i32 = 0;
+pub const DUST: i32 = -50;
+pub const PICKUPS: i32 = -100;
+pub const ABOVE: i32 = -8500;
+pub const SOLIDS: i32 = -9000;
+pub const FG_TERRAIN: i32 = -10000;
+pub const FG_DECALS: i32 = -10500;
+pub const DREAM_BLOCKS: i32 = -11000;
+pub const FAKE_WALLS: i32 = -13000;
+pub const TOP: i32 = -1000000;
+
+/// Depth of vanilla entities which don't use the default of `0`
+pub fn vanilla_entity_depth(name: &str) -> Option<i32> {
+    Some(match name {
+        "spinner" => ABOVE,
+        "trackSpinner" | "rotateSpinner" | "dustCreature" => DUST,
+        "refill" | "strawberry" | "goldenBerry" | "infiniteStar" | "key" => PICKUPS,
+        "booster" | "towerviewer" => ABOVE,
+        "jumpThru" => -60,
+        "spikesUp" | "spikesDown" | "spikesLeft" | "spikesRight" => -1,
+        "triggerSpikesUp" | "triggerSpikesDown" | "triggerSpikesLeft" | "triggerSpikesRight" => -1,
+        "crumbleBlock" | "crushBlock" | "switchGate" | "floatySpaceBlock" | "goldenBlock"
+        | "templeCrackedBlock" | "ridgeGate" | "fallingBlock" | "dashBlock" => SOLIDS,
+        "zipMover" | "swapBlock" | "water" | "flutterbird" => -9999,
+        "moveBlock" => -1,
+        "dreamBlock" => DREAM_BLOCKS,
+        "fakeWall" | "exitBlock" => FAKE_WALLS,
+        "cassetteBlock" => -10,
+        "lamp" => 5,
+        "torch" | "wire" | "touchSwitch" => BELOW,
+        "npc" => NPCS,
+        "checkpoint" => 9990,
+        "summitcheckpoint" | "clutterCabinet" => 8999,
+        "whiteblock" => 8990,
+        "bonfire" | "floatingDebris" => -5,
+        "memorial" | "everest/memorial" => THEO_CRYSTAL,
+        "payphone" => 1,
+        "foregroundDebris" => -999900,
+        "badelineBoost" | "cassette" => TOP,
+        "wallBooster" => -1,
+        _ => return None,
+    })
+}
@@ -16,13 +16,16 @@ use crate::{
 
 use self::nine_patch::{nine_patch, NinePatchOptions};
 
-use super::{tileset::Matrix, RenderContext};
-
-fn _coordinate_seed(x: f32, y: f32) -> u32 {
-    // TODO make sure this is correct
-    let shl = |x: f32, y: f32| x.to_bits() << y.to_bits();
+use super::{
+    depth,
+    random::{coordinate_seed, DotnetRandom},
+    tileset::Matrix,
+    RenderContext,
+};
 
-    shl(x, f32::ceil(f32::log2(y.abs() + 1.0))) + y.abs() as u32
+/// Randomness of a single entity, so it doesn't depend on what else is rendered
+fn entity_random(entity: &Entity) -> DotnetRandom {
+    DotnetRandom::new(coordinate_seed(entity.position.0, entity.position.1))
 }
 
 fn to_tile(val: f32) -> i32 {
@@ -34,7 +37,7 @@ pub(super) fn render_entity<L: LookupAsset>(
     r: &mut RenderContext<L>,
     fgtiles: &Matrix<char>,
     cx: &CelesteRenderData,
-    asset_db: &mut AssetDb<L>,
+    asset_db: &AssetDb<L>,
     room: &Room,
     entity: &Entity,
 ) -> Result<bool> {
@@ -108,7 +111,7 @@ pub(super) fn render_entity<L: LookupAsset>(
     match entity.name.as_str() {
         "flutterbird" => {
             let colors = ["89FBFF", "F0FC6C", "F493FF", "93BAFF"];
-            let color = parse_color(fastrand::choice(colors).unwrap())?;
+            let color = parse_color(entity_random(entity).choose(&colors).unwrap())?;
 
             let asset = asset_db.lookup_gameplay(cx, "scenery/flutterbird/idle00")?;
             r.sprite(
@@ -679,7 +682,7 @@ pub(super) fn render_entity<L: LookupAsset>(
         }
         "floatingDebris" => {
             let sprite = asset_db.lookup_gameplay(cx, "scenery/debris")?;
-            let offset_x = fastrand::u8(0..7) * 8;
+            let offset_x = entity_random(entity).next_max(7) * 8;
 
             r.sprite(
                 cx,
@@ -701,7 +704,7 @@ pub(super) fn render_entity<L: LookupAsset>(
                 ],
                 &["scenery/fgdebris/rock_b00", "scenery/fgdebris/rock_b01"],
             ];
-            let rock = *fastrand::choice(rock_textures).unwrap();
+            let rock = *entity_random(entity).choose(rock_textures).unwrap();
 
             for texture in rock {
                 let sprite = asset_db.lookup_gameplay(cx, texture)?;
@@ -1184,10 +1187,7 @@ pub(super) fn render_entity<L: LookupAsset>(
 
             let similar = room.entities.iter().filter(|other| {
                 other.name == entity.name
-                    && other
-                        .raw
-                        .get_attr_int("index")
-                        .is_ok_and(|i| i == index)
+                    && other.raw.get_attr_int("index").is_ok_and(|i| i == index)
             });
 
             let mut rectangles = Vec::new();
@@ -1310,7 +1310,7 @@ fn entity_rect(entity: &Entity) -> Result<((f32, f32), (i32, i32))> {
 fn render_faketiles<L: LookupAsset>(
     r: &mut RenderContext<L>,
     cx: &CelesteRenderData,
-    asset_db: &mut AssetDb<L>,
+    asset_db: &AssetDb<L>,
     room: &Room,
     entity: &Entity,
     material_key: &'static str,
@@ -1365,6 +1365,7 @@ fn render_faketiles<L: LookupAsset>(
         },
     );
     let _blend_mode = blend_key;
+    let mut random = entity_random(entity);
     let pos = room
         .bounds
         .position
@@ -1376,6 +1377,7 @@ fn render_faketiles<L: LookupAsset>(
         ),
         pos.offset_tile(-(draw_extra_around as i32), -(draw_extra_around as i32)),
         &tiles,
+        &mut |_, _| random.sample(),
         tilesets,
         cx,
         asset_db,
@@ -1387,7 +1389,7 @@ fn ninepatch_entity<L: LookupAsset>(
     entity: &Entity,
     default_size: (i32, i32),
     texture_block: &str,
-    asset_db: &mut AssetDb<L>,
+    asset_db: &AssetDb<L>,
     cx: &CelesteRenderData,
     r: &mut RenderContext<L>,
     map_pos: (f32, f32),
@@ -1419,7 +1421,7 @@ fn ninepatch_middle<L: LookupAsset>(
     default_size: (i32, i32),
     texture_block: &str,
     texture_middle: &str,
-    asset_db: &mut AssetDb<L>,
+    asset_db: &AssetDb<L>,
     cx: &CelesteRenderData,
     r: &mut RenderContext<L>,
     map_pos: (f32, f32),
@@ -1455,11 +1457,25 @@ fn ninepatch_middle<L: LookupAsset>(
     Ok(())
 }
 
-// bad substitute for depth
+/// Depth of an entity: its `depth` attribute, the vanilla depth, or that of solids for fake tile entities
+pub(super) fn entity_depth(entity: &Entity) -> i32 {
+    if let Ok(Some(depth)) = entity.raw.try_get_attr_int("depth") {
+        return depth;
+    }
+    if let Some(depth) = depth::vanilla_entity_depth(&entity.name) {
+        return depth;
+    }
+    match texture_map().get(entity.name.as_str()) {
+        Some(RenderMethod::FakeTiles { .. }) => depth::SOLIDS,
+        _ => depth::PLAYER,
+    }
+}
+
+/// Parts of an entity which are drawn just behind it, like spinner connectors
 pub(super) fn pre_render_entity<L: LookupAsset>(
     r: &mut RenderContext<L>,
     cx: &CelesteRenderData,
-    asset_db: &mut AssetDb<L>,
+    asset_db: &AssetDb<L>,
     room: &Room,
     entity: &Entity,
 ) -> Result<()> {
@@ -1481,7 +1497,7 @@ fn get_spinner_texture(color: &str, foreground: bool) -> String {
 fn spinner_connectors<L: LookupAsset>(
     entity: &Entity,
     room: &Room,
-    asset_db: &mut AssetDb<L>,
+    asset_db: &AssetDb<L>,
     cx: &CelesteRenderData,
     r: &mut RenderContext<L>,
 ) -> Result<(), anyhow::Error> {
@@ -1547,7 +1563,7 @@ fn spinner_connectors<L: LookupAsset>(
 fn spinner_main<L: LookupAsset>(
     entity: &Entity,
     room: &Room,
-    asset_db: &mut AssetDb<L>,
+    asset_db: &AssetDb<L>,
     cx: &CelesteRenderData,
     r: &mut RenderContext<L>,
     map_pos: (f32, f32),
@@ -1611,7 +1627,7 @@ fn spinner_main<L: LookupAsset>(
 fn frost_spinner_main<L: LookupAsset>(
     entity: &Entity,
     _room: &Room,
-    asset_db: &mut AssetDb<L>,
+    asset_db: &AssetDb<L>,
     cx: &CelesteRenderData,
     r: &mut RenderContext<L>,
     map_pos: (f32, f32),
@@ -1654,7 +1670,7 @@ fn frost_spinner_main<L: LookupAsset>(
 fn frost_spinner_connectors<L: LookupAsset>(
     entity: &Entity,
     room: &Room,
-    asset_db: &mut AssetDb<L>,
+    asset_db: &AssetDb<L>,
     cx: &CelesteRenderData,
     r: &mut RenderContext<L>,
 ) -> Result<(), anyhow::Error> {
@@ -1716,7 +1732,7 @@ fn frost_spinner_connectors<L: LookupAsset>(
 #[cfg_attr(feature = "tracing_detailed", tracing::instrument(skip_all))]
 fn custom_sprite_path<L: LookupAsset>(
     entity: &Entity,
-    asset_db: &mut AssetDb<L>,
+    asset_db: &AssetDb<L>,
     cx: &CelesteRenderData,
     property_name: &str,
     postfix: &str,
@@ -1812,7 +1828,7 @@ fn spikes<L: LookupAsset>(
     entity: &Entity,
     dir: CardinalDir,
     trigger: bool, // TODO
-    asset_db: &mut AssetDb<L>,
+    asset_db: &AssetDb<L>,
     cx: &CelesteRenderData,
     r: &mut RenderContext<L>,
 ) -> Result<()> {
@@ -1850,6 +1866,7 @@ fn spikes<L: LookupAsset>(
             CardinalDir::Left => PI * 3.0 / 2.0,
         };
 
+        let mut random = entity_random(entity);
         for offset in (0..=length - 4).step_by(4) {
             let second_sprite = offset % 8 == 0;
 
@@ -1862,7 +1879,7 @@ fn spikes<L: LookupAsset>(
                 Color::from_rgba8(242, 16, 103, 255),
             ];
 
-            let color = fastrand::choice(colors).unwrap();
+            let color = *random.choose(&colors).unwrap();
 
             let texture = match second_sprite {
                 true => "danger/triggertentacle/wiggle_v03",
@@ -1969,7 +1986,7 @@ fn spikes<L: LookupAsset>(
 fn jump_thru<L: LookupAsset>(
     entity: &Entity,
     fgtiles: &Matrix<char>,
-    asset_db: &mut AssetDb<L>,
+    asset_db: &AssetDb<L>,
     cx: &CelesteRenderData,
     r: &mut RenderContext<L>,
     map_pos: (f32, f32),
@@ -2139,7 +2156,7 @@ enum RenderMethod {
     },
 }
 
-fn parse_color(color: &str) -> Result<Color> {
+pub(super) fn parse_color(color: &str) -> Result<Color> {
     match color {
         "Transparent" => return Ok(Color::from_rgba8(0, 0, 0, 0)),
         "AliceBlue" => return Ok(Color::from_rgba8(240, 248, 255, 255)),
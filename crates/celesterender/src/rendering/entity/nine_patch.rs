@@ -67,7 +67,7 @@ impl Default for NinePatchOptions {
 }
 
 pub fn nine_patch<L: LookupAsset>(
-    asset_db: &mut AssetDb<L>,
+    asset_db: &AssetDb<L>,
     cx: &CelesteRenderData,
     r: &mut RenderContext<L>,
     texture: &str,
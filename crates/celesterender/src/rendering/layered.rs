@@ -0,0 +1,209 @@
+//! Rendering the layers of a map into separate transparent images, for editing them in e.g. GIMP or Krita.
+//!
+//! Every layer is rendered in a pass of its own, so things are no longer sorted by depth across layers.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufWriter, Seek, Write},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use celesteloader::map::{Bounds, Map};
+use tiny_skia::{Color, FilterQuality, Pixmap, PixmapPaint, Transform};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use crate::asset::{AssetDb, LookupAsset};
+
+use super::{encode_png, render, CelesteRenderData, Layer, RenderMapSettings};
+
+/// Layers rendered separately, from bottom to top.
+/// The room outlines and labels go into a `rooms` layer on top.
+const LAYERS: [(Layer, &str); 7] = [
+    (Layer::STYLEGROUNDS_BG, "stylegrounds_bg"),
+    (Layer::TILES_BG, "tiles_bg"),
+    (Layer::DECALS_BG, "decals_bg"),
+    (Layer::ENTITIES, "entities"),
+    (Layer::TILES_FG, "tiles_fg"),
+    (Layer::DECALS_FG, "decals_fg"),
+    (Layer::TRIGGERS, "triggers"),
+];
+
+/// Maximum width and height of the OpenRaster thumbnail
+const THUMBNAIL_SIZE: u32 = 256;
+
+pub struct RenderLayer {
+    pub layer: Layer,
+    pub name: &'static str,
+    /// Transparent image of the whole map
+    pub image: Pixmap,
+}
+
+pub struct LayeredRenderResult {
+    pub bounds: Bounds,
+    /// Names of the rendered layers, from bottom to top
+    pub layers: Vec<&'static str>,
+    pub unknown_entities: BTreeMap<String, u32>,
+}
+
+/// Renders each of the layers in [`RenderMapSettings::layer`] into an image of its own,
+/// calling `on_layer` for each from bottom to top.
+#[tracing::instrument(skip_all, fields(name = map.package))]
+pub fn render_layers<L: LookupAsset>(
+    render_data: &CelesteRenderData,
+    asset_db: &AssetDb<L>,
+    map: &Map,
+    settings: RenderMapSettings,
+    mut on_layer: impl FnMut(RenderLayer) -> Result<()>,
+) -> Result<LayeredRenderResult> {
+    let mut layers = LAYERS
+        .into_iter()
+        .filter(|&(layer, _)| settings.layer.has(layer))
+        .collect::<Vec<_>>();
+    let overlays = settings.room_outlines.is_some() || settings.room_labels;
+    if overlays {
+        layers.push((Layer::NONE, "rooms"));
+    }
+
+    let mut bounds = Bounds::empty();
+    let mut unknown_entities = BTreeMap::new();
+    for (i, &(layer, name)) in layers.iter().enumerate() {
+        let status_update =
+            |done, rooms| (settings.status_update)(i * rooms + done, layers.len() * rooms);
+        let is_overlay = overlays && i == layers.len() - 1;
+        let result = render(
+            render_data,
+            asset_db,
+            map,
+            RenderMapSettings {
+                layer,
+                status_update: &status_update,
+                background: Color::TRANSPARENT,
+                room_outlines: settings.room_outlines.filter(|_| is_overlay),
+                room_labels: settings.room_labels && is_overlay,
+                ..settings
+            },
+        )?;
+
+        bounds = result.bounds;
+        for (name, count) in result.unknown_entities {
+            *unknown_entities.entry(name).or_default() += count;
+        }
+        on_layer(RenderLayer {
+            layer,
+            name,
+            image: result.image,
+        })?;
+    }
+
+    Ok(LayeredRenderResult {
+        bounds,
+        layers: layers.into_iter().map(|(_, name)| name).collect(),
+        unknown_entities,
+    })
+}
+
+/// Renders the layers into `dir` as aligned `{index}_{name}.png` images, see [`render_layers`]
+pub fn render_layers_to_dir<L: LookupAsset>(
+    render_data: &CelesteRenderData,
+    asset_db: &AssetDb<L>,
+    map: &Map,
+    settings: RenderMapSettings,
+    dir: &Path,
+    compression: png::Compression,
+) -> Result<LayeredRenderResult> {
+    std::fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+
+    let mut index = 0;
+    render_layers(render_data, asset_db, map, settings, |layer| {
+        let path = dir.join(format!("{index}_{}.png", layer.name));
+        let file =
+            File::create(&path).with_context(|| format!("failed to create {}", path.display()))?;
+        encode_png(layer.image, BufWriter::new(file), compression)?;
+        index += 1;
+        Ok(())
+    })
+}
+
+/// Renders the layers into an [OpenRaster](https://www.openraster.org/) file, which can be opened by GIMP and Krita.
+/// See [`render_layers`].
+pub fn render_openraster<L: LookupAsset>(
+    render_data: &CelesteRenderData,
+    asset_db: &AssetDb<L>,
+    map: &Map,
+    settings: RenderMapSettings,
+    w: impl Write + Seek,
+    compression: png::Compression,
+) -> Result<LayeredRenderResult> {
+    // the PNGs are compressed already
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+
+    let mut zip = ZipWriter::new(w);
+    // has to come first, uncompressed
+    zip.start_file("mimetype", options)?;
+    zip.write_all(b"image/openraster")?;
+
+    let mut merged: Option<Pixmap> = None;
+    let result = render_layers(render_data, asset_db, map, settings, |layer| {
+        let merged = merged.get_or_insert_with(|| {
+            let mut pixmap = Pixmap::new(layer.image.width(), layer.image.height()).unwrap();
+            pixmap.fill(settings.background);
+            pixmap
+        });
+        merged.draw_pixmap(
+            0,
+            0,
+            layer.image.as_ref(),
+            &PixmapPaint::default(),
+            Transform::identity(),
+            None,
+        );
+
+        zip.start_file(format!("data/{}.png", layer.name), options)?;
+        encode_png(layer.image, &mut zip, compression)?;
+        Ok(())
+    })?;
+    let merged = merged.context("No layers to render")?;
+
+    let (width, height) = (merged.width(), merged.height());
+    let mut stack = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<image version=\"0.0.5\" w=\"{width}\" h=\"{height}\">\n<stack>\n"
+    );
+    // listed from top to bottom
+    for name in result.layers.iter().rev() {
+        stack.push_str(&format!(
+            "<layer name=\"{name}\" src=\"data/{name}.png\" x=\"0\" y=\"0\" visibility=\"visible\" opacity=\"1.0\"/>\n"
+        ));
+    }
+    stack.push_str("</stack>\n</image>\n");
+    zip.start_file("stack.xml", options)?;
+    zip.write_all(stack.as_bytes())?;
+
+    let scale = (THUMBNAIL_SIZE as f32 / width.max(height) as f32).min(1.0);
+    let mut thumbnail = Pixmap::new(
+        ((width as f32 * scale).ceil() as u32).max(1),
+        ((height as f32 * scale).ceil() as u32).max(1),
+    )
+    .unwrap();
+    thumbnail.draw_pixmap(
+        0,
+        0,
+        merged.as_ref(),
+        &PixmapPaint {
+            quality: FilterQuality::Bilinear,
+            ..Default::default()
+        },
+        Transform::from_scale(scale, scale),
+        None,
+    );
+    zip.start_file("Thumbnails/thumbnail.png", options)?;
+    encode_png(thumbnail, &mut zip, compression)?;
+
+    zip.start_file("mergedimage.png", options)?;
+    encode_png(merged, &mut zip, compression)?;
+
+    zip.finish()?;
+
+    Ok(result)
+}
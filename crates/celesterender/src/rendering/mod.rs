@@ -1,5 +1,13 @@
+mod depth;
 pub mod entity;
+mod font;
+mod layered;
+mod random;
+mod rooms;
+mod styleground;
+mod tiled;
 pub mod tileset;
+mod trigger;
 
 use std::{
     collections::{BTreeMap, HashMap},
@@ -13,30 +21,45 @@ use anyhow::{anyhow, ensure, Context, Result};
 use celesteloader::{
     archive::ModArchive,
     atlas::Sprite,
-    map::{utils::parse_map_name, Bounds, Decal, Map, Pos, Room},
+    map::{style::Style, utils::parse_map_name, Bounds, Decal, Entity, Map, Pos, Room},
     CelesteInstallation,
 };
+use rayon::prelude::*;
 use tiny_skia::{
-    BlendMode, Color, IntSize, Paint, PathBuilder, Pattern, Pixmap, PixmapRef,
+    BlendMode, Color, IntSize, Paint, PathBuilder, Pattern, Pixmap, PixmapPaint, PixmapRef,
     PremultipliedColorU8, Rect, Shader, Stroke, Transform,
 };
 use tracing::instrument;
 
 use crate::asset::{AssetDb, LookupAsset, SpriteLocation};
 
+pub use self::layered::{
+    render_layers, render_layers_to_dir, render_openraster, LayeredRenderResult, RenderLayer,
+};
+use self::random::RoomTileRandom;
+pub use self::rooms::{
+    render_rooms, render_rooms_to_dir, ManifestBounds, ManifestRoom, RoomImage, RoomManifest,
+};
+pub use self::tiled::{
+    render_png_streaming, render_tiled, render_tiles_to_dir, RenderTile, TiledRenderResult,
+};
 use self::tileset::{tiles_to_matrix, tiles_to_matrix_scenery, Matrix, ParsedTileset};
 
 #[derive(Clone, Copy)]
 pub struct Layer(u8);
 impl Layer {
     pub const NONE: Layer = Layer(0b00000000);
-    pub const ALL: Layer = Layer(0b00111111);
+    pub const ALL: Layer = Layer(0b01111111);
+    /// Everything in the room itself, i.e. all layers except triggers and stylegrounds
+    pub const DEFAULT: Layer = Layer(0b00011111);
     pub const TILES_BG: Layer = Layer(1 << 0);
     pub const DECALS_BG: Layer = Layer(1 << 1);
     pub const ENTITIES: Layer = Layer(1 << 2);
     pub const TILES_FG: Layer = Layer(1 << 3);
     pub const DECALS_FG: Layer = Layer(1 << 4);
     pub const TRIGGERS: Layer = Layer(1 << 5);
+    /// Background stylegrounds behind each room
+    pub const STYLEGROUNDS_BG: Layer = Layer(1 << 6);
 
     pub fn has(self, other: Layer) -> bool {
         self.0 & other.0 == other.0
@@ -165,6 +188,8 @@ impl CelesteRenderData {
 pub struct RenderResult {
     pub image: Pixmap,
     pub bounds: Bounds,
+    /// Pixels in `image` per pixel of the map, see [`RenderMapSettings::scale`]
+    pub scale: f32,
     pub unknown_entities: BTreeMap<String, u32>,
 }
 impl RenderResult {
@@ -184,89 +209,167 @@ impl RenderResult {
         w: impl std::io::Write,
         compression: png::Compression,
     ) -> Result<(), png::EncodingError> {
-        let mut image = std::mem::replace(&mut self.image, Pixmap::new(1, 1).unwrap());
+        let image = std::mem::replace(&mut self.image, Pixmap::new(1, 1).unwrap());
+        encode_png(image, w, compression)
+    }
+}
 
-        for pixel in image.pixels_mut() {
-            let c = pixel.demultiply();
-            // SAFETY: we just demultiplied
-            *pixel = unsafe {
-                PremultipliedColorU8::from_rgba(c.red(), c.green(), c.blue(), c.alpha())
-                    .unwrap_unchecked()
-            };
-        }
+/// Turns the premultiplied pixels into the straight RGBA data PNG expects
+fn demultiply(image: &mut Pixmap) {
+    for pixel in image.pixels_mut() {
+        let c = pixel.demultiply();
+        // SAFETY: we just demultiplied
+        *pixel = unsafe {
+            PremultipliedColorU8::from_rgba(c.red(), c.green(), c.blue(), c.alpha())
+                .unwrap_unchecked()
+        };
+    }
+}
 
-        let mut encoder = png::Encoder::new(w, image.width(), image.height());
-        encoder.set_color(png::ColorType::Rgba);
-        encoder.set_depth(png::BitDepth::Eight);
-        encoder.set_compression(compression);
-        encoder.set_filter(png::Filter::Adaptive);
-        let mut writer = encoder.write_header()?;
-        writer.write_image_data(image.data())?;
+fn png_encoder<W: std::io::Write>(
+    w: W,
+    (width, height): (u32, u32),
+    compression: png::Compression,
+) -> png::Encoder<'static, W> {
+    let mut encoder = png::Encoder::new(w, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_compression(compression);
+    encoder.set_filter(png::Filter::Adaptive);
+    encoder
+}
 
-        Ok(())
-    }
+pub(crate) fn encode_png(
+    mut image: Pixmap,
+    w: impl std::io::Write,
+    compression: png::Compression,
+) -> Result<(), png::EncodingError> {
+    demultiply(&mut image);
+
+    let encoder = png_encoder(w, (image.width(), image.height()), compression);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(image.data())?;
+
+    Ok(())
 }
 
+#[derive(Clone, Copy)]
 pub struct RenderMapSettings<'a> {
     pub layer: Layer,
     pub include_room: &'a dyn Fn(&Room) -> bool,
     pub status_update: &'a dyn Fn(usize, usize),
+    /// SID the map is loaded as in game, e.g. `Celeste/1-ForsakenCity`, which seeds the tile variants.
+    /// Defaults to the map's package.
+    pub sid: Option<&'a str>,
+    /// Color of the parts of the map not covered by rooms, e.g. `Color::TRANSPARENT`
+    pub background: Color,
+    /// Color to fill the filler rectangles between rooms with, if they should be drawn.
+    /// They are part of the foreground tiles, and only drawn within the bounds of the rendered rooms.
+    pub fillers: Option<Color>,
+    /// Color to draw the edges of the rooms with, if they should be drawn
+    pub room_outlines: Option<Color>,
+    /// Whether to write the room names in their top left corner
+    pub room_labels: bool,
+    /// Size of the output relative to the map. Whole numbers scale up without smoothing.
+    pub scale: f32,
 }
 impl<'a> Default for RenderMapSettings<'a> {
     fn default() -> Self {
         Self {
-            layer: Layer::ALL,
+            layer: Layer::DEFAULT,
             include_room: &|_| true,
             status_update: &|_, _| {},
+            sid: None,
+            background: Color::from_rgba8(50, 50, 50, 255),
+            fillers: None,
+            room_outlines: None,
+            room_labels: false,
+            scale: 1.0,
         }
     }
 }
 impl<'a> RenderMapSettings<'a> {
+    /// Layers to render, e.g. `Layer::DEFAULT | Layer::TRIGGERS` to show triggers
+    pub fn layer(self, layer: Layer) -> Self {
+        RenderMapSettings { layer, ..self }
+    }
+
     pub fn include_room(self, f: &'a dyn Fn(&Room) -> bool) -> Self {
         RenderMapSettings {
-            layer: self.layer,
             include_room: f,
-            status_update: self.status_update,
+            ..self
         }
     }
 
     pub fn status_update(self, f: &'a dyn Fn(usize, usize)) -> Self {
         RenderMapSettings {
-            layer: self.layer,
-            include_room: self.include_room,
             status_update: f,
+            ..self
         }
     }
+
+    pub fn sid(self, sid: &'a str) -> Self {
+        RenderMapSettings {
+            sid: Some(sid),
+            ..self
+        }
+    }
+
+    pub fn background(self, background: Color) -> Self {
+        RenderMapSettings { background, ..self }
+    }
+
+    pub fn fillers(self, color: Color) -> Self {
+        RenderMapSettings {
+            fillers: Some(color),
+            ..self
+        }
+    }
+
+    pub fn room_outlines(self, color: Color) -> Self {
+        RenderMapSettings {
+            room_outlines: Some(color),
+            ..self
+        }
+    }
+
+    pub fn room_labels(self, room_labels: bool) -> Self {
+        RenderMapSettings {
+            room_labels,
+            ..self
+        }
+    }
+
+    /// e.g. `0.25` for thumbnails or `3.0` for crisp upscales
+    pub fn scale(self, scale: f32) -> Self {
+        RenderMapSettings { scale, ..self }
+    }
+
+    /// Whether the output is drawn at a size other than the map's
+    fn is_scaled(&self) -> bool {
+        self.scale != 1.0
+    }
 }
 
 #[instrument(skip_all, fields(name = map.package))]
 pub fn render<L: LookupAsset>(
     render_data: &CelesteRenderData,
-    asset_db: &mut AssetDb<L>,
+    asset_db: &AssetDb<L>,
     map: &Map,
     settings: RenderMapSettings,
 ) -> Result<RenderResult> {
-    fastrand::seed(2);
-
-    let parsed_map_name = parse_map_name(&map.package);
-
-    let mut map_bounds = Bounds::empty();
-    let mut rooms = Vec::new();
-    for room in &map.rooms {
-        if (settings.include_room)(room) {
-            map_bounds = map_bounds.join(room.bounds);
-            rooms.push(room);
-        }
-    }
-
-    ensure!(!rooms.is_empty(), "No rooms to render");
+    let MapRooms {
+        rooms,
+        map_bounds,
+        area_id,
+    } = MapRooms::new(map, &settings)?;
 
     let pixmap = {
         let size_pixels = map_bounds.size.0 as usize * map_bounds.size.1 as usize;
 
         let data = {
             let _span = tracing::info_span!("allocate_pixmap").entered();
-            allocate_data(size_pixels, [50, 50, 50, 255]).map_err(|_| {
+            allocate_data(size_pixels, premultiplied(settings.background)).map_err(|_| {
                 anyhow!(
                     "could not allocate {:.02}GiB",
                     size_pixels as f32 * 4.0 / (1024.0 * 1024.0 * 1024.0)
@@ -285,26 +388,347 @@ pub fn render<L: LookupAsset>(
         map_bounds,
         pixmap,
         unknown_entities: Default::default(),
-        area_id: parsed_map_name.order,
+        area_id,
         _marker: PhantomData::<L>,
     };
-    if parsed_map_name.name == "LostLevels" {
-        cx.area_id = Some(10);
-    }
+    cx.draw_fillers(map, &settings);
+
+    let rendered_rooms = rooms.iter().map(|&(room, _)| room).collect::<Vec<_>>();
+
+    // rooms are rendered in parallel into their own pixmaps, which are composited
+    // in map order as they come in, so the result doesn't depend on scheduling
+    let room_count = rooms.len();
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::scope(|s| {
+        let layer = settings.layer;
+        let background = settings.background;
+        let style = &map.style;
+        s.spawn(move || {
+            let _ = rooms.into_par_iter().enumerate().try_for_each_with(
+                tx,
+                |tx, (i, (room, tile_random))| {
+                    let room_cx = RenderContext::render_room_canvas(
+                        room_canvas_bounds(map_bounds, room, render_data, asset_db),
+                        background,
+                        area_id,
+                        room,
+                        &tile_random,
+                        style,
+                        render_data,
+                        asset_db,
+                        layer,
+                    );
+                    // fails once the compositor stopped because of an error
+                    tx.send((i, room_cx))
+                },
+            );
+        });
+
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        (settings.status_update)(0, room_count);
+        for (i, room_cx) in rx {
+            pending.insert(i, room_cx?);
+            while let Some(room_cx) = pending.remove(&next) {
+                cx.composite(&room_cx);
+                cx.merge_unknown_entities(room_cx);
+                next += 1;
+                if next < room_count {
+                    (settings.status_update)(next, room_count);
+                }
+            }
+        }
 
-    for (i, room) in rooms.iter().enumerate() {
-        (settings.status_update)(i, rooms.len());
-        cx.render_room(room, render_data, asset_db, settings.layer)?;
-    }
+        anyhow::Ok(())
+    })?;
+
+    let mut image = scale_image(cx.pixmap, settings.scale)?;
+    draw_room_overlays(
+        &mut image,
+        map_bounds,
+        settings.scale,
+        &rendered_rooms,
+        &settings,
+    );
 
     Ok(RenderResult {
-        image: cx.pixmap,
+        image,
         bounds: map_bounds,
+        scale: settings.scale,
         unknown_entities: cx.unknown_entities,
     })
 }
 
+/// Color in the premultiplied form pixmaps store
+fn premultiplied(color: Color) -> [u8; 4] {
+    let color = color.premultiply().to_color_u8();
+    [color.red(), color.green(), color.blue(), color.alpha()]
+}
+
+/// Resizes `image` to `scale` times its size. Downscaling goes in halving steps, so small scales don't alias.
+fn scale_image(mut image: Pixmap, scale: f32) -> Result<Pixmap> {
+    if scale == 1.0 {
+        return Ok(image);
+    }
+
+    let scaled = |size: u32| ((size as f32 * scale).round() as u32).max(1);
+    let size = (scaled(image.width()), scaled(image.height()));
+
+    while image.width() / 2 >= size.0 && image.height() / 2 >= size.1 {
+        let half = (image.width() / 2, image.height() / 2);
+        image = resize_image(&image, half, tiny_skia::FilterQuality::Bilinear)?;
+    }
+
+    let quality = if scale.fract() == 0.0 {
+        tiny_skia::FilterQuality::Nearest
+    } else {
+        tiny_skia::FilterQuality::Bilinear
+    };
+    resize_image(&image, size, quality)
+}
+
+fn resize_image(
+    image: &Pixmap,
+    (width, height): (u32, u32),
+    quality: tiny_skia::FilterQuality,
+) -> Result<Pixmap> {
+    let mut resized = Pixmap::new(width, height).context("failed to create scaled pixmap")?;
+    resized.draw_pixmap(
+        0,
+        0,
+        image.as_ref(),
+        &PixmapPaint {
+            quality,
+            blend_mode: BlendMode::Source,
+            ..Default::default()
+        },
+        Transform::from_scale(
+            width as f32 / image.width() as f32,
+            height as f32 / image.height() as f32,
+        ),
+        None,
+    );
+    Ok(resized)
+}
+
+/// Draws the room outlines and labels enabled in `settings` over `image`,
+/// which shows the part `bounds` of the map at `scale`.
+fn draw_room_overlays(
+    image: &mut Pixmap,
+    bounds: Bounds,
+    scale: f32,
+    rooms: &[&Room],
+    settings: &RenderMapSettings,
+) {
+    if settings.room_outlines.is_none() && !settings.room_labels {
+        return;
+    }
+
+    // lines and letters are made of whole pixels, so they stay crisp when scaled up
+    let pixel = scale.round().max(1.0) as i32;
+    let to_image = |x: i32, y: i32| {
+        (
+            ((x - bounds.position.x) as f32 * scale).round() as i32,
+            ((y - bounds.position.y) as f32 * scale).round() as i32,
+        )
+    };
+
+    for room in rooms {
+        let (left, top) = to_image(room.bounds.position.x, room.bounds.position.y);
+        let (right, bottom) = to_image(room.bounds.r(), room.bounds.b());
+
+        if let Some(color) = settings.room_outlines {
+            fill_clipped(image, (left, top), (right - left, pixel), color);
+            fill_clipped(image, (left, bottom - pixel), (right - left, pixel), color);
+            fill_clipped(image, (left, top), (pixel, bottom - top), color);
+            fill_clipped(image, (right - pixel, top), (pixel, bottom - top), color);
+        }
+
+        if settings.room_labels {
+            let (width, height) = font::measure(&room.name);
+            let (x, y) = (left + 2 * pixel, top + 2 * pixel);
+            fill_clipped(
+                image,
+                (x - pixel, y - pixel),
+                ((width as i32 + 2) * pixel, (height as i32 + 2) * pixel),
+                Color::from_rgba8(0, 0, 0, 160),
+            );
+            for (dx, dy) in font::pixels(&room.name) {
+                fill_clipped(
+                    image,
+                    (x + dx as i32 * pixel, y + dy as i32 * pixel),
+                    (pixel, pixel),
+                    Color::WHITE,
+                );
+            }
+        }
+    }
+}
+
+/// Fills a rectangle of `image`, cut off at its edges
+fn fill_clipped(image: &mut Pixmap, (x, y): (i32, i32), (width, height): (i32, i32), color: Color) {
+    let left = x.max(0);
+    let top = y.max(0);
+    let right = (x + width).min(image.width() as i32);
+    let bottom = (y + height).min(image.height() as i32);
+    let Some(rect) = Rect::from_ltrb(left as f32, top as f32, right as f32, bottom as f32) else {
+        return;
+    };
+    image.fill_rect(
+        rect,
+        &Paint {
+            shader: Shader::SolidColor(color),
+            anti_alias: false,
+            ..Default::default()
+        },
+        Transform::identity(),
+        None,
+    );
+}
+
+/// How far an entity's sprites may reach beyond its rectangle and nodes
+const ENTITY_OVERDRAW: f32 = 64.0;
+
+/// Part of the map a room is rendered into, cut to `map_bounds`, see [`room_drawn_bounds`]
+fn room_canvas_bounds<L: LookupAsset>(
+    map_bounds: Bounds,
+    room: &Room,
+    cx: &CelesteRenderData,
+    asset_db: &AssetDb<L>,
+) -> Bounds {
+    let drawn = room_drawn_bounds(room, |decal| {
+        let sprite = asset_db
+            .lookup_gameplay(cx, &format!("decals/{}", decal.texture))
+            .ok()?;
+        Some((sprite.real_width() as f32, sprite.real_height() as f32))
+    });
+    drawn.intersection(map_bounds).unwrap_or(room.bounds)
+}
+
+/// The room along with everything it draws outside of itself: decals by the size of their sprite,
+/// entities with their nodes and [`ENTITY_OVERDRAW`] around them, and the boxes of trigger nodes.
+fn room_drawn_bounds(room: &Room, decal_size: impl Fn(&Decal) -> Option<(f32, f32)>) -> Bounds {
+    let room_pos = (room.bounds.position.x as f32, room.bounds.position.y as f32);
+    let mut bounds = room.bounds;
+    let mut include = |(x, y): (f32, f32), (w, h): (f32, f32), margin: f32| {
+        let (left, top) = (room_pos.0 + x - margin, room_pos.1 + y - margin);
+        let (right, bottom) = (left + w + 2.0 * margin, top + h + 2.0 * margin);
+        let (left, top) = (left.floor() as i32, top.floor() as i32);
+        bounds = bounds.join(Bounds {
+            position: Pos { x: left, y: top },
+            size: (
+                (right.ceil() as i32 - left).max(0) as u32,
+                (bottom.ceil() as i32 - top).max(0) as u32,
+            ),
+        });
+    };
+
+    for decal in room.decals_bg.iter().chain(&room.decals_fg) {
+        match decal_size(decal) {
+            Some((w, h)) => {
+                // drawn centered, so half the diagonal covers any rotation
+                let radius = (w * decal.scale_x).hypot(h * decal.scale_y) / 2.0;
+                include((decal.x, decal.y), (0.0, 0.0), radius.ceil());
+            }
+            None => include((decal.x, decal.y), (0.0, 0.0), ENTITY_OVERDRAW),
+        }
+    }
+    for entity in &room.entities {
+        let size = |name| {
+            entity
+                .raw
+                .try_get_attr_num(name)
+                .ok()
+                .flatten()
+                .unwrap_or(0.0)
+                .max(0.0)
+        };
+        include(
+            entity.position,
+            (size("width"), size("height")),
+            ENTITY_OVERDRAW,
+        );
+        for node in &entity.nodes {
+            include(node.position, (0.0, 0.0), ENTITY_OVERDRAW);
+        }
+    }
+    for trigger in &room.triggers {
+        let size = (
+            trigger.extents.0.max(1) as f32,
+            trigger.extents.1.max(1) as f32,
+        );
+        // the name may be wider than the trigger
+        include(trigger.position, size, ENTITY_OVERDRAW);
+        for node in &trigger.nodes {
+            include(node.position, (trigger::NODE_SIZE, trigger::NODE_SIZE), 1.0);
+        }
+    }
+
+    bounds
+}
+
+/// Bounds of the fillers drawn by [`RenderContext::draw_fillers`], none if `settings` doesn't draw them
+fn drawn_fillers<'m>(
+    map: &'m Map,
+    settings: &RenderMapSettings,
+) -> impl Iterator<Item = Bounds> + 'm {
+    let enabled = settings.fillers.is_some() && settings.layer.has(Layer::TILES_FG);
+    map.fillers
+        .iter()
+        .filter(move |_| enabled)
+        .map(|filler| Bounds {
+            position: Pos {
+                x: filler.position.0 * 8,
+                y: filler.position.1 * 8,
+            },
+            size: (filler.size.0 as u32 * 8, filler.size.1 as u32 * 8),
+        })
+}
+
+/// The rooms of a map included by [`RenderMapSettings::include_room`], with what's needed to render them
+struct MapRooms<'a> {
+    rooms: Vec<(&'a Room, RoomTileRandom)>,
+    map_bounds: Bounds,
+    area_id: Option<u32>,
+}
+impl<'a> MapRooms<'a> {
+    fn new(map: &'a Map, settings: &RenderMapSettings) -> Result<Self> {
+        ensure!(
+            settings.scale.is_finite() && settings.scale > 0.0,
+            "scale must be positive"
+        );
+
+        let parsed_map_name = parse_map_name(&map.package);
+        let area_id = match parsed_map_name.name {
+            "LostLevels" => Some(10),
+            _ => parsed_map_name.order,
+        };
+
+        // drawn for all rooms, so the variants don't depend on which ones are included
+        let seed = random::map_load_seed(settings.sid.unwrap_or(&map.package));
+        let tile_random = random::autotile_random(map, seed)?;
+
+        let mut map_bounds = Bounds::empty();
+        let mut rooms = Vec::new();
+        for (room, tile_random) in map.rooms.iter().zip(tile_random) {
+            if (settings.include_room)(room) {
+                map_bounds = map_bounds.join(room.bounds);
+                rooms.push((room, tile_random));
+            }
+        }
+
+        ensure!(!rooms.is_empty(), "No rooms to render");
+
+        Ok(MapRooms {
+            rooms,
+            map_bounds,
+            area_id,
+        })
+    }
+}
+
 struct RenderContext<L> {
+    /// Part of the map covered by `pixmap`
     map_bounds: Bounds,
     pixmap: Pixmap,
     unknown_entities: BTreeMap<String, u32>,
@@ -313,6 +737,96 @@ struct RenderContext<L> {
 }
 
 impl<L: LookupAsset> RenderContext<L> {
+    /// Renders a room into a pixmap of its own covering `bounds`, see [`room_canvas_bounds`]
+    fn render_room_canvas(
+        bounds: Bounds,
+        background: Color,
+        area_id: Option<u32>,
+        room: &Room,
+        tile_random: &RoomTileRandom,
+        style: &Style,
+        cx: &CelesteRenderData,
+        asset_db: &AssetDb<L>,
+        layer: Layer,
+    ) -> Result<Self> {
+        let mut room_cx = RenderContext::room_canvas(bounds, background, area_id, room)?;
+        room_cx.render_room(room, tile_random, style, cx, asset_db, layer)?;
+        Ok(room_cx)
+    }
+
+    /// Transparent canvas covering `bounds`, except for the room itself being filled with `background`,
+    /// so additive drawing like water or additive stylegrounds blends with the same color as when
+    /// drawing onto the map directly
+    fn room_canvas(
+        bounds: Bounds,
+        background: Color,
+        area_id: Option<u32>,
+        room: &Room,
+    ) -> Result<Self> {
+        let mut room_cx = RenderContext {
+            map_bounds: bounds,
+            pixmap: Pixmap::new(bounds.size.0, bounds.size.1)
+                .with_context(|| format!("failed to create pixmap for room {}", room.name))?,
+            unknown_entities: Default::default(),
+            area_id,
+            _marker: PhantomData,
+        };
+        let room_rect = room_cx.transform_bounds(room.bounds);
+        room_cx.rect(room_rect, background, BlendMode::Source);
+        Ok(room_cx)
+    }
+
+    /// Draws a room rendered by [`RenderContext::render_room_canvas`] on top
+    fn composite(&mut self, room_cx: &RenderContext<L>) {
+        let Some(visible) = room_cx.map_bounds.intersection(self.map_bounds) else {
+            return;
+        };
+        let (x, y) = self.transform_pos(room_cx.map_bounds.position);
+        let (visible_x, visible_y) = self.transform_pos(visible.position);
+
+        // only the overlap is filled, as `draw_pixmap` smears the bottom row of pixmaps starting above the canvas
+        let rect = Rect::from_xywh(
+            visible_x as f32,
+            visible_y as f32,
+            visible.size.0 as f32,
+            visible.size.1 as f32,
+        )
+        .unwrap();
+        let paint = Paint {
+            shader: Pattern::new(
+                room_cx.pixmap.as_ref(),
+                tiny_skia::SpreadMode::Pad,
+                tiny_skia::FilterQuality::Nearest,
+                1.0,
+                Transform::from_translate(x as f32, y as f32),
+            ),
+            anti_alias: false,
+            ..Default::default()
+        };
+        self.pixmap
+            .fill_rect(rect, &paint, Transform::identity(), None);
+    }
+
+    fn merge_unknown_entities(&mut self, room_cx: RenderContext<L>) {
+        for (name, count) in room_cx.unknown_entities {
+            *self.unknown_entities.entry(name).or_default() += count;
+        }
+    }
+
+    /// Draws the fillers if enabled in `settings`, to be done before compositing the rooms
+    fn draw_fillers(&mut self, map: &Map, settings: &RenderMapSettings) {
+        let Some(color) = settings.fillers else {
+            return;
+        };
+
+        for bounds in drawn_fillers(map, settings) {
+            if let Some(visible) = bounds.intersection(self.map_bounds) {
+                let rect = self.transform_bounds(visible);
+                self.rect(rect, color, BlendMode::SourceOver);
+            }
+        }
+    }
+
     /// World space to image space
     fn transform_pos(&self, pos: Pos) -> (i32, i32) {
         let top_left = self.map_bounds.position;
@@ -426,6 +940,32 @@ impl<L: LookupAsset> RenderContext<L> {
         );
     }
 
+    /// Draws `text` with the 3x5 font, `map_pos` being the top left of the first glyph
+    fn text(&mut self, map_pos: (f32, f32), text: &str, color: Color) {
+        let (x, y) = self.transform_pos_f32(map_pos);
+        let mut pb = PathBuilder::new();
+        for (dx, dy) in font::pixels(text) {
+            if let Some(rect) = Rect::from_xywh(x + dx as f32, y + dy as f32, 1.0, 1.0) {
+                pb.push_rect(rect);
+            }
+        }
+        let Some(path) = pb.finish() else {
+            return;
+        };
+
+        self.pixmap.fill_path(
+            &path,
+            &Paint {
+                shader: tiny_skia::Shader::SolidColor(color),
+                anti_alias: false,
+                ..Default::default()
+            },
+            tiny_skia::FillRule::Winding,
+            Transform::identity(),
+            None,
+        );
+    }
+
     fn sprite(
         &mut self,
         cx: &CelesteRenderData,
@@ -591,8 +1131,10 @@ impl<L: LookupAsset> RenderContext<L> {
     fn render_room(
         &mut self,
         room: &Room,
+        tile_random: &RoomTileRandom,
+        style: &Style,
         cx: &CelesteRenderData,
-        asset_db: &mut AssetDb<L>,
+        asset_db: &AssetDb<L>,
         layer: Layer,
     ) -> Result<()> {
         if false {
@@ -608,29 +1150,56 @@ impl<L: LookupAsset> RenderContext<L> {
             );
         }
 
+        if layer.has(Layer::STYLEGROUNDS_BG) {
+            styleground::render_backgrounds(self, room, style, cx, asset_db)?;
+        }
+
         let bgtiles = tiles_to_matrix(room.bounds.size_tiles(), &room.bg_tiles_raw)?;
         let fgtiles = tiles_to_matrix(room.bounds.size_tiles(), &room.fg_tiles_raw)?;
 
-        if layer.has(Layer::TILES_BG) {
-            self.render_tileset(room, &bgtiles, &cx.map_tileset.tileset_bg, cx, asset_db)?;
-            self.render_tileset_scenery(room, &room.scenery_bg_raw, cx)?;
-        }
-        if layer.has(Layer::DECALS_BG) {
-            self.render_decals(room, &room.decals_bg, cx, asset_db)?;
-        }
-        if layer.has(Layer::ENTITIES) {
-            // TODO: sort by depth
-            self.render_entities(room, &fgtiles, cx, asset_db)?;
-        }
-        if layer.has(Layer::TILES_FG) {
-            self.render_tileset(room, &fgtiles, &cx.map_tileset.tileset_fg, cx, asset_db)?;
-            self.render_tileset_scenery(room, &room.scenery_fg_raw, cx)?;
-        }
-        if layer.has(Layer::DECALS_FG) {
-            self.render_decals(room, &room.decals_fg, cx, asset_db)?;
+        for item in draw_order(room, layer) {
+            match item {
+                DrawItem::TilesBg => {
+                    self.render_tileset(
+                        room,
+                        &bgtiles,
+                        &tile_random.bg,
+                        &cx.map_tileset.tileset_bg,
+                        cx,
+                        asset_db,
+                    )?;
+                    self.render_tileset_scenery(room, &room.scenery_bg_raw, cx)?;
+                }
+                DrawItem::TilesFg => {
+                    self.render_tileset(
+                        room,
+                        &fgtiles,
+                        &tile_random.fg,
+                        &cx.map_tileset.tileset_fg,
+                        cx,
+                        asset_db,
+                    )?;
+                    self.render_tileset_scenery(room, &room.scenery_fg_raw, cx)?;
+                }
+                DrawItem::Decal(decal) => self.render_decal(room, decal, cx, asset_db)?,
+                DrawItem::EntityBehind(e) => {
+                    let _span = tracing::info_span!("render_entities_pre").entered();
+                    entity::pre_render_entity(self, cx, asset_db, room, e)?;
+                }
+                DrawItem::Entity(e) => {
+                    let _span = tracing::info_span!("render_entities").entered();
+                    if !entity::render_entity(self, &fgtiles, cx, asset_db, room, e)
+                        .with_context(|| format!("couldn't render entity {}", e.name))?
+                    {
+                        *self.unknown_entities.entry(e.name.clone()).or_default() += 1;
+                    }
+                }
+            }
         }
         if layer.has(Layer::TRIGGERS) {
-            // trigger
+            for trigger in &room.triggers {
+                trigger::render_trigger(self, room, trigger);
+            }
         }
 
         Ok(())
@@ -640,15 +1209,17 @@ impl<L: LookupAsset> RenderContext<L> {
         &mut self,
         room: &Room,
         tiles: &Matrix<char>,
+        random: &Matrix<f64>,
         tilesets: &HashMap<char, ParsedTileset>,
         cx: &CelesteRenderData,
-        asset_db: &mut AssetDb<L>,
+        asset_db: &AssetDb<L>,
     ) -> Result<()> {
         let tile_pos = room.bounds.position;
         self.render_tileset_inner(
             room.bounds.size_tiles(),
             tile_pos,
             tiles,
+            &mut |x, y| random.get(x, y),
             tilesets,
             cx,
             asset_db,
@@ -661,9 +1232,10 @@ impl<L: LookupAsset> RenderContext<L> {
         size: (u32, u32),
         tile_pos: Pos,
         tiles: &Matrix<char>,
+        random: &mut dyn FnMut(u32, u32) -> f64,
         tilesets: &HashMap<char, ParsedTileset>,
         cx: &CelesteRenderData,
-        asset_db: &mut AssetDb<L>,
+        asset_db: &AssetDb<L>,
     ) -> Result<()> {
         let (w, h) = size;
 
@@ -680,7 +1252,9 @@ impl<L: LookupAsset> RenderContext<L> {
                     .ok_or_else(|| anyhow!("tileset for '{}' not found", c))?;
 
                 let random_tiles = tileset::choose_tile(tileset, x, y, tiles)?.unwrap();
-                let sprite_tile_offset = fastrand::choice(random_tiles).unwrap();
+                // `Calc.Random.Choose`
+                let variant = (random(x, y) * random_tiles.len() as f64) as usize;
+                let sprite_tile_offset = random_tiles[variant.min(random_tiles.len() - 1)];
 
                 let sprite = asset_db.lookup_gameplay(cx, &format!("tilesets/{}", tileset.path))?;
 
@@ -765,60 +1339,74 @@ impl<L: LookupAsset> RenderContext<L> {
     }
 
     #[instrument(skip_all)]
-    fn render_decals(
+    fn render_decal(
         &mut self,
         room: &Room,
-        decals: &[Decal],
+        decal: &Decal,
         cx: &CelesteRenderData,
-        asset_db: &mut AssetDb<L>,
+        asset_db: &AssetDb<L>,
     ) -> Result<()> {
-        for decal in decals {
-            let map_pos = (
-                room.bounds.position.x as f32 + decal.x,
-                room.bounds.position.y as f32 + decal.y,
-            );
-
-            let sprite = asset_db.lookup_gameplay(cx, &format!("decals/{}", decal.texture))?;
-            self.sprite(
-                cx,
-                map_pos,
-                sprite,
-                SpriteDesc {
-                    scale: (decal.scale_x, decal.scale_y),
-                    ..Default::default()
-                },
-            )?;
-        }
+        let map_pos = (
+            room.bounds.position.x as f32 + decal.x,
+            room.bounds.position.y as f32 + decal.y,
+        );
 
-        Ok(())
+        let sprite = asset_db.lookup_gameplay(cx, &format!("decals/{}", decal.texture))?;
+        self.sprite(
+            cx,
+            map_pos,
+            sprite,
+            SpriteDesc {
+                scale: (decal.scale_x, decal.scale_y),
+                ..Default::default()
+            },
+        )
     }
+}
 
-    fn render_entities(
-        &mut self,
-        room: &Room,
-        fgtiles: &Matrix<char>,
-        cx: &CelesteRenderData,
-        asset_db: &mut AssetDb<L>,
-    ) -> Result<()> {
-        {
-            let _span = tracing::info_span!("render_entities_pre").entered();
-            for e in &room.entities {
-                entity::pre_render_entity(self, cx, asset_db, room, e)?;
-            }
-        }
-        {
-            let _span = tracing::info_span!("render_entities").entered();
-            for e in &room.entities {
-                if !entity::render_entity(self, fgtiles, cx, asset_db, room, e)
-                    .with_context(|| format!("couldn't render entity {}", e.name))?
-                {
-                    *self.unknown_entities.entry(e.name.clone()).or_default() += 1;
-                }
-            }
+/// The room contents drawn in the depth-sorted pass of [`RenderContext::render_room`], back to front
+fn draw_order(room: &Room, layer: Layer) -> Vec<DrawItem<'_>> {
+    let mut items = Vec::new();
+    if layer.has(Layer::TILES_BG) {
+        items.push((depth::BG_TERRAIN, DrawItem::TilesBg));
+    }
+    if layer.has(Layer::DECALS_BG) {
+        items.extend(room.decals_bg.iter().map(|decal| {
+            let depth = decal.depth.unwrap_or(depth::BG_DECALS);
+            (depth, DrawItem::Decal(decal))
+        }));
+    }
+    if layer.has(Layer::ENTITIES) {
+        for entity in &room.entities {
+            let depth = entity::entity_depth(entity);
+            items.push((depth.saturating_add(1), DrawItem::EntityBehind(entity)));
+            items.push((depth, DrawItem::Entity(entity)));
         }
-
-        Ok(())
     }
+    if layer.has(Layer::TILES_FG) {
+        items.push((depth::FG_TERRAIN, DrawItem::TilesFg));
+    }
+    if layer.has(Layer::DECALS_FG) {
+        items.extend(room.decals_fg.iter().map(|decal| {
+            let depth = decal.depth.unwrap_or(depth::FG_DECALS);
+            (depth, DrawItem::Decal(decal))
+        }));
+    }
+
+    // stable, so things of the same depth are drawn in file order
+    items.sort_by_key(|&(depth, _)| std::cmp::Reverse(depth));
+
+    items.into_iter().map(|(_, item)| item).collect()
+}
+
+/// Something drawn as part of a room's depth-sorted pass
+enum DrawItem<'a> {
+    TilesBg,
+    TilesFg,
+    Decal(&'a Decal),
+    /// See [`entity::pre_render_entity`]
+    EntityBehind(&'a Entity),
+    Entity(&'a Entity),
 }
 
 pub fn allocate_data(
@@ -850,3 +1438,255 @@ pub fn allocate_data(
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use celesteloader::map::{
+        decode::{ElementOwned, Value},
+        Decal, Entity, EntityNode, Trigger,
+    };
+
+    use super::*;
+    use crate::asset::NullLookup;
+
+    /// Empty room at `position` with a size of `size` pixels
+    pub(super) fn room(position: (i32, i32), size: (u32, u32)) -> Room {
+        Room {
+            name: String::new(),
+            bounds: Bounds {
+                position: Pos {
+                    x: position.0,
+                    y: position.1,
+                },
+                size,
+            },
+            fg_tiles_raw: String::new(),
+            bg_tiles_raw: String::new(),
+            obj_tiles_raw: String::new(),
+            scenery_fg_raw: String::new(),
+            scenery_bg_raw: String::new(),
+            dark: false,
+            space: false,
+            underwater: false,
+            whisper: false,
+            disable_down_transition: false,
+            wind_pattern: String::new(),
+            color: 0,
+            camera_offset: (0.0, 0.0),
+            entities: Vec::new(),
+            triggers: Vec::new(),
+            decals_bg: Vec::new(),
+            decals_fg: Vec::new(),
+        }
+    }
+
+    pub(super) fn map(rooms: Vec<Room>) -> Map {
+        Map {
+            package: String::new(),
+            rooms,
+            fillers: Vec::new(),
+            style: Style::default(),
+            meta: celesteloader::map::Metadata {
+                icon: None,
+                override_a_site_meta: false,
+                intro_type: None,
+                background_tiles: None,
+                foreground_tiles: None,
+            },
+        }
+    }
+
+    /// Render data without any sprites or tilesets, for maps which don't need them
+    pub(super) fn empty_render_data() -> CelesteRenderData {
+        CelesteRenderData {
+            gameplay_sprites: HashMap::new(),
+            map_tileset: MapTileset {
+                tileset_fg: HashMap::new(),
+                tileset_bg: HashMap::new(),
+            },
+            gameplay_atlas: Pixmap::new(1, 1).unwrap(),
+            scenery: Sprite {
+                path: String::new(),
+                x: 0,
+                y: 0,
+                w: 0,
+                h: 0,
+                offset_x: 0,
+                offset_y: 0,
+                real_w: 0,
+                real_h: 0,
+            },
+        }
+    }
+
+    fn ltrb(bounds: Bounds) -> (i32, i32, i32, i32) {
+        (bounds.position.x, bounds.position.y, bounds.r(), bounds.b())
+    }
+
+    #[test]
+    fn drawn_bounds_cover_room_contents() {
+        let mut room = room((100, 100), (40, 40));
+        assert_eq!(
+            ltrb(room_drawn_bounds(&room, |_| None)),
+            (100, 100, 140, 140)
+        );
+
+        room.decals_fg.push(Decal {
+            x: 0.0,
+            y: 20.0,
+            scale_x: -2.0,
+            scale_y: 1.0,
+            rotation: 0.0,
+            texture: "big".into(),
+            depth: None,
+        });
+        // 60x80 with a diagonal of 100
+        let decal_size = |_: &Decal| Some((30.0, 80.0));
+        assert_eq!(
+            ltrb(room_drawn_bounds(&room, decal_size)),
+            (50, 70, 150, 170)
+        );
+        room.decals_fg.clear();
+
+        room.triggers.push(Trigger {
+            id: None,
+            position: (8.0, 8.0),
+            extents: (16, 16),
+            name: "cameraOffsetTrigger".into(),
+            nodes: vec![EntityNode {
+                position: (-200.0, 300.0),
+            }],
+        });
+        let bounds = room_drawn_bounds(&room, |_| None);
+        assert_eq!((bounds.position.x, bounds.b()), (-101, 409));
+        room.triggers.clear();
+
+        room.entities.push(Entity {
+            id: None,
+            position: (16.0, 16.0),
+            name: "zipMover".into(),
+            raw: ElementOwned {
+                name: "zipMover".into(),
+                attributes: [("width".to_owned(), Value::I32(500))]
+                    .into_iter()
+                    .collect(),
+                children: Vec::new(),
+            },
+            nodes: vec![EntityNode {
+                position: (16.0, -300.0),
+            }],
+        });
+        let margin = ENTITY_OVERDRAW as i32;
+        let expected = (
+            100 + 16 - margin,
+            100 - 300 - margin,
+            100 + 16 + 500 + margin,
+            100 + 16 + margin,
+        );
+        assert_eq!(ltrb(room_drawn_bounds(&room, |_| None)), expected);
+    }
+
+    #[test]
+    fn draw_order_sorts_by_depth() {
+        let decal = |texture: &str, depth| Decal {
+            x: 0.0,
+            y: 0.0,
+            scale_x: 1.0,
+            scale_y: 1.0,
+            rotation: 0.0,
+            texture: texture.into(),
+            depth,
+        };
+        let entity = |name: &str, depth: Option<i32>| Entity {
+            id: None,
+            position: (0.0, 0.0),
+            name: name.into(),
+            raw: ElementOwned {
+                name: name.into(),
+                attributes: depth
+                    .map(|depth| ("depth".to_owned(), Value::I32(depth)))
+                    .into_iter()
+                    .collect(),
+                children: Vec::new(),
+            },
+            nodes: Vec::new(),
+        };
+
+        let mut room = room((0, 0), (8, 8));
+        room.decals_bg = vec![decal("bg", None), decal("bg_front", Some(-9500))];
+        room.decals_fg = vec![decal("fg", None), decal("fg_back", Some(5000))];
+        room.entities = vec![
+            entity("spinner", None),
+            entity("first", Some(0)),
+            entity("second", Some(0)),
+        ];
+
+        let order = draw_order(&room, Layer::ALL)
+            .into_iter()
+            .map(|item| match item {
+                DrawItem::TilesBg => "bg tiles".to_owned(),
+                DrawItem::TilesFg => "fg tiles".to_owned(),
+                DrawItem::Decal(decal) => decal.texture.clone(),
+                DrawItem::EntityBehind(entity) => format!("behind {}", entity.name),
+                DrawItem::Entity(entity) => entity.name.clone(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            order,
+            [
+                "bg tiles",
+                "bg",
+                "fg_back",
+                "behind first",
+                "behind second",
+                "first",
+                "second",
+                "behind spinner",
+                "spinner",
+                "bg_front",
+                "fg tiles",
+                "fg",
+            ]
+        );
+
+        // only the entities and what is drawn behind them
+        assert_eq!(draw_order(&room, Layer::ENTITIES).len(), 6);
+    }
+
+    #[test]
+    fn additive_room_canvas_matches_drawing_onto_the_map() {
+        let background = Color::from_rgba8(20, 40, 60, 255);
+        let map_bounds = Bounds {
+            position: Pos { x: 0, y: 0 },
+            size: (32, 16),
+        };
+        let room = room((8, 0), (16, 16));
+        let water = Rect::from_xywh(12.0, 4.0, 8.0, 8.0).unwrap();
+        let color = Color::from_rgba(0.1, 0.2, 0.3, 0.6).unwrap();
+
+        let new_map = || {
+            let mut pixmap = Pixmap::new(32, 16).unwrap();
+            pixmap.fill(background);
+            RenderContext::<NullLookup> {
+                map_bounds,
+                pixmap,
+                unknown_entities: Default::default(),
+                area_id: None,
+                _marker: PhantomData,
+            }
+        };
+
+        let mut direct = new_map();
+        direct.rect(water, color, BlendMode::Plus);
+
+        let mut composited = new_map();
+        let canvas_bounds = room.bounds.grow(4).intersection(map_bounds).unwrap();
+        let mut room_cx =
+            RenderContext::room_canvas(canvas_bounds, background, None, &room).unwrap();
+        let water_in_room = Rect::from_xywh(8.0, 4.0, 8.0, 8.0).unwrap();
+        room_cx.rect(water_in_room, color, BlendMode::Plus);
+        composited.composite(&room_cx);
+
+        assert!(direct.pixmap.data() == composited.pixmap.data());
+    }
+}
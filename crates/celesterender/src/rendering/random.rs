@@ -0,0 +1,352 @@
+//! Randomness the way the game does it, so that tile variants match what is seen in game.
+//!
+//! Celeste uses `Calc.Random`, a seeded .NET `System.Random`. When loading a map, the autotiler
+//! is run for the whole map at once with `MapData.LoadSeed` pushed, drawing one number per
+//! non-air tile in a fixed order, so every tile's variant only depends on the map and not on
+//! which rooms are rendered.
+
+use anyhow::Result;
+use celesteloader::map::Map;
+
+use super::tileset::{tiles_to_matrix, Matrix, AIR};
+
+/// The autotiler walks the map in segments of this many tiles
+const SEGMENT_SIZE: i32 = 50;
+
+/// Port of the seeded, legacy algorithm of .NET's `System.Random`
+pub struct DotnetRandom {
+    seed_array: [i32; 56],
+    inext: usize,
+    inextp: usize,
+}
+
+impl DotnetRandom {
+    const MBIG: i32 = i32::MAX;
+    const MSEED: i32 = 161803398;
+
+    pub fn new(seed: i32) -> Self {
+        let mut seed_array = [0; 56];
+
+        let subtraction = if seed == i32::MIN {
+            i32::MAX
+        } else {
+            seed.abs()
+        };
+        let mut mj = Self::MSEED - subtraction;
+        seed_array[55] = mj;
+        let mut mk = 1;
+        for i in 1..55 {
+            let ii = (21 * i) % 55;
+            seed_array[ii] = mk;
+            mk = mj - mk;
+            if mk < 0 {
+                mk += Self::MBIG;
+            }
+            mj = seed_array[ii];
+        }
+        for _ in 1..5 {
+            for i in 1..56 {
+                seed_array[i] = seed_array[i].wrapping_sub(seed_array[1 + (i + 30) % 55]);
+                if seed_array[i] < 0 {
+                    seed_array[i] += Self::MBIG;
+                }
+            }
+        }
+
+        DotnetRandom {
+            seed_array,
+            inext: 0,
+            inextp: 21,
+        }
+    }
+
+    /// `Random.Next()`, in `0..i32::MAX`
+    pub fn next(&mut self) -> i32 {
+        let mut inext = self.inext + 1;
+        if inext >= 56 {
+            inext = 1;
+        }
+        let mut inextp = self.inextp + 1;
+        if inextp >= 56 {
+            inextp = 1;
+        }
+
+        let mut value = self.seed_array[inext].wrapping_sub(self.seed_array[inextp]);
+        if value == Self::MBIG {
+            value -= 1;
+        }
+        if value < 0 {
+            value += Self::MBIG;
+        }
+
+        self.seed_array[inext] = value;
+        self.inext = inext;
+        self.inextp = inextp;
+        value
+    }
+
+    /// `Random.NextDouble()`, in `0.0..1.0`
+    pub fn sample(&mut self) -> f64 {
+        self.next() as f64 * (1.0 / Self::MBIG as f64)
+    }
+
+    /// `Random.Next(max)`, in `0..max`
+    pub fn next_max(&mut self, max: i32) -> i32 {
+        (self.sample() * max as f64) as i32
+    }
+
+    /// `Calc.Random.Choose(choices)`
+    pub fn choose<'a, T>(&mut self, choices: &'a [T]) -> Option<&'a T> {
+        if choices.is_empty() {
+            return None;
+        }
+        let index = self.next_max(choices.len() as i32);
+        choices.get(index as usize)
+    }
+}
+
+/// `LevelData.LoadSeed`: the sum of all characters of `text`
+pub fn load_seed(text: &str) -> i32 {
+    text.encode_utf16()
+        .fold(0i32, |acc, c| acc.wrapping_add(c as i32))
+}
+
+/// `MapData.LoadSeed` of the map with the given SID, e.g. `Celeste/1-ForsakenCity-B`.
+/// The seed is computed from the `AreaKey`, which marks B- and C-sides with `H` and `HH`.
+pub fn map_load_seed(sid: &str) -> i32 {
+    let sid = sid.trim_end_matches(".bin");
+    if let Some(sid) = sid.strip_suffix("-B") {
+        load_seed(&format!("{sid}H"))
+    } else if let Some(sid) = sid.strip_suffix("-C") {
+        load_seed(&format!("{sid}HH"))
+    } else {
+        load_seed(sid)
+    }
+}
+
+/// Seed for randomness of a single entity, computed like Lönn's `utils.setSimpleCoordinateSeed`,
+/// including the 32-bit wrapping of LuaJIT's `bit.lshift`.
+/// Lönn feeds the seed to LuaJIT's `math.random` though, so the values drawn from it differ.
+pub fn coordinate_seed(x: f32, y: f32) -> i32 {
+    let (x, y) = (x as i32, y as i32);
+    let shift = (y.unsigned_abs() as f64 + 1.0).log2().ceil() as u32;
+
+    (x.wrapping_shl(shift).unsigned_abs() as i64 + y.unsigned_abs() as i64) as i32
+}
+
+/// Autotiler samples for every tile of a room, indexed like the room's tile matrix
+pub(super) struct RoomTileRandom {
+    pub bg: Matrix<f64>,
+    pub fg: Matrix<f64>,
+}
+
+/// Draws the samples the game's autotiler uses to choose tile variants, for every room of the map.
+///
+/// The game fills the whole map into one grid and generates the background tiles, then the
+/// foreground tiles (fillers included), going through the grid in segments of 50x50 tiles,
+/// column by column.
+pub(super) fn autotile_random(map: &Map, seed: i32) -> Result<Vec<RoomTileRandom>> {
+    let mut left = i32::MAX;
+    let mut top = i32::MAX;
+    for room in &map.rooms {
+        let (x, y) = room.bounds.position_tiles();
+        left = left.min(x);
+        top = top.min(y);
+    }
+    for filler in &map.fillers {
+        left = left.min(filler.position.0);
+        top = top.min(filler.position.1);
+    }
+
+    let mut random = Vec::with_capacity(map.rooms.len());
+    let mut bg = Vec::with_capacity(map.rooms.len());
+    let mut fg = Vec::with_capacity(map.rooms.len());
+    for room in &map.rooms {
+        let size = room.bounds.size_tiles();
+        bg.push(tiles_to_matrix(size, &room.bg_tiles_raw)?);
+        fg.push(tiles_to_matrix(size, &room.fg_tiles_raw)?);
+        random.push(RoomTileRandom {
+            bg: Matrix::filled(0.0, size.0, size.1),
+            fg: Matrix::filled(0.0, size.0, size.1),
+        });
+    }
+
+    let mut rng = DotnetRandom::new(seed);
+    for is_fg in [false, true] {
+        // (segment x, segment y, x, y, source), where sources after the rooms are fillers
+        let mut order = Vec::new();
+        let mut push = |x: i32, y: i32, source: usize| {
+            let (x, y) = (x - left, y - top);
+            order.push((
+                x.div_euclid(SEGMENT_SIZE),
+                y.div_euclid(SEGMENT_SIZE),
+                x,
+                y,
+                source,
+            ));
+        };
+
+        let tiles = if is_fg { &fg } else { &bg };
+        for (i, (room, tiles)) in map.rooms.iter().zip(tiles).enumerate() {
+            let (room_x, room_y) = room.bounds.position_tiles();
+            let (w, h) = room.bounds.size_tiles();
+            for y in 0..h {
+                for x in 0..w {
+                    if tiles.get(x, y) != AIR {
+                        push(room_x + x as i32, room_y + y as i32, i);
+                    }
+                }
+            }
+        }
+        if is_fg {
+            for (i, filler) in map.fillers.iter().enumerate() {
+                let (x, y) = filler.position;
+                for offset_y in 0..filler.size.1 {
+                    for offset_x in 0..filler.size.0 {
+                        push(x + offset_x, y + offset_y, map.rooms.len() + i);
+                    }
+                }
+            }
+        }
+
+        order.sort_unstable();
+
+        // where things overlap, the one written last into the grid decides the tile
+        let mut entries = order.iter().peekable();
+        while let Some(&(_, _, x, y, source)) = entries.next() {
+            if entries
+                .peek()
+                .is_some_and(|&&(_, _, next_x, next_y, _)| (next_x, next_y) == (x, y))
+            {
+                continue;
+            }
+
+            let sample = rng.sample();
+            let Some(room) = map.rooms.get(source) else {
+                continue;
+            };
+            let (room_x, room_y) = room.bounds.position_tiles();
+            let room_random = &mut random[source];
+            let matrix = if is_fg {
+                &mut room_random.fg
+            } else {
+                &mut room_random.bg
+            };
+            matrix.set(
+                (x + left - room_x) as u32,
+                (y + top - room_y) as u32,
+                sample,
+            );
+        }
+    }
+
+    Ok(random)
+}
+
+#[cfg(test)]
+mod tests {
+    use celesteloader::map::{Filler, Room};
+
+    use super::*;
+
+    #[test]
+    fn dotnet_random_known_values() {
+        let mut rng = DotnetRandom::new(0);
+        let values: Vec<_> = (0..5).map(|_| rng.next()).collect();
+        assert_eq!(
+            values,
+            [1559595546, 1755192844, 1649316166, 1198642031, 442452829]
+        );
+
+        let mut rng = DotnetRandom::new(1);
+        assert_eq!(rng.next(), 534011718);
+        assert_eq!(rng.next(), 237820880);
+
+        let mut rng = DotnetRandom::new(0);
+        assert_eq!(rng.sample(), 1559595546.0 / i32::MAX as f64);
+        assert_eq!(rng.next_max(10), 8);
+
+        // negative seeds behave like their absolute value
+        assert_eq!(DotnetRandom::new(-1).next(), 534011718);
+        DotnetRandom::new(i32::MIN).next();
+    }
+
+    #[test]
+    fn seeds() {
+        assert_eq!(load_seed("ab"), 97 + 98);
+        assert_eq!(
+            map_load_seed("Celeste/1-ForsakenCity-B"),
+            load_seed("Celeste/1-ForsakenCityH")
+        );
+        assert_eq!(
+            map_load_seed("Celeste/1-ForsakenCity-C.bin"),
+            load_seed("Celeste/1-ForsakenCityHH")
+        );
+
+        assert_eq!(coordinate_seed(8.0, 0.0), 8);
+        assert_eq!(coordinate_seed(8.0, 7.0), 8 * 8 + 7);
+        assert_eq!(coordinate_seed(-3.0, -1.0), 6 + 1);
+        // `bit.lshift` is 32-bit
+        assert_eq!(coordinate_seed((1 << 30) as f32, 1.0), i32::MIN + 1);
+    }
+
+    fn room(x: i32, y: i32, size: (u32, u32), fg: &str, bg: &str) -> Room {
+        Room {
+            fg_tiles_raw: fg.to_owned(),
+            bg_tiles_raw: bg.to_owned(),
+            ..super::super::tests::room((x * 8, y * 8), (size.0 * 8, size.1 * 8))
+        }
+    }
+
+    #[test]
+    fn autotile_order() {
+        // offset, to check segments are relative to the top left of the map
+        let (x, y) = (-10, -3);
+        let mut map = super::super::tests::map(vec![
+            room(x, y, (2, 2), "11\n11", "00\n01"),
+            // next segment to the right
+            room(x + SEGMENT_SIZE, y, (1, 1), "1", ""),
+            // next segment down, drawn before the one to the right
+            room(x, y + SEGMENT_SIZE, (1, 1), "1", ""),
+        ]);
+        map.fillers.push(Filler {
+            position: (x + 2, y),
+            size: (1, 1),
+        });
+
+        let random = autotile_random(&map, 0).unwrap();
+
+        let mut rng = DotnetRandom::new(0);
+        let mut samples = std::iter::repeat_with(|| rng.sample());
+        let mut next = || samples.next().unwrap();
+
+        // background first
+        assert_eq!(random[0].bg.get(1, 1), next());
+        assert_eq!(random[0].bg.get(0, 0), 0.0);
+        // then foreground, column by column within a segment
+        assert_eq!(random[0].fg.get(0, 0), next());
+        assert_eq!(random[0].fg.get(0, 1), next());
+        assert_eq!(random[0].fg.get(1, 0), next());
+        assert_eq!(random[0].fg.get(1, 1), next());
+        // the filler consumes a sample
+        next();
+        assert_eq!(random[2].fg.get(0, 0), next());
+        assert_eq!(random[1].fg.get(0, 0), next());
+    }
+
+    #[test]
+    fn filler_overrides_room_tiles() {
+        let mut map = super::super::tests::map(vec![room(0, 0, (1, 2), "1\n1", "")]);
+        map.fillers.push(Filler {
+            position: (0, 0),
+            size: (1, 1),
+        });
+
+        let random = autotile_random(&map, 0).unwrap();
+
+        let mut rng = DotnetRandom::new(0);
+        rng.sample();
+        assert_eq!(random[0].fg.get(0, 0), 0.0);
+        assert_eq!(random[0].fg.get(0, 1), rng.sample());
+    }
+}
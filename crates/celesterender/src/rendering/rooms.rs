@@ -0,0 +1,339 @@
+//! Rendering every room into an image of its own, e.g. for wikis, with a `manifest.json` describing them.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::File,
+    io::BufWriter,
+    marker::PhantomData,
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use celesteloader::map::{Bounds, Map, Room};
+use rayon::prelude::*;
+use serde::Serialize;
+use tiny_skia::Pixmap;
+
+use crate::asset::{AssetDb, LookupAsset};
+
+use super::{
+    draw_room_overlays, encode_png, room_canvas_bounds, scale_image, CelesteRenderData, MapRooms,
+    RenderContext, RenderMapSettings,
+};
+
+pub struct RoomImage<'a> {
+    pub room: &'a Room,
+    /// Part of the map the image shows, which is the room extended by the padding
+    pub bounds: Bounds,
+    /// Image of `bounds` at [`RenderMapSettings::scale`]
+    pub image: Pixmap,
+    pub unknown_entities: BTreeMap<String, u32>,
+}
+
+/// Renders every room included by [`RenderMapSettings::include_room`] into its own image,
+/// calling `on_room` for each in map order. Returns the unknown entities of all included rooms.
+///
+/// The images extend `padding` pixels into the neighbouring rooms, which are drawn even if they aren't included.
+#[tracing::instrument(skip_all, fields(name = map.package))]
+pub fn render_rooms<'a, L: LookupAsset>(
+    render_data: &CelesteRenderData,
+    asset_db: &AssetDb<L>,
+    map: &'a Map,
+    settings: RenderMapSettings,
+    padding: u32,
+    mut on_room: impl FnMut(RoomImage<'a>) -> Result<()>,
+) -> Result<BTreeMap<String, u32>> {
+    let image_bounds = map
+        .rooms
+        .iter()
+        .filter(|room| (settings.include_room)(room))
+        .map(|room| room.bounds.grow(padding))
+        .collect::<Vec<_>>();
+    let visible = |room: &Room| {
+        image_bounds
+            .iter()
+            .any(|bounds| bounds.intersection(room.bounds).is_some())
+    };
+
+    let MapRooms {
+        rooms,
+        map_bounds,
+        area_id,
+    } = MapRooms::new(
+        map,
+        &RenderMapSettings {
+            include_room: &visible,
+            ..settings
+        },
+    )?;
+
+    let canvas_bounds = rooms
+        .iter()
+        .map(|(room, _)| room_canvas_bounds(map_bounds, room, render_data, asset_db))
+        .collect::<Vec<_>>();
+    let images = rooms
+        .iter()
+        .enumerate()
+        .filter(|(_, (room, _))| (settings.include_room)(room))
+        .map(|(i, (room, _))| (i, room.bounds.grow(padding)))
+        .collect::<Vec<_>>();
+    let shown_in = |bounds: Bounds| {
+        (0..rooms.len())
+            .filter(|&i| canvas_bounds[i].intersection(bounds).is_some())
+            .collect::<Vec<_>>()
+    };
+
+    // rooms are rendered when the first image showing them comes up and dropped after the last one
+    let mut last_shown = vec![0; rooms.len()];
+    for (image, &(_, bounds)) in images.iter().enumerate() {
+        for i in shown_in(bounds) {
+            last_shown[i] = image;
+        }
+    }
+    let mut rendered: BTreeMap<usize, RenderContext<L>> = BTreeMap::new();
+
+    (settings.status_update)(0, images.len());
+
+    let layer = settings.layer;
+    let mut unknown_entities = BTreeMap::new();
+    for (image, &(i, bounds)) in images.iter().enumerate() {
+        let room = rooms[i].0;
+        let shown = shown_in(bounds);
+
+        let missing = shown
+            .iter()
+            .copied()
+            .filter(|j| !rendered.contains_key(j))
+            .collect::<Vec<_>>();
+        let new_rooms = missing
+            .into_par_iter()
+            .map(|j| {
+                let (room, tile_random) = &rooms[j];
+                let room_cx = RenderContext::<L>::render_room_canvas(
+                    canvas_bounds[j],
+                    settings.background,
+                    area_id,
+                    room,
+                    tile_random,
+                    &map.style,
+                    render_data,
+                    asset_db,
+                    layer,
+                );
+                (j, room_cx)
+            })
+            .collect::<Vec<_>>();
+        for (j, room_cx) in new_rooms {
+            rendered.insert(j, room_cx?);
+        }
+
+        let mut pixmap = Pixmap::new(bounds.size.0, bounds.size.1)
+            .with_context(|| format!("failed to create pixmap for room {}", room.name))?;
+        pixmap.fill(settings.background);
+
+        let mut cx = RenderContext {
+            map_bounds: bounds,
+            pixmap,
+            unknown_entities: Default::default(),
+            area_id,
+            _marker: PhantomData::<L>,
+        };
+        cx.draw_fillers(map, &settings);
+        for j in &shown {
+            cx.composite(&rendered[j]);
+        }
+
+        let mut image_pixmap = scale_image(cx.pixmap, settings.scale)?;
+        let overlay_rooms = rooms
+            .iter()
+            .map(|&(room, _)| room)
+            .filter(|room| room.bounds.intersection(bounds).is_some())
+            .collect::<Vec<_>>();
+        draw_room_overlays(
+            &mut image_pixmap,
+            bounds,
+            settings.scale,
+            &overlay_rooms,
+            &settings,
+        );
+
+        let room_unknown = rendered
+            .get_mut(&i)
+            .map(|room_cx| std::mem::take(&mut room_cx.unknown_entities))
+            .unwrap_or_default();
+        for (name, &count) in &room_unknown {
+            *unknown_entities.entry(name.clone()).or_default() += count;
+        }
+
+        rendered.retain(|&j, _| last_shown[j] > image);
+
+        on_room(RoomImage {
+            room,
+            bounds,
+            image: image_pixmap,
+            unknown_entities: room_unknown,
+        })?;
+
+        if image + 1 < images.len() {
+            (settings.status_update)(image + 1, images.len());
+        }
+    }
+
+    Ok(unknown_entities)
+}
+
+/// Renders every included room into `dir` as `{room name}.png`, together with a `manifest.json`
+/// listing them, see [`render_rooms`]. Room names are made into valid and distinct file names first.
+pub fn render_rooms_to_dir<L: LookupAsset>(
+    render_data: &CelesteRenderData,
+    asset_db: &AssetDb<L>,
+    map: &Map,
+    settings: RenderMapSettings,
+    padding: u32,
+    dir: &Path,
+    compression: png::Compression,
+) -> Result<RoomManifest> {
+    std::fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+
+    let mut rooms = Vec::new();
+    let mut file_names = HashSet::new();
+    let unknown_entities = render_rooms(
+        render_data,
+        asset_db,
+        map,
+        settings,
+        padding,
+        |room_image| {
+            let image = format!("{}.png", file_name(&room_image.room.name, &mut file_names));
+            let path = dir.join(&image);
+            let file = File::create(&path)
+                .with_context(|| format!("failed to create {}", path.display()))?;
+            encode_png(room_image.image, BufWriter::new(file), compression)?;
+
+            let mut entities = BTreeMap::new();
+            for entity in &room_image.room.entities {
+                *entities.entry(entity.name.clone()).or_default() += 1;
+            }
+            rooms.push(ManifestRoom {
+                name: room_image.room.name.clone(),
+                image,
+                bounds: room_image.room.bounds.into(),
+                image_bounds: room_image.bounds.into(),
+                entities,
+                unknown_entities: room_image.unknown_entities,
+            });
+            Ok(())
+        },
+    )?;
+
+    let manifest = RoomManifest {
+        map: settings.sid.unwrap_or(&map.package).to_owned(),
+        padding,
+        rooms,
+        unknown_entities,
+    };
+
+    let path = dir.join("manifest.json");
+    let file =
+        File::create(&path).with_context(|| format!("failed to create {}", path.display()))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), &manifest)?;
+
+    Ok(manifest)
+}
+
+/// File name for the image of a room, without extension, which is valid on all platforms and
+/// not in `used` yet. Names are compared ignoring case, as file systems often do.
+fn file_name(room: &str, used: &mut HashSet<String>) -> String {
+    const RESERVED: &[&str] = &[
+        "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+        "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+    ];
+
+    let mut name: String = room
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    // windows drops trailing dots and spaces
+    let trimmed = name.trim_end_matches(['.', ' ']).len();
+    name.replace_range(trimmed.., &"_".repeat(name.len() - trimmed));
+    if name.is_empty()
+        || RESERVED
+            .iter()
+            .any(|reserved| name.eq_ignore_ascii_case(reserved))
+    {
+        name.push('_');
+    }
+
+    let mut unique = name.clone();
+    let mut n = 2;
+    while !used.insert(unique.to_lowercase()) {
+        unique = format!("{name}-{n}");
+        n += 1;
+    }
+    unique
+}
+
+/// Contents of the `manifest.json` written by [`render_rooms_to_dir`]
+#[derive(Serialize)]
+pub struct RoomManifest {
+    pub map: String,
+    pub padding: u32,
+    pub rooms: Vec<ManifestRoom>,
+    pub unknown_entities: BTreeMap<String, u32>,
+}
+
+#[derive(Serialize)]
+pub struct ManifestRoom {
+    pub name: String,
+    /// File name of the image, next to the manifest
+    pub image: String,
+    pub bounds: ManifestBounds,
+    /// Part of the map the image shows
+    pub image_bounds: ManifestBounds,
+    /// Number of entities of each kind
+    pub entities: BTreeMap<String, u32>,
+    pub unknown_entities: BTreeMap<String, u32>,
+}
+
+#[derive(Serialize, Clone, Copy)]
+pub struct ManifestBounds {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+impl From<Bounds> for ManifestBounds {
+    fn from(bounds: Bounds) -> Self {
+        ManifestBounds {
+            x: bounds.position.x,
+            y: bounds.position.y,
+            width: bounds.size.0,
+            height: bounds.size.1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_names() {
+        let mut used = HashSet::new();
+        let mut name = |room| file_name(room, &mut used);
+
+        assert_eq!(name("a-00"), "a-00");
+        assert_eq!(name("a/b"), "a_b");
+        assert_eq!(name("a_b"), "a_b-2");
+        assert_eq!(name("A/B"), "A_B-3");
+        assert_eq!(name("c\\:*?\"<>|\t"), "c_________");
+        assert_eq!(name("d. "), "d__");
+        assert_eq!(name("con"), "con_");
+        assert_eq!(name(""), "_");
+        assert_eq!(name("a-00"), "a-00-2");
+    }
+}
@@ -0,0 +1,242 @@
+//! Static approximation of a room's background stylegrounds, as seen with the camera at the room's top left.
+//!
+//! Parallax layers are placed using their position and scroll factor, effects like stars and snow are
+//! replaced by a random scattering of dots. Layers depending on a flag are hidden, as the flag isn't set
+//! when entering the room.
+
+use anyhow::Result;
+use celesteloader::map::{
+    style::{Style, Styleground, StylegroundKind},
+    Room,
+};
+use tiny_skia::{BlendMode, Color, Paint, Pattern, Pixmap, PixmapPaint, Rect, Transform};
+
+use crate::{
+    asset::{AssetDb, LookupAsset, SpriteLocation},
+    CelesteRenderData,
+};
+
+use super::{entity::parse_color, RenderContext};
+
+/// Dots per pixel of room area
+const STAR_DENSITY: f32 = 1.0 / 300.0;
+const SNOW_DENSITY: f32 = 1.0 / 500.0;
+
+pub(super) fn render_backgrounds<L: LookupAsset>(
+    r: &mut RenderContext<L>,
+    room: &Room,
+    style: &Style,
+    cx: &CelesteRenderData,
+    asset_db: &AssetDb<L>,
+) -> Result<()> {
+    let room_rect = r.transform_bounds(room.bounds);
+    let clear = style
+        .color
+        .as_deref()
+        .and_then(|color| parse_color(color.trim_start_matches('#')).ok())
+        .unwrap_or(Color::BLACK);
+    r.rect(room_rect, clear, BlendMode::Source);
+
+    let mut rng = fastrand::Rng::with_seed(
+        (room.bounds.position.x as u64) << 32 | room.bounds.position.y as u32 as u64,
+    );
+
+    for styleground in &style.backgrounds {
+        if !styleground.visible_in(&room.name) || styleground.flag.is_some() {
+            continue;
+        }
+
+        match &styleground.kind {
+            StylegroundKind::Parallax { texture, additive } => {
+                let Ok(sprite) = asset_db.lookup_gameplay(cx, texture) else {
+                    continue;
+                };
+                let texture = texture_pixmap(cx, &sprite, styleground);
+                parallax(r, room, room_rect, &texture, styleground, *additive);
+            }
+            StylegroundKind::Effect { name } => {
+                effect(r, room_rect, &mut rng, name, styleground);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Copies the sprite out of its atlas and applies the styleground's tint
+fn texture_pixmap(
+    cx: &CelesteRenderData,
+    sprite: &SpriteLocation,
+    styleground: &Styleground,
+) -> Pixmap {
+    let mut pixmap = match sprite {
+        SpriteLocation::Atlas(sprite) => {
+            let mut pixmap =
+                Pixmap::new(sprite.real_w.max(1) as u32, sprite.real_h.max(1) as u32).unwrap();
+            let atlas_region = cx
+                .gameplay_atlas
+                .clone_rect(
+                    tiny_skia::IntRect::from_xywh(
+                        sprite.x as i32,
+                        sprite.y as i32,
+                        sprite.w.max(1) as u32,
+                        sprite.h.max(1) as u32,
+                    )
+                    .unwrap(),
+                )
+                .unwrap();
+            pixmap.draw_pixmap(
+                -sprite.offset_x as i32,
+                -sprite.offset_y as i32,
+                atlas_region.as_ref(),
+                &PixmapPaint::default(),
+                Transform::identity(),
+                None,
+            );
+            pixmap
+        }
+        SpriteLocation::Raw(pixmap) => Pixmap::clone(pixmap),
+    };
+
+    let tint = styleground
+        .color
+        .as_deref()
+        .and_then(|color| parse_color(color.trim_start_matches('#')).ok())
+        .map(|color| color.to_color_u8());
+    if let Some(tint) =
+        tint.filter(|tint| tint.red() != 255 || tint.green() != 255 || tint.blue() != 255)
+    {
+        for pixel in pixmap.pixels_mut() {
+            let mul = |a: u8, b: u8| (a as u16 * b as u16 / 255) as u8;
+            *pixel = tiny_skia::PremultipliedColorU8::from_rgba(
+                mul(pixel.red(), tint.red()),
+                mul(pixel.green(), tint.green()),
+                mul(pixel.blue(), tint.blue()),
+                pixel.alpha(),
+            )
+            .unwrap();
+        }
+    }
+
+    pixmap
+}
+
+fn parallax<L: LookupAsset>(
+    r: &mut RenderContext<L>,
+    room: &Room,
+    room_rect: Rect,
+    texture: &Pixmap,
+    styleground: &Styleground,
+    additive: bool,
+) {
+    let (w, h) = (texture.width() as f32, texture.height() as f32);
+
+    // screen position with the camera at the room's top left, moved to where the room is in the image
+    let camera = (room.bounds.position.x as f32, room.bounds.position.y as f32);
+    let x = (styleground.position.0 - camera.0 * styleground.scroll.0).floor() + room_rect.x();
+    let y = (styleground.position.1 - camera.1 * styleground.scroll.1).floor() + room_rect.y();
+
+    let (left, right) = if styleground.loop_x {
+        (room_rect.left(), room_rect.right())
+    } else {
+        (x.max(room_rect.left()), (x + w).min(room_rect.right()))
+    };
+    let (top, bottom) = if styleground.loop_y {
+        (room_rect.top(), room_rect.bottom())
+    } else {
+        (y.max(room_rect.top()), (y + h).min(room_rect.bottom()))
+    };
+    let Some(rect) = Rect::from_ltrb(left, top, right, bottom) else {
+        return;
+    };
+
+    let pattern_transform = Transform::from_row(
+        if styleground.flip_x { -1.0 } else { 1.0 },
+        0.0,
+        0.0,
+        if styleground.flip_y { -1.0 } else { 1.0 },
+        if styleground.flip_x { x + w } else { x },
+        if styleground.flip_y { y + h } else { y },
+    );
+
+    r.pixmap.fill_rect(
+        rect,
+        &Paint {
+            shader: Pattern::new(
+                texture.as_ref(),
+                tiny_skia::SpreadMode::Repeat,
+                tiny_skia::FilterQuality::Nearest,
+                styleground.alpha.clamp(0.0, 1.0),
+                pattern_transform,
+            ),
+            blend_mode: if additive {
+                BlendMode::Plus
+            } else {
+                BlendMode::SourceOver
+            },
+            anti_alias: false,
+            ..Default::default()
+        },
+        Transform::identity(),
+        None,
+    );
+}
+
+fn effect<L: LookupAsset>(
+    r: &mut RenderContext<L>,
+    room_rect: Rect,
+    rng: &mut fastrand::Rng,
+    name: &str,
+    styleground: &Styleground,
+) {
+    let lowercase = name.to_ascii_lowercase();
+    let area = room_rect.width() * room_rect.height();
+
+    if lowercase.contains("star") {
+        for _ in 0..(area * STAR_DENSITY) as usize {
+            let alpha = 0.3 + rng.f32() * 0.7;
+            dot(
+                r,
+                room_rect,
+                rng,
+                1.0,
+                Color::from_rgba(1.0, 1.0, 1.0, alpha).unwrap(),
+            );
+        }
+    } else if lowercase.contains("snow") {
+        for _ in 0..(area * SNOW_DENSITY) as usize {
+            let size = if rng.bool() { 1.0 } else { 2.0 };
+            dot(
+                r,
+                room_rect,
+                rng,
+                size,
+                Color::from_rgba(1.0, 1.0, 1.0, 0.8).unwrap(),
+            );
+        }
+    } else if lowercase.contains("tint") || lowercase.contains("overlay") {
+        let Some(mut color) = styleground
+            .color
+            .as_deref()
+            .and_then(|color| parse_color(color.trim_start_matches('#')).ok())
+        else {
+            return;
+        };
+        color.apply_opacity(styleground.alpha.clamp(0.0, 1.0));
+        r.rect(room_rect, color, BlendMode::SourceOver);
+    }
+}
+
+fn dot<L: LookupAsset>(
+    r: &mut RenderContext<L>,
+    room_rect: Rect,
+    rng: &mut fastrand::Rng,
+    size: f32,
+    color: Color,
+) {
+    let x = room_rect.x() + (rng.f32() * (room_rect.width() - size)).floor();
+    let y = room_rect.y() + (rng.f32() * (room_rect.height() - size)).floor();
+    if let Some(rect) = Rect::from_xywh(x, y, size, size) {
+        r.rect(rect, color, BlendMode::SourceOver);
+    }
+}
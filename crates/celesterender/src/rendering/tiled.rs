@@ -0,0 +1,473 @@
+//! Rendering a map in fixed-size tiles, for maps too big to fit into memory as one image.
+//!
+//! The tiles are produced row by row. Rooms are rendered once the current row reaches them and
+//! dropped once it is past them, so only the rooms around the current row are kept in memory.
+//! Tiles not touched by any room or drawn filler are skipped.
+
+use std::{collections::BTreeMap, io::Write, marker::PhantomData, path::Path};
+
+use anyhow::{ensure, Context, Result};
+use celesteloader::map::{Bounds, Map, Pos};
+use rayon::prelude::*;
+use tiny_skia::Pixmap;
+
+use crate::asset::{AssetDb, LookupAsset};
+
+use super::{
+    demultiply, draw_room_overlays, drawn_fillers, encode_png, png_encoder, room_canvas_bounds,
+    CelesteRenderData, MapRooms, RenderContext, RenderMapSettings,
+};
+
+/// Height of the bands [`render_png_streaming`] renders at once
+const STREAMING_BAND_HEIGHT: u32 = 256;
+
+pub struct RenderTile {
+    pub column: u32,
+    pub row: u32,
+    /// Part of the map the tile shows. Tiles at the right and bottom edge may be smaller than the tile size.
+    pub bounds: Bounds,
+    pub image: Pixmap,
+}
+
+pub struct TiledRenderResult {
+    /// Bounds of the whole map, with the first tile at its top left
+    pub bounds: Bounds,
+    pub tile_size: (u32, u32),
+    pub columns: u32,
+    pub rows: u32,
+    /// Number of tiles which weren't skipped for being empty
+    pub tiles: usize,
+    pub unknown_entities: BTreeMap<String, u32>,
+}
+
+/// Renders the map in tiles of `tile_size` pixels, calling `on_tile` for each non-empty tile,
+/// from left to right and top to bottom.
+#[tracing::instrument(skip_all, fields(name = map.package))]
+pub fn render_tiled<L: LookupAsset>(
+    render_data: &CelesteRenderData,
+    asset_db: &AssetDb<L>,
+    map: &Map,
+    settings: RenderMapSettings,
+    tile_size: (u32, u32),
+    on_tile: impl FnMut(RenderTile) -> Result<()>,
+) -> Result<TiledRenderResult> {
+    let rooms = MapRooms::new(map, &settings)?;
+    render_rooms_tiled(
+        render_data,
+        asset_db,
+        map,
+        &settings,
+        rooms,
+        tile_size,
+        on_tile,
+    )
+}
+
+/// Renders the map into `dir` as `{column}_{row}.png` tiles of `tile_size`x`tile_size` pixels, skipping empty tiles
+pub fn render_tiles_to_dir<L: LookupAsset>(
+    render_data: &CelesteRenderData,
+    asset_db: &AssetDb<L>,
+    map: &Map,
+    settings: RenderMapSettings,
+    tile_size: u32,
+    dir: &Path,
+    compression: png::Compression,
+) -> Result<TiledRenderResult> {
+    std::fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+
+    render_tiled(
+        render_data,
+        asset_db,
+        map,
+        settings,
+        (tile_size, tile_size),
+        |tile| {
+            let path = dir.join(format!("{}_{}.png", tile.column, tile.row));
+            let file = std::fs::File::create(&path)
+                .with_context(|| format!("failed to create {}", path.display()))?;
+            encode_png(tile.image, std::io::BufWriter::new(file), compression)?;
+            Ok(())
+        },
+    )
+}
+
+/// Renders the map as a single PNG, encoded in bands of rows as they are rendered,
+/// so the full image is never held in memory.
+pub fn render_png_streaming<L: LookupAsset>(
+    render_data: &CelesteRenderData,
+    asset_db: &AssetDb<L>,
+    map: &Map,
+    settings: RenderMapSettings,
+    w: impl Write,
+    compression: png::Compression,
+) -> Result<TiledRenderResult> {
+    let rooms = MapRooms::new(map, &settings)?;
+    let (width, height) = rooms.map_bounds.size;
+
+    let encoder = png_encoder(w, (width, height), compression);
+    let mut writer = encoder.write_header()?;
+    let mut stream = writer.stream_writer()?;
+
+    // bands without any rooms or fillers are skipped, so their rows are filled with the background
+    let background = settings.background.to_color_u8();
+    let empty_row = [
+        background.red(),
+        background.green(),
+        background.blue(),
+        background.alpha(),
+    ]
+    .repeat(width as usize);
+    let fill_rows = |stream: &mut png::StreamWriter<_>, from: u32, to: u32| -> Result<()> {
+        for _ in from..to {
+            stream.write_all(&empty_row)?;
+        }
+        Ok(())
+    };
+
+    let mut rows_written = 0;
+    let result = render_rooms_tiled(
+        render_data,
+        asset_db,
+        map,
+        &settings,
+        rooms,
+        (width, STREAMING_BAND_HEIGHT),
+        |mut band| {
+            let top = band.row * STREAMING_BAND_HEIGHT;
+            fill_rows(&mut stream, rows_written, top)?;
+            demultiply(&mut band.image);
+            stream.write_all(band.image.data())?;
+            rows_written = top + band.bounds.size.1;
+            Ok(())
+        },
+    )?;
+    fill_rows(&mut stream, rows_written, height)?;
+
+    stream.finish()?;
+    writer.finish()?;
+
+    Ok(result)
+}
+
+fn render_rooms_tiled<L: LookupAsset>(
+    render_data: &CelesteRenderData,
+    asset_db: &AssetDb<L>,
+    map: &Map,
+    settings: &RenderMapSettings,
+    map_rooms: MapRooms,
+    tile_size: (u32, u32),
+    mut on_tile: impl FnMut(RenderTile) -> Result<()>,
+) -> Result<TiledRenderResult> {
+    ensure!(
+        tile_size.0 > 0 && tile_size.1 > 0,
+        "tile size must not be zero"
+    );
+    ensure!(
+        !settings.is_scaled(),
+        "tiled rendering doesn't support scaling"
+    );
+
+    let MapRooms {
+        rooms,
+        map_bounds,
+        area_id,
+    } = map_rooms;
+    let canvases = rooms
+        .iter()
+        .map(|(room, _)| room_canvas_bounds(map_bounds, room, render_data, asset_db))
+        .collect::<Vec<_>>();
+    let room_list = rooms.iter().map(|&(room, _)| room).collect::<Vec<_>>();
+    let fillers = drawn_fillers(map, settings).collect::<Vec<_>>();
+
+    let columns = map_bounds.size.0.div_ceil(tile_size.0);
+    let rows = map_bounds.size.1.div_ceil(tile_size.1);
+
+    let mut unknown_entities = BTreeMap::new();
+    let mut tiles = 0;
+
+    let mut rendered = vec![false; rooms.len()];
+    let mut rendered_count = 0;
+    // rooms touching the current row, by index so they are composited in map order
+    let mut current: BTreeMap<usize, RenderContext<L>> = BTreeMap::new();
+
+    (settings.status_update)(0, rooms.len());
+    for row in 0..rows {
+        let row_bounds = tile_bounds(map_bounds, tile_size, (0, row), columns);
+
+        let new_rooms = (0..rooms.len())
+            .filter(|&i| !rendered[i] && canvases[i].intersection(row_bounds).is_some())
+            .collect::<Vec<_>>();
+        let layer = settings.layer;
+        let background = settings.background;
+        let new_rooms = new_rooms
+            .into_par_iter()
+            .map(|i| {
+                let (room, tile_random) = &rooms[i];
+                let room_cx = RenderContext::render_room_canvas(
+                    canvases[i],
+                    background,
+                    area_id,
+                    room,
+                    tile_random,
+                    &map.style,
+                    render_data,
+                    asset_db,
+                    layer,
+                );
+                (i, room_cx)
+            })
+            .collect::<Vec<_>>();
+        for (i, room_cx) in new_rooms {
+            let mut room_cx = room_cx?;
+            for (name, count) in std::mem::take(&mut room_cx.unknown_entities) {
+                *unknown_entities.entry(name).or_default() += count;
+            }
+            rendered[i] = true;
+            current.insert(i, room_cx);
+
+            rendered_count += 1;
+            if rendered_count < rooms.len() {
+                (settings.status_update)(rendered_count, rooms.len());
+            }
+        }
+
+        for column in 0..columns {
+            let bounds = tile_bounds(map_bounds, tile_size, (column, row), 1);
+            let mut overlapping = current
+                .values()
+                .filter(|room_cx| room_cx.map_bounds.intersection(bounds).is_some())
+                .peekable();
+            let has_filler = fillers
+                .iter()
+                .any(|filler| filler.intersection(bounds).is_some());
+            if overlapping.peek().is_none() && !has_filler {
+                continue;
+            }
+
+            let mut pixmap = Pixmap::new(bounds.size.0, bounds.size.1)
+                .context("failed to create pixmap for tile")?;
+            pixmap.fill(settings.background);
+
+            let mut tile_cx = RenderContext {
+                map_bounds: bounds,
+                pixmap,
+                unknown_entities: Default::default(),
+                area_id,
+                _marker: PhantomData::<L>,
+            };
+            tile_cx.draw_fillers(map, settings);
+            for room_cx in overlapping {
+                tile_cx.composite(room_cx);
+            }
+            draw_room_overlays(&mut tile_cx.pixmap, bounds, 1.0, &room_list, settings);
+
+            tiles += 1;
+            on_tile(RenderTile {
+                column,
+                row,
+                bounds,
+                image: tile_cx.pixmap,
+            })?;
+        }
+
+        current.retain(|_, room_cx| room_cx.map_bounds.b() > row_bounds.b());
+    }
+
+    Ok(TiledRenderResult {
+        bounds: map_bounds,
+        tile_size,
+        columns,
+        rows,
+        tiles,
+        unknown_entities,
+    })
+}
+
+/// Bounds of `count` tiles starting at `(column, row)`, cut off at the edge of the map
+fn tile_bounds(
+    map_bounds: Bounds,
+    tile_size: (u32, u32),
+    (column, row): (u32, u32),
+    count: u32,
+) -> Bounds {
+    let x = column * tile_size.0;
+    let y = row * tile_size.1;
+    Bounds {
+        position: Pos {
+            x: map_bounds.position.x + x as i32,
+            y: map_bounds.position.y + y as i32,
+        },
+        size: (
+            (count * tile_size.0).min(map_bounds.size.0 - x),
+            tile_size.1.min(map_bounds.size.1 - y),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use celesteloader::map::{
+        decode::ElementOwned,
+        style::{Styleground, StylegroundKind},
+        EntityNode, Filler, Trigger,
+    };
+    use tiny_skia::{BlendMode, Color, PixmapPaint, Transform};
+
+    use super::*;
+    use crate::{
+        asset::NullLookup,
+        rendering::{
+            render,
+            tests::{empty_render_data, map, room},
+            Layer,
+        },
+    };
+
+    const TILE_SIZE: (u32, u32) = (40, 28);
+
+    /// Three rooms, one with a trigger reaching out of it, with stars in the background and a filler
+    /// far enough from the rooms that the tiles around it contain nothing else
+    fn test_map() -> Map {
+        let mut a = room((0, 0), (64, 48));
+        a.name = "a".into();
+        a.triggers.push(Trigger {
+            id: None,
+            position: (8.0, 8.0),
+            extents: (24, 16),
+            name: "cameraOffsetTrigger".into(),
+            nodes: vec![EntityNode {
+                position: (40.0, 60.0),
+            }],
+        });
+        let mut b = room((240, 16), (64, 64));
+        b.name = "b".into();
+        let mut c = room((32, 200), (80, 40));
+        c.name = "c".into();
+
+        let mut map = map(vec![a, b, c]);
+        map.fillers.push(Filler {
+            position: (20, 2),
+            size: (4, 2),
+        });
+        map.style.color = Some("102030".into());
+        map.style.backgrounds.push(Styleground {
+            kind: StylegroundKind::Effect {
+                name: "stars".into(),
+            },
+            only: None,
+            exclude: None,
+            flag: None,
+            not_flag: None,
+            position: (0.0, 0.0),
+            scroll: (1.0, 1.0),
+            speed: (0.0, 0.0),
+            loop_x: true,
+            loop_y: true,
+            flip_x: false,
+            flip_y: false,
+            color: None,
+            alpha: 1.0,
+            raw: ElementOwned {
+                name: "stars".into(),
+                attributes: Default::default(),
+                children: Vec::new(),
+            },
+        });
+        map
+    }
+
+    fn settings() -> RenderMapSettings<'static> {
+        RenderMapSettings::default()
+            .layer(Layer::ALL)
+            .fillers(Color::from_rgba8(200, 100, 0, 255))
+            .room_outlines(Color::from_rgba8(255, 0, 0, 128))
+            .room_labels(true)
+    }
+
+    #[test]
+    fn tiles_match_full_render() {
+        let map = test_map();
+        let render_data = empty_render_data();
+        let asset_db = AssetDb::new(NullLookup);
+
+        let full = render(&render_data, &asset_db, &map, settings()).unwrap();
+        assert_eq!(full.image.width(), 304);
+        assert_eq!(full.image.height(), 240);
+
+        let mut stitched = Pixmap::new(304, 240).unwrap();
+        stitched.fill(settings().background);
+        let mut tiles = Vec::new();
+        let result = render_tiled(
+            &render_data,
+            &asset_db,
+            &map,
+            settings(),
+            TILE_SIZE,
+            |tile| {
+                let (x, y) = (tile.column * TILE_SIZE.0, tile.row * TILE_SIZE.1);
+                assert_eq!(
+                    (tile.bounds.position.x, tile.bounds.position.y),
+                    (x as i32, y as i32)
+                );
+                stitched.draw_pixmap(
+                    x as i32,
+                    y as i32,
+                    tile.image.as_ref(),
+                    &PixmapPaint {
+                        blend_mode: BlendMode::Source,
+                        ..Default::default()
+                    },
+                    Transform::identity(),
+                    None,
+                );
+                tiles.push(((tile.column, tile.row), tile.bounds.size));
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        assert_eq!((result.columns, result.rows), (8, 9));
+        assert_eq!(result.tiles, tiles.len());
+        // edges
+        assert!(tiles.contains(&((7, 1), (24, 28))));
+        assert!(tiles.contains(&((1, 8), (40, 16))));
+        // only the filler
+        assert!(tiles.contains(&((4, 0), TILE_SIZE)));
+        // nothing at all
+        assert!(!tiles.iter().any(|&(tile, _)| tile == (4, 4)));
+
+        assert!(stitched.data() == full.image.data());
+    }
+
+    #[test]
+    fn streamed_rows_match_full_render() {
+        let map = test_map();
+        let render_data = empty_render_data();
+        let asset_db = AssetDb::new(NullLookup);
+
+        let mut full = render(&render_data, &asset_db, &map, settings())
+            .unwrap()
+            .image;
+        demultiply(&mut full);
+
+        let mut png = Vec::new();
+        render_png_streaming(
+            &render_data,
+            &asset_db,
+            &map,
+            settings(),
+            &mut png,
+            png::Compression::Fast,
+        )
+        .unwrap();
+
+        let mut reader = png::Decoder::new(std::io::Cursor::new(png))
+            .read_info()
+            .unwrap();
+        let mut streamed = vec![0; reader.output_buffer_size().unwrap()];
+        let info = reader.next_frame(&mut streamed).unwrap();
+        assert_eq!((info.width, info.height), (304, 240));
+
+        assert!(streamed[..info.buffer_size()] == *full.data());
+    }
+}
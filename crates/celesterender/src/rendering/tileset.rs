@@ -165,7 +165,6 @@ pub(crate) struct Matrix<T> {
 }
 
 impl<T: Copy> Matrix<T> {
-    #[allow(dead_code)]
     pub fn filled(val: T, width: u32, height: u32) -> Self {
         Matrix::from_fn(width, height, |_, _| val)
     }
@@ -183,6 +182,12 @@ impl<T: Copy> Matrix<T> {
         let idx = self.size.0 * y + x;
         self.backing[idx as usize]
     }
+    #[track_caller]
+    pub(crate) fn set(&mut self, x: u32, y: u32, val: T) {
+        assert!(x < self.size.0);
+        let idx = self.size.0 * y + x;
+        self.backing[idx as usize] = val;
+    }
     pub(crate) fn get_or(&self, x: i32, y: i32, default: T) -> T {
         if x >= self.size.0 as i32 || x < 0 {
             return default;
@@ -0,0 +1,205 @@
+//! Lönn-style trigger rendering: a translucent rectangle coloured by category, the trigger name on top
+//! and lines to the trigger's nodes.
+
+use celesteloader::map::{Room, Trigger};
+use tiny_skia::{BlendMode, Color, Paint, PathBuilder, Rect, Stroke, Transform};
+
+use crate::asset::LookupAsset;
+
+use super::{font, RenderContext};
+
+const FILL_ALPHA: f32 = 0.3;
+const BORDER_ALPHA: f32 = 0.8;
+pub(super) const NODE_SIZE: f32 = 8.0;
+const TEXT_PADDING: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Category {
+    General,
+    Camera,
+    Audio,
+    Visual,
+    Flag,
+}
+
+impl Category {
+    fn of(name: &str) -> Category {
+        let name = name.to_ascii_lowercase();
+        let has = |words: &[&str]| words.iter().any(|word| name.contains(word));
+
+        if has(&["camera", "lookout"]) {
+            Category::Camera
+        } else if has(&["music", "ambience", "audio", "sound", "sfx"]) {
+            Category::Audio
+        } else if has(&["flag", "session", "counter"]) {
+            Category::Flag
+        } else if has(&[
+            "light",
+            "bloom",
+            "colorgrade",
+            "styleground",
+            "parallax",
+            "glitch",
+            "fade",
+        ]) {
+            Category::Visual
+        } else {
+            Category::General
+        }
+    }
+
+    fn color(self) -> (u8, u8, u8) {
+        match self {
+            Category::General => (47, 114, 100),
+            Category::Camera => (142, 80, 200),
+            Category::Audio => (62, 126, 214),
+            Category::Visual => (206, 150, 42),
+            Category::Flag => (200, 70, 70),
+        }
+    }
+}
+
+fn color((r, g, b): (u8, u8, u8), alpha: f32) -> Color {
+    Color::from_rgba8(r, g, b, (alpha * 255.0) as u8)
+}
+
+/// `everest/flagTrigger` -> `Flag`, `cameraOffsetTrigger` -> `Camera Offset`
+fn display_name(name: &str) -> String {
+    let name = name.rsplit('/').next().unwrap_or(name);
+    let name = name.strip_suffix("Trigger").unwrap_or(name);
+
+    let mut words = String::with_capacity(name.len() + 4);
+    let mut prev: Option<char> = None;
+    for c in name.chars() {
+        let boundary = match prev {
+            Some(prev) => {
+                (c.is_uppercase() && prev.is_lowercase())
+                    || (c.is_ascii_digit() && !prev.is_ascii_digit())
+            }
+            None => false,
+        };
+        if c == '_' {
+            words.push(' ');
+        } else {
+            if boundary {
+                words.push(' ');
+            }
+            if words.is_empty() || words.ends_with(' ') {
+                words.push(c.to_ascii_uppercase());
+            } else {
+                words.push(c);
+            }
+        }
+        prev = Some(c);
+    }
+    words
+}
+
+pub(super) fn render_trigger<L: LookupAsset>(
+    r: &mut RenderContext<L>,
+    room: &Room,
+    trigger: &Trigger,
+) {
+    let base = Category::of(&trigger.name).color();
+    let room_pos = (room.bounds.position.x as f32, room.bounds.position.y as f32);
+    let map_pos = (
+        room_pos.0 + trigger.position.0,
+        room_pos.1 + trigger.position.1,
+    );
+    let size = (
+        trigger.extents.0.max(1) as f32,
+        trigger.extents.1.max(1) as f32,
+    );
+    let center = (map_pos.0 + size.0 / 2.0, map_pos.1 + size.1 / 2.0);
+
+    if !trigger.nodes.is_empty() {
+        let mut pb = PathBuilder::new();
+        for node in &trigger.nodes {
+            let (x, y) = r.transform_pos_f32(center);
+            pb.move_to(x, y);
+            let (x, y) = r.transform_pos_f32((
+                room_pos.0 + node.position.0 + NODE_SIZE / 2.0,
+                room_pos.1 + node.position.1 + NODE_SIZE / 2.0,
+            ));
+            pb.line_to(x, y);
+        }
+        if let Some(path) = pb.finish() {
+            r.pixmap.stroke_path(
+                &path,
+                &Paint {
+                    shader: tiny_skia::Shader::SolidColor(color(base, BORDER_ALPHA)),
+                    anti_alias: false,
+                    ..Default::default()
+                },
+                &Stroke::default(),
+                Transform::identity(),
+                None,
+            );
+        }
+
+        for node in &trigger.nodes {
+            let node_pos = (room_pos.0 + node.position.0, room_pos.1 + node.position.1);
+            box_with_border(r, node_pos, (NODE_SIZE, NODE_SIZE), base);
+        }
+    }
+
+    box_with_border(r, map_pos, size, base);
+
+    let max_width = (size.0 as u32).saturating_sub(2 * TEXT_PADDING);
+    let lines = font::wrap(&display_name(&trigger.name), max_width);
+    let text_height = (lines.len() as u32 * font::LINE_HEIGHT).saturating_sub(1);
+    let mut y = center.1 - (text_height / 2) as f32;
+    for line in lines {
+        let (width, _) = font::measure(&line);
+        let x = center.0 - (width / 2) as f32;
+        r.text((x.floor(), y.floor()), &line, Color::WHITE);
+        y += font::LINE_HEIGHT as f32;
+    }
+}
+
+fn box_with_border<L: LookupAsset>(
+    r: &mut RenderContext<L>,
+    map_pos: (f32, f32),
+    size: (f32, f32),
+    base: (u8, u8, u8),
+) {
+    let (x, y) = r.transform_pos_f32(map_pos);
+    let Some(rect) = Rect::from_xywh(x, y, size.0, size.1) else {
+        return;
+    };
+    r.rect(rect, color(base, FILL_ALPHA), BlendMode::SourceOver);
+    r.stroke_rect(rect, color(base, BORDER_ALPHA));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_names() {
+        assert_eq!(display_name("everest/flagTrigger"), "Flag");
+        assert_eq!(display_name("cameraOffsetTrigger"), "Camera Offset");
+        assert_eq!(display_name("MaxHelpingHand/Stage2Trigger"), "Stage 2");
+        assert_eq!(
+            display_name("spawnFacingTrigger12"),
+            "Spawn Facing Trigger 12"
+        );
+        assert_eq!(display_name("my_cool_trigger"), "My Cool Trigger");
+        assert_eq!(display_name("Trigger"), "");
+    }
+
+    #[test]
+    fn categories() {
+        assert_eq!(Category::of("everest/lookoutBlocker"), Category::Camera);
+        assert_eq!(Category::of("everest/musicFadeTrigger"), Category::Audio);
+        assert_eq!(Category::of("everest/flagTrigger"), Category::Flag);
+        assert_eq!(Category::of("bloomFadeTrigger"), Category::Visual);
+        assert_eq!(Category::of("noRefillTrigger"), Category::General);
+        assert_eq!(Category::of("FLAG"), Category::Flag);
+
+        // earlier keyword groups win
+        assert_eq!(Category::of("cameraFlagTrigger"), Category::Camera);
+        assert_eq!(Category::of("sfxFlagTrigger"), Category::Audio);
+        assert_eq!(Category::of("sessionLightTrigger"), Category::Flag);
+    }
+}
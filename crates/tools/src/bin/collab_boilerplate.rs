@@ -7,6 +7,7 @@ use std::{
 };
 
 use anyhow::{anyhow, ensure, Result};
+use celesteloader::tas::{Action, CommandKind, Input, Line, TasFile};
 use zip::ZipArchive;
 
 fn main() -> Result<()> {
@@ -119,27 +120,34 @@ fn boilerplate_map(mod_name: &str, folder: &str, name: &str, map_bin: &[u8]) ->
 
     let intro_len = intro_type_nocontrol(intro_type)?;
 
-    let wakeup = match intro_len {
-        Some(0) => "".to_owned(),
-        Some(len) => format!("\n{: >4}", len),
-        None => "\n#TODO: replace with correct amount of intro animation frames\n   0".to_owned(),
-    };
-
-    let inputs = format!(
-        r#"RecordCount:
-console load {mod_name}/{folder}/{name}
-   1
-
-#Start{}
-
-#lvl_{}
-   1,J
+    let mut tas = TasFile::default();
+    tas.push(CommandKind::RecordCount(None))
+        .push(CommandKind::Console(vec![
+            "load".into(),
+            format!("{mod_name}/{folder}/{name}"),
+        ]))
+        .push(Input::new(1, []))
+        .push(Line::Empty)
+        .push(Line::Label("Start".into()));
+    match intro_len {
+        Some(0) => {}
+        Some(len) => {
+            tas.push(Input::new(len, []));
+        }
+        None => {
+            tas.push(Line::Comment(
+                "TODO: replace with correct amount of intro animation frames".into(),
+            ))
+            .push(Input::new(0, []));
+        }
+    }
+    tas.push(Line::Empty)
+        .push(Line::Label(format!("lvl_{start_level}")))
+        .push(Input::new(1, [Action::Jump]))
+        .push(Line::Empty)
+        .push(CommandKind::ChapterTime(None));
 
-ChapterTime:
-"#,
-        wakeup, start_level,
-    );
-    Ok(inputs)
+    Ok(tas.to_string())
 }
 
 pub struct MapFile {
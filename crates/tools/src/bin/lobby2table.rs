@@ -1,23 +1,66 @@
 //! lobby2table --format csv
 //! lobby2table --format raw
 //! lobby2table --format draftmsg
+//! lobby2table --format json
+//! lobby2table --format markdown
+//! lobby2table --check
+//! lobby2table --no-wait
+//! lobby2table --format improvement [--rev HEAD~3 | --rev v1.0..HEAD]
+//! lobby2table --format route [--end 12] [--routes 5] [--no-restarts] [--benches]
 //!
-//! Read all lobby files in the current directly and copy the routing table/connection csv/discord draft message to the clipboard
+//! Read all lobby files in the current directly and copy the routing table/connection csv/discord draft message to the clipboard.
+//! When launched from the file manager on Windows, the console stays open until enter is pressed, `--wait`/`--no-wait` override this.
+//! With `--check`, warn about time comments that don't match the inputs of the file.
+//! With `--benches`, benches (`{prefix}_3-A.tas`, `{prefix}_A-B.tas`, `{prefix}_B-5.tas`) become nodes connections and routes may pass through,
+//! walking between them or warping. Warping costs `--warp-cost` frames, or the `--bench-cost` of the target bench,
+//! and `--ignore-bench` benches can't be warped to. The table lists which connections go through which benches.
+//! `--format route` prints the fastest order to visit every map, followed by the runner-up routes. `--no-restarts` forbids going back to the start.
+//! `--format improvement` compares the files against `--rev` (default `HEAD`), or with a `base..tip` range,
+//! lists the time changes of every connection per commit along with the resulting route time.
+//! With `--lobby-map`, missing connections are estimated from the distance between the maps in the lobby and marked with `~`.
+//!
+//! Defaults can be set per lobby in a `lobby2table.toml` next to the TAS files, command line arguments take precedence:
+//! ```toml
+//! prefix = "lvl"
+//! restart-penalty = 190
+//! placeholder = ""
+//! format = "table"
+//!
+//! # routes through benches if present
+//! [benches]
+//! warp-cost = 40
+//! ignore = ["E", "D"]
+//! costs = { A = 30 }
+//!
+//! # estimates connections which aren't TASed yet
+//! [estimate]
+//! map = "Maps/Collab/0-Lobbies/1-Lobby.bin"
+//! speed = 2.0 # pixels per frame
+//! ```
 
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet},
     fmt::Write,
+    io::IsTerminal,
     path::{Path, PathBuf},
     process::Command,
     str::FromStr,
 };
 
 use anyhow::{anyhow, ensure, Context, Result};
+use celesteloader::tas::{resolve::Resolver, TasFile, TasTime};
+use serde::{Deserialize, Serialize};
+use tools::{
+    lobby::{
+        geometry::LobbyGeometry, geometry::DEFAULT_SPEED, node_path, BenchSettings, Connection,
+        Connections, EdgeKind, Lobby, LobbyGraph, Location, RoutedConnections, Via,
+    },
+    route::{self, Matrix, Route, RouteSettings},
+};
 use walkdir::WalkDir;
 
 const RESTART_PENALTY: u32 = 190;
-const INCLUDE_BENCHES: bool = false;
-const IGNORE_BENCH_TARGETS: &[&str] = &["E", "D", "G", "F"];
+const CONFIG_FILE: &str = "lobby2table.toml";
 
 #[derive(Clone, Copy)]
 enum Format {
@@ -26,76 +69,267 @@ enum Format {
     Raw,
     DraftMsg,
     Improvement,
+    Route,
+    Json,
+    Markdown,
+}
+impl Format {
+    const ALL: &[(&str, Format)] = &[
+        ("table", Format::Table),
+        ("csv", Format::Csv),
+        ("raw", Format::Raw),
+        ("draftmsg", Format::DraftMsg),
+        ("improvement", Format::Improvement),
+        ("route", Format::Route),
+        ("json", Format::Json),
+        ("markdown", Format::Markdown),
+    ];
+
+    fn names() -> String {
+        Format::ALL
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join("|")
+    }
+}
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Format::ALL
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+            .map(|&(_, format)| format)
+            .ok_or_else(|| anyhow!("unknown format: {s}, expected one of {}", Format::names()))
+    }
+}
+
+/// `lobby2table.toml`
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Config {
+    prefix: Option<String>,
+    restart_penalty: Option<u32>,
+    placeholder: Option<String>,
+    format: Option<String>,
+    benches: Option<BenchSettings>,
+    estimate: Option<EstimateConfig>,
+}
+
+/// `[estimate]` section of `lobby2table.toml`
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct EstimateConfig {
+    /// Lobby `.bin`, relative to the config file
+    map: Option<PathBuf>,
+    speed: Option<f32>,
+}
+
+impl Config {
+    fn load(dir: &Path) -> Result<Config> {
+        let path = dir.join(CONFIG_FILE);
+        match std::fs::read_to_string(&path) {
+            Ok(text) => {
+                toml::from_str(&text).with_context(|| format!("failed to parse {}", path.display()))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+        }
+    }
 }
+
 struct Args {
-    format: Format,
-    placeholder: String,
+    format: Option<Format>,
+    route: RouteSettings,
+    benches: bool,
+    warp_cost: Option<u32>,
+    bench_costs: BTreeMap<String, u32>,
+    ignore_benches: Vec<String>,
+    restart_penalty: Option<u32>,
+    placeholder: Option<String>,
     paths: Vec<PathBuf>,
     only_changed: bool,
+    check: bool,
+    clipboard: bool,
+    rev: Option<String>,
+    lobby_map: Option<PathBuf>,
+    speed: Option<f32>,
+    /// `--wait`/`--no-wait`, otherwise only wait when launched from the file manager
+    wait: Option<bool>,
+}
+
+/// Settings for one lobby, from the command line and the lobby's config file
+struct Settings {
+    format: Format,
+    prefix: Option<String>,
+    restart_penalty: u32,
+    placeholder: String,
+    benches: Option<BenchSettings>,
+    /// Lobby map and speed in pixels per frame for estimating missing connections
+    estimate: Option<(PathBuf, f32)>,
+}
+
+impl Args {
+    fn settings(&self, dir: &Path, config: Config) -> Result<Settings> {
+        let format = match (self.format, config.format) {
+            (Some(format), _) => format,
+            (None, Some(format)) => format.parse()?,
+            (None, None) => Format::Table,
+        };
+
+        let benches = match (self.benches, config.benches) {
+            (false, None) => None,
+            (_, benches) => {
+                let mut benches = benches.unwrap_or_default();
+                if let Some(warp_cost) = self.warp_cost {
+                    benches.warp_cost = warp_cost;
+                }
+                benches.costs.extend(self.bench_costs.clone());
+                benches.ignore.extend(self.ignore_benches.iter().cloned());
+                Some(benches)
+            }
+        };
+
+        let estimate = config.estimate.unwrap_or_default();
+        let lobby_map = match (&self.lobby_map, estimate.map) {
+            (Some(map), _) => Some(map.clone()),
+            (None, Some(map)) => Some(dir.join(map)),
+            (None, None) => None,
+        };
+        let speed = self.speed.or(estimate.speed).unwrap_or(DEFAULT_SPEED);
+        ensure!(speed > 0.0, "estimate speed must be positive");
+
+        Ok(Settings {
+            format,
+            prefix: config.prefix,
+            restart_penalty: self
+                .restart_penalty
+                .or(config.restart_penalty)
+                .unwrap_or(RESTART_PENALTY),
+            placeholder: self
+                .placeholder
+                .clone()
+                .or(config.placeholder)
+                .unwrap_or_default(),
+            benches,
+            estimate: lobby_map.map(|map| (map, speed)),
+        })
+    }
 }
 
 fn parse_args() -> Result<Args> {
     use lexopt::prelude::*;
 
-    let mut format = Format::Table;
-    let mut paths = Vec::new();
-    let mut placeholder = String::new();
-    let mut only_changed = None;
+    let mut args = Args {
+        format: None,
+        route: RouteSettings::default(),
+        benches: false,
+        warp_cost: None,
+        bench_costs: BTreeMap::new(),
+        ignore_benches: Vec::new(),
+        restart_penalty: None,
+        placeholder: None,
+        paths: Vec::new(),
+        only_changed: false,
+        check: false,
+        clipboard: true,
+        rev: None,
+        lobby_map: None,
+        speed: None,
+        wait: None,
+    };
 
     let mut parser = lexopt::Parser::from_env();
     while let Some(arg) = parser.next()? {
         match arg {
-            Long("format") => {
+            Long("format") => args.format = Some(parser.value()?.string()?.parse()?),
+            Long("only-changed") => args.only_changed = true,
+            Long("check") => args.check = true,
+            Long("rev") => args.rev = Some(parser.value()?.string()?),
+            Long("no-clipboard") => args.clipboard = false,
+            Long("wait") => args.wait = Some(true),
+            Long("no-wait") => args.wait = Some(false),
+            Long("placeholder") => args.placeholder = Some(parser.value()?.string()?),
+            Long("restart-penalty") => args.restart_penalty = Some(parser.value()?.parse()?),
+            Long("end") => args.route.end = Some(parser.value()?.parse()?),
+            Long("routes") => args.route.routes = parser.value()?.parse()?,
+            Long("no-restarts") => args.route.restarts = false,
+            Long("benches") => args.benches = true,
+            Long("lobby-map") => args.lobby_map = Some(parser.value()?.parse()?),
+            Long("speed") => args.speed = Some(parser.value()?.parse()?),
+            Long("warp-cost") => args.warp_cost = Some(parser.value()?.parse()?),
+            Long("bench-cost") => {
                 let val = parser.value()?.string()?;
-                if val.eq_ignore_ascii_case("csv") {
-                    format = Format::Csv
-                } else if val.eq_ignore_ascii_case("table") {
-                    format = Format::Table
-                } else if val.eq_ignore_ascii_case("raw") {
-                    format = Format::Raw
-                } else if val.eq_ignore_ascii_case("draftmsg") {
-                    format = Format::DraftMsg
-                } else if val.eq_ignore_ascii_case("improvement") {
-                    format = Format::Improvement
-                } else {
-                    return Err(anyhow::anyhow!("unknown format: {val}"));
-                }
+                let (bench, cost) = val
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("expected BENCH=FRAMES, got {val}"))?;
+                args.bench_costs.insert(bench.to_owned(), cost.parse()?);
+            }
+            Long("ignore-bench") => {
+                let val = parser.value()?.string()?;
+                args.ignore_benches
+                    .extend(val.split(',').map(|bench| bench.trim().to_owned()));
             }
-            Long("only-changed") => only_changed = Some(true),
-            Long("placeholder") => placeholder = parser.value()?.string()?,
             Long("help") | Short('h') => {
                 println!(
-                    "Usage: lobby2table [--format=csv|raw|table|draftmsg|improvement] [--placeholder placeholder] PATHS..."
+                    "Usage: lobby2table [--format={}] [--placeholder placeholder] [--restart-penalty frames] [--check] [--no-clipboard] [--wait|--no-wait] [--rev rev|base..tip] [--end node] [--routes n] [--no-restarts] [--benches [--warp-cost frames] [--bench-cost bench=frames] [--ignore-bench bench,...]] [--lobby-map lobby.bin [--speed pixels-per-frame]] PATHS...",
+                    Format::names()
                 );
                 std::process::exit(0);
             }
-            Value(val) => paths.push(val.parse()?),
+            Value(val) => args.paths.push(val.parse()?),
             _ => return Err(arg.unexpected().into()),
         }
     }
 
-    Ok(Args {
-        format,
-        placeholder,
-        paths,
-        only_changed: only_changed.unwrap_or(false),
-    })
+    Ok(args)
+}
+
+/// When double-clicked, the console window would close immediately, so keep it open until enter is pressed.
+/// Only Windows creates a console just for us, in which case we're the only process attached to it.
+#[cfg(windows)]
+fn launched_from_file_manager() -> bool {
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetConsoleProcessList(process_list: *mut u32, count: u32) -> u32;
+    }
+
+    let mut processes = [0u32; 2];
+    // SAFETY: the buffer is valid for `processes.len()` entries
+    let count = unsafe { GetConsoleProcessList(processes.as_mut_ptr(), processes.len() as u32) };
+    count == 1
+}
+
+#[cfg(not(windows))]
+fn launched_from_file_manager() -> bool {
+    false
 }
 
 fn main() {
-    if let Err(e) = do_main() {
+    let args = parse_args();
+    let wait = match &args {
+        Ok(args) => args.wait,
+        Err(_) => None,
+    }
+    .unwrap_or_else(launched_from_file_manager);
+
+    let result = args.and_then(do_main);
+    if let Err(e) = &result {
         eprintln!("{e:#?}");
+    }
+    if wait && std::io::stdin().is_terminal() {
+        eprintln!("Press enter to exit");
         let _ = std::io::stdin().read_line(&mut String::new());
     }
+    if result.is_err() {
+        std::process::exit(1);
+    }
 }
 
-fn do_main() -> Result<()> {
-    let mut args = parse_args()?;
-
-    let mut in_cwd = false;
+fn do_main(mut args: Args) -> Result<()> {
     if args.paths.is_empty() {
         args.paths.push(std::env::current_dir().unwrap());
-        in_cwd = true;
     }
 
     for path in &args.paths {
@@ -103,6 +337,8 @@ fn do_main() -> Result<()> {
             eprintln!("{}:", path.display());
         }
 
+        let settings = args.settings(path, Config::load(path)?)?;
+
         let only_paths = if !args.only_changed {
             None
         } else {
@@ -125,20 +361,95 @@ fn do_main() -> Result<()> {
             )
         };
 
-        let (n, connections, _benches, prefix) =
-            collect_entries(path, INCLUDE_BENCHES, only_paths)?;
+        if args.check {
+            for problem in check_times(path, only_paths.as_deref())? {
+                eprintln!("warning: {problem}");
+            }
+        }
+
+        let lobby = Lobby::collect(path, only_paths.as_deref())?;
+        if let (Some(expected), Some(prefix)) = (&settings.prefix, &lobby.prefix) {
+            ensure!(
+                expected == prefix,
+                "Lobby prefix '{prefix}' is not the same as '{expected}' from {CONFIG_FILE}"
+            );
+        }
+        let prefix = settings.prefix.as_deref().or(lobby.prefix.as_deref());
+
+        let benches = settings.benches.as_ref();
+        if let Some(benches) = benches {
+            let known = lobby.benches();
+            let configured = benches.costs.keys().chain(&benches.ignore);
+            for bench in configured.filter(|bench| !known.contains(bench.as_str())) {
+                eprintln!("warning: bench {bench} does not appear in any lobby file");
+            }
+        }
+        let mut graph = lobby.graph(benches);
+        if let Some((lobby_map, speed)) = &settings.estimate {
+            let data = std::fs::read(lobby_map)
+                .with_context(|| format!("failed to read {}", lobby_map.display()))?;
+            let map = celesteloader::map::decode::decode_map(&data)?;
+            let sid = lobby_map.file_stem().and_then(|stem| stem.to_str());
+            let layout =
+                celesteloader::lobby::Lobby::from_map(sid.unwrap_or_default(), &map, None)?;
+            let geometry = LobbyGeometry::new(layout);
+            if (geometry.maps() as u32) < lobby.maps {
+                eprintln!(
+                    "warning: {} only has {} maps, but the TAS files go up to {}",
+                    lobby_map.display(),
+                    geometry.maps(),
+                    lobby.maps
+                );
+            }
 
-        let result = match args.format {
-            Format::Table => format_connections(n, connections, &args.placeholder, true)?,
-            Format::Csv => format_connections(n, connections, &args.placeholder, false)?,
-            Format::Raw => format_connections_raw(connections),
-            Format::DraftMsg => format_connections_draftmsg(connections, prefix.as_deref()),
-            Format::Improvement => format_connections_improvement(path)?,
+            graph.estimate_missing(geometry.maps() as u32, |start, end| {
+                geometry.estimate_frames(&Location::Map(start), &Location::Map(end), *speed)
+            });
+        }
+        let connections = graph.routed_connections();
+        let n = graph.maps;
+        let restart_penalty = settings.restart_penalty;
+        let placeholder = settings.placeholder.as_str();
+
+        let result = match settings.format {
+            Format::Table => {
+                let mut table =
+                    format_connections(n, &connections, restart_penalty, placeholder, true)?;
+                let indirect = format_indirect_connections(&lobby, &connections);
+                if !indirect.is_empty() {
+                    let _ = write!(&mut table, "\n{indirect}");
+                }
+                table
+            }
+            Format::Csv => {
+                let mut table =
+                    format_connections(n, &connections, restart_penalty, placeholder, false)?;
+                let indirect = format_indirect_connections_csv(&connections);
+                if !indirect.is_empty() {
+                    let _ = write!(&mut table, "\nfrom,to,time,via\n{indirect}");
+                }
+                table
+            }
+            Format::Raw => format_connections_raw(&connections),
+            Format::DraftMsg => format_connections_draftmsg(&lobby.connections, prefix),
+            Format::Improvement => match args.rev.as_deref().unwrap_or("HEAD").split_once("..") {
+                Some((base, tip)) => {
+                    format_improvement_history(path, base, tip, &settings, &args.route)?
+                }
+                None => {
+                    format_connections_improvement(path, args.rev.as_deref().unwrap_or("HEAD"))?
+                }
+            },
+            Format::Route => format_route(&graph, restart_penalty, &args.route)?,
+            Format::Json => format_connections_json(n, &connections, restart_penalty, prefix)?,
+            Format::Markdown => {
+                format_connections_markdown(n, &connections, restart_penalty, placeholder)
+            }
         };
         println!("{}", result);
 
         #[cfg(feature = "clipboard")]
-        {
+        if args.clipboard {
             let mut clipboard = arboard::Clipboard::new().context("failed to acquire clipboard")?;
             clipboard
                 .set()
@@ -148,32 +459,108 @@ fn do_main() -> Result<()> {
         }
     }
 
-    if in_cwd {
-        let _ = std::io::stdin().read_line(&mut String::new());
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct JsonLobby<'a> {
+    prefix: Option<&'a str>,
+    maps: u32,
+    restart_penalty: u32,
+    connections: Vec<JsonConnection<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonConnection<'a> {
+    from: u32,
+    to: u32,
+    #[serde(flatten)]
+    connection: &'a Connection,
+}
+
+fn format_connections_json(
+    n: u32,
+    connections: &RoutedConnections,
+    restart_penalty: u32,
+    prefix: Option<&str>,
+) -> Result<String> {
+    let connections = connections
+        .iter()
+        .flat_map(|(&from, targets)| {
+            targets.iter().map(move |(&to, connection)| JsonConnection {
+                from,
+                to,
+                connection,
+            })
+        })
+        .collect();
+    let lobby = JsonLobby {
+        prefix,
+        maps: n,
+        restart_penalty,
+        connections,
+    };
+    Ok(serde_json::to_string_pretty(&lobby)?)
+}
+
+/// Connection table, where connections through benches are annotated with the benches used
+fn format_connections_markdown(
+    n: u32,
+    connections: &RoutedConnections,
+    restart_penalty: u32,
+    placeholder: &str,
+) -> String {
+    let mut text = String::new();
+
+    let header = (0..=n).map(|to| to.to_string()).collect::<Vec<_>>();
+    let _ = writeln!(&mut text, "| from \\ to | {} |", header.join(" | "));
+    let _ = writeln!(&mut text, "|---|{}", "---:|".repeat(n as usize + 1));
+
+    let empty = BTreeMap::new();
+    for from in 0..=n {
+        let row = connections.get(&from).unwrap_or(&empty);
+        let cells = (0..=n)
+            .map(|to| {
+                if to == from {
+                    return "0".to_string();
+                }
+                if to == 0 {
+                    return restart_penalty.to_string();
+                }
+                match row.get(&to) {
+                    Some(Connection {
+                        time,
+                        via: Via::Direct,
+                    }) => time.to_string(),
+                    Some(Connection {
+                        time,
+                        via: Via::Bench { benches },
+                    }) => format!("{time} ({})", benches.join("→")),
+                    Some(Connection {
+                        time,
+                        via: Via::Estimated,
+                    }) => format!("~{time}"),
+                    None => placeholder.to_string(),
+                }
+            })
+            .collect::<Vec<_>>();
+        let _ = writeln!(&mut text, "| **{from}** | {} |", cells.join(" | "));
     }
 
-    Ok(())
+    text
 }
 
-fn format_connections_raw(connections: Connections) -> String {
+fn format_connections_raw(connections: &RoutedConnections) -> String {
     connections
         .iter()
-        .flat_map(|(from, to)| to.iter().map(|(to, value)| (*from, *to, *value)))
+        .flat_map(|(from, to)| to.iter().map(|(to, value)| (*from, *to, value.time)))
         .fold(String::new(), |mut out, (from, to, value)| {
             let _ = writeln!(&mut out, "{from},{to},{value}");
             out
         })
 }
 
-fn frames_to_finaltime(frames: u32) -> String {
-    let ms = frames * 17;
-    let s = ms / 1000;
-    let min = s / 60;
-
-    format!("{}:{:0>2}.{:0>3}({frames})", min, s % 60, ms % 1000)
-}
-
-fn format_connections_draftmsg(connections: Connections, prefix: Option<&str>) -> String {
+fn format_connections_draftmsg(connections: &Connections, prefix: Option<&str>) -> String {
     connections
         .iter()
         .flat_map(|(from, to)| to.iter().map(|(to, value)| (*from, *to, *value)))
@@ -182,45 +569,99 @@ fn format_connections_draftmsg(connections: Connections, prefix: Option<&str>) -
                 Some(prefix) => format!("{prefix}_{from}-{to}.tas"),
                 None => format!("{from}-{to}.tas"),
             };
-            let _ = writeln!(&mut out, "{file} draft in {}", frames_to_finaltime(time));
+            let _ = writeln!(&mut out, "{file} draft in {}", TasTime::from_frames(time));
             out
         })
 }
 
-fn format_connections_improvement(path: &Path) -> Result<String> {
-    let repo = gix::discover(path)?;
+/// Directory of the lobby relative to the repository root
+fn repo_relative(repo: &gix::Repository, path: &Path) -> Result<PathBuf> {
+    let workdir = repo.workdir().context("no workdir")?;
+    let relative = path
+        .canonicalize()?
+        .strip_prefix(workdir.canonicalize()?)
+        .with_context(|| format!("{} is not inside the repository", path.display()))?
+        .to_owned();
+    Ok(relative)
+}
 
-    let tree = repo.head_commit()?.tree()?;
+/// Contents of the lobby files below `dir`, keyed by their path relative to `dir`
+fn tree_lobby_files(tree: &gix::Tree<'_>, dir: &Path) -> Result<BTreeMap<PathBuf, String>> {
+    let tree = if dir.as_os_str().is_empty() {
+        tree.clone()
+    } else {
+        match tree.lookup_entry_by_path(dir)? {
+            Some(entry) if entry.mode().is_tree() => entry.object()?.into_tree(),
+            _ => return Ok(BTreeMap::new()),
+        }
+    };
 
     let mut recorder = gix::traverse::tree::Recorder::default();
     tree.traverse().breadthfirst(&mut recorder)?;
 
-    let mut out = String::new();
-
-    let mut improvements = Vec::new();
+    let mut files = BTreeMap::new();
     for record in recorder.records {
         if !record.mode.is_blob() {
             continue;
         }
         let filepath = PathBuf::from(std::str::from_utf8(&record.filepath)?);
-
-        let Some(node) = filepath
-            .file_stem()
-            .and_then(|name| name.to_str())
-            .and_then(node_path)
-        else {
+        if filepath.extension().is_none_or(|ext| ext != "tas")
+            || filepath
+                .file_stem()
+                .and_then(|name| name.to_str())
+                .and_then(node_path)
+                .is_none()
+        {
             continue;
-        };
+        }
+
+        let object = tree.repo.find_object(record.oid)?;
+        let text = std::str::from_utf8(&object.data)?.replace("\r\n", "\n");
+        files.insert(filepath, text);
+    }
+
+    Ok(files)
+}
+
+/// Times of the lobby files in `tree` by file stem, skipping files without a time comment
+fn tree_lobby_times(tree: &gix::Tree<'_>, dir: &Path) -> Result<BTreeMap<String, u32>> {
+    let times = tree_lobby_files(tree, dir)?
+        .into_iter()
+        .filter_map(|(path, text)| {
+            let stem = path.file_stem()?.to_str()?.to_owned();
+            Some((stem, extract_node_time(&text).ok()?))
+        })
+        .collect();
+    Ok(times)
+}
+
+/// Diffs the lobby files at `rev` against the working directory
+fn format_connections_improvement(path: &Path, rev: &str) -> Result<String> {
+    let repo = gix::discover(path.canonicalize()?)?;
+    let dir = repo_relative(&repo, path)?;
+
+    let tree = repo
+        .rev_parse_single(rev)?
+        .object()?
+        .peel_to_commit()?
+        .tree()?;
+
+    let mut out = String::new();
+
+    let mut improvements = Vec::new();
+    for (filepath, old) in tree_lobby_files(&tree, &dir)? {
+        let node = filepath.file_stem().unwrap().to_string_lossy().into_owned();
 
-        let object = repo.find_object(record.oid)?;
-        let old = std::str::from_utf8(&object.data)?.replace("\r\n", "\n");
-        let new = std::fs::read_to_string(repo.workdir().context("no workdir")?.join(&filepath))?
-            .replace("\r\n", "\n");
+        let new = match std::fs::read_to_string(path.join(&filepath)) {
+            Ok(new) => new.replace("\r\n", "\n"),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
 
         if old != new {
             let old_time = extract_node_time(&old)?;
             let new_time = extract_node_time(&new)?;
-            improvements.push((node.to_string(), old_time, new_time));
+            improvements.push((node, old_time, new_time));
         }
     }
     improvements.sort_by(|(a, ..), (b, ..)| lexical_sort::natural_cmp(a, b));
@@ -228,19 +669,299 @@ fn format_connections_improvement(path: &Path) -> Result<String> {
     for (node, old_time, new_time) in improvements {
         let _ = writeln!(
             &mut out,
-            "-{}f {node}.tas {} -> {}",
-            old_time as i32 - new_time as i32,
-            frames_to_finaltime(old_time),
-            frames_to_finaltime(new_time)
+            "{} {node}.tas {} -> {}",
+            format_frames_delta(old_time, new_time),
+            TasTime::from_frames(old_time),
+            TasTime::from_frames(new_time)
         );
     }
 
     Ok(out)
 }
 
+/// Time of the fastest route through a lobby with the given file times, if every map can be reached
+fn route_time(
+    times: &BTreeMap<String, u32>,
+    settings: &Settings,
+    route: &RouteSettings,
+) -> Option<u32> {
+    let mut lobby = Lobby::default();
+    for (stem, &time) in times {
+        lobby.insert(stem, time).ok()?;
+    }
+
+    let graph = lobby.graph(settings.benches.as_ref());
+    let (matrix, _) = route_matrix(&graph, settings.restart_penalty);
+    let route = RouteSettings {
+        routes: 1,
+        ..route.clone()
+    };
+    let routes = route::solve(&matrix, &route).ok()?;
+    Some(routes[0].frames)
+}
+
+fn format_frames_delta(old: u32, new: u32) -> String {
+    match new.cmp(&old) {
+        std::cmp::Ordering::Less => format!("-{}f", old - new),
+        std::cmp::Ordering::Equal => "±0f".to_string(),
+        std::cmp::Ordering::Greater => format!("+{}f", new - old),
+    }
+}
+
+/// Time changes of every connection and of the fastest route for each commit in `base..tip`,
+/// following first parents only.
+fn format_improvement_history(
+    path: &Path,
+    base: &str,
+    tip: &str,
+    settings: &Settings,
+    route: &RouteSettings,
+) -> Result<String> {
+    let repo = gix::discover(path.canonicalize()?)?;
+    let dir = repo_relative(&repo, path)?;
+
+    let tip = repo.rev_parse_single(if tip.is_empty() { "HEAD" } else { tip })?;
+    let base = match base {
+        "" => None,
+        base => Some(repo.rev_parse_single(base)?.object()?.peel_to_commit()?),
+    };
+
+    let mut walk = repo.rev_walk([tip]).first_parent_only();
+    if let Some(base) = &base {
+        walk = walk.with_hidden([base.id]);
+    }
+    let mut commits = walk.all()?.collect::<Result<Vec<_>, _>>()?;
+    commits.reverse();
+
+    let mut previous = match &base {
+        Some(base) => tree_lobby_times(&base.tree()?, &dir)?,
+        None => BTreeMap::new(),
+    };
+    let mut previous_route = route_time(&previous, settings, route);
+
+    let mut history = BTreeMap::<String, Vec<String>>::new();
+    let mut timeline = Vec::new();
+    if let (Some(base), Some(time)) = (&base, previous_route) {
+        timeline.push(format!(
+            "{} {} (base)",
+            TasTime::from_frames(time),
+            base.short_id()?
+        ));
+    }
+
+    for info in commits {
+        let commit = info.object()?;
+        let times = tree_lobby_times(&commit.tree()?, &dir)?;
+        if times == previous {
+            continue;
+        }
+
+        let author = commit.author()?;
+        let description = format!(
+            "{} {} {}: {}",
+            commit.short_id()?,
+            author.time()?.format(gix::date::time::format::SHORT),
+            author.name,
+            commit.message()?.summary()
+        );
+
+        let stems = previous.keys().chain(times.keys()).collect::<BTreeSet<_>>();
+        for stem in stems {
+            let change = match (previous.get(stem), times.get(stem)) {
+                (Some(old), Some(new)) if old == new => continue,
+                (Some(&old), Some(&new)) => format!(
+                    "{} {} -> {}",
+                    format_frames_delta(old, new),
+                    TasTime::from_frames(old),
+                    TasTime::from_frames(new)
+                ),
+                (None, Some(&new)) => format!("new {}", TasTime::from_frames(new)),
+                (Some(_), None) => "removed".to_string(),
+                (None, None) => unreachable!(),
+            };
+            history
+                .entry(stem.clone())
+                .or_default()
+                .push(format!("{change} {description}"));
+        }
+
+        let route_time = route_time(&times, settings, route);
+        match (previous_route, route_time) {
+            (Some(old), Some(new)) if old == new => {}
+            (_, Some(new)) => timeline.push(format!(
+                "{} {}{description}",
+                TasTime::from_frames(new),
+                previous_route
+                    .map(|old| format!("{} ", format_frames_delta(old, new)))
+                    .unwrap_or_default(),
+            )),
+            (Some(_), None) => timeline.push(format!("incomplete {description}")),
+            (None, None) => {}
+        }
+
+        previous = times;
+        previous_route = route_time;
+    }
+
+    let mut history = history.into_iter().collect::<Vec<_>>();
+    history.sort_by(|(a, _), (b, _)| lexical_sort::natural_cmp(a, b));
+
+    let mut out = String::new();
+    for (stem, changes) in history {
+        let _ = writeln!(&mut out, "{stem}.tas");
+        for change in changes {
+            let _ = writeln!(&mut out, "  {change}");
+        }
+    }
+
+    let _ = writeln!(&mut out, "\nroute time:");
+    for entry in timeline {
+        let _ = writeln!(&mut out, "  {entry}");
+    }
+
+    Ok(out)
+}
+
+/// The route matrix of the graph, with benches as waypoints after the maps, and the location of every node
+fn route_matrix(graph: &LobbyGraph, restart_penalty: u32) -> (Matrix, Vec<Location>) {
+    let locations = (0..=graph.maps)
+        .map(Location::Map)
+        .chain(
+            graph
+                .benches()
+                .into_iter()
+                .map(|bench| Location::Bench(bench.to_owned())),
+        )
+        .collect::<Vec<_>>();
+    let index = |location: &Location| locations.iter().position(|other| other == location);
+
+    let maps = graph.maps as usize + 1;
+    let mut matrix = Matrix::with_waypoints(maps, locations.len() - maps);
+    for from in 1..locations.len() {
+        matrix.set(from, 0, restart_penalty);
+    }
+    for (from, targets) in &graph.edges {
+        for (to, &(time, _)) in targets {
+            if let (Some(from), Some(to)) = (index(from), index(to)) {
+                matrix.set_min(from, to, time);
+            }
+        }
+    }
+    (matrix, locations)
+}
+
+fn format_route_steps(route: &Route, graph: &LobbyGraph, locations: &[Location]) -> String {
+    let mut out = "0".to_string();
+    let mut current = &locations[0];
+    for leg in &route.legs {
+        let via = &leg[..leg.len() - 1];
+        for &node in leg {
+            let location = &locations[node];
+            let estimated = match graph.edge(current, location) {
+                Some((_, EdgeKind::Estimated)) => "~",
+                _ => "",
+            };
+
+            match location {
+                Location::Map(0) => out.push_str(" -> restart"),
+                Location::Bench(bench) => {
+                    let _ = write!(&mut out, " -> [{bench}]");
+                }
+                Location::Map(map) if via.contains(&node) => {
+                    let _ = write!(&mut out, " -> ({estimated}{map})");
+                }
+                Location::Map(map) => {
+                    let _ = write!(&mut out, " -> {estimated}{map}");
+                }
+            }
+            current = location;
+        }
+    }
+    out
+}
+
+fn format_route(
+    graph: &LobbyGraph,
+    restart_penalty: u32,
+    settings: &RouteSettings,
+) -> Result<String> {
+    let (matrix, locations) = route_matrix(graph, restart_penalty);
+    let routes = route::solve(&matrix, settings)?;
+
+    let mut out = String::new();
+    let best = &routes[0];
+    let _ = writeln!(
+        &mut out,
+        "{}f ({})\n{}",
+        best.frames,
+        TasTime::from_frames(best.frames),
+        format_route_steps(best, graph, &locations)
+    );
+
+    if routes.len() > 1 {
+        let _ = writeln!(&mut out, "\nrunner-up routes:");
+    }
+    for route in &routes[1..] {
+        let _ = writeln!(
+            &mut out,
+            "+{}f {}f ({})\n{}",
+            route.frames - best.frames,
+            route.frames,
+            TasTime::from_frames(route.frames),
+            format_route_steps(route, graph, &locations)
+        );
+    }
+
+    Ok(out)
+}
+
+/// Lists the connections which are faster by going through a bench, or are estimated
+fn format_indirect_connections(lobby: &Lobby, connections: &RoutedConnections) -> String {
+    let mut out = String::new();
+    for (from, targets) in connections {
+        for (to, connection) in targets {
+            let benches = match &connection.via {
+                Via::Direct => continue,
+                Via::Estimated => {
+                    let _ = writeln!(&mut out, "{from}-{to}: ~{} estimated", connection.time);
+                    continue;
+                }
+                Via::Bench { benches } => benches.join(" -> "),
+            };
+            let direct = match lobby.connections.get(from).and_then(|t| t.get(to)) {
+                Some(direct) => format!("direct {direct}"),
+                None => "no direct connection".to_string(),
+            };
+            let _ = writeln!(
+                &mut out,
+                "{from}-{to}: {} via bench {benches} ({direct})",
+                connection.time
+            );
+        }
+    }
+    out
+}
+
+/// Rows of `from,to,time,via` for the connections which don't use a direct TAS
+fn format_indirect_connections_csv(connections: &RoutedConnections) -> String {
+    let mut out = String::new();
+    for (from, targets) in connections {
+        for (to, connection) in targets {
+            let via = match &connection.via {
+                Via::Direct => continue,
+                Via::Estimated => "estimated".to_string(),
+                Via::Bench { benches } => format!("bench {}", benches.join(" -> ")),
+            };
+            let _ = writeln!(&mut out, "{from},{to},{},{via}", connection.time);
+        }
+    }
+    out
+}
+
 fn format_connections(
     n: u32,
-    connections: Connections,
+    connections: &RoutedConnections,
+    restart_penalty: u32,
     placeholder: &str,
     with_brackets: bool,
 ) -> Result<String> {
@@ -257,11 +978,11 @@ fn format_connections(
                 }
 
                 if to == 0 {
-                    return RESTART_PENALTY.to_string();
+                    return restart_penalty.to_string();
                 }
 
                 match row.get(&to) {
-                    Some(time) => time.to_string(),
+                    Some(connection) => connection.time.to_string(),
                     None => placeholder.into(),
                 }
             })
@@ -278,237 +999,66 @@ fn format_connections(
     Ok(text)
 }
 
-type Connections = BTreeMap<u32, BTreeMap<u32, u32>>;
-
-fn collect_entries(
-    path: &Path,
-    include_benches: bool,
-    only_paths: Option<Vec<PathBuf>>,
-) -> Result<(u32, Connections, Vec<BenchNode>, Option<String>)> {
-    let dir = WalkDir::new(path);
-
-    let mut nodes = Vec::new();
-    let mut benches = Vec::new();
+/// Compares the recorded time of every lobby file against its statically computed input frames
+fn check_times(path: &Path, only_paths: Option<&[PathBuf]>) -> Result<Vec<String>> {
+    let mut resolver = Resolver::new();
+    let mut problems = Vec::new();
 
-    let mut prefix = None;
-
-    for entry in dir {
+    for entry in WalkDir::new(path).sort_by_file_name() {
         let entry = entry?;
         let path = entry.path();
 
-        if let Some(only_paths) = &only_paths {
-            if !only_paths.iter().any(|p| p == path) {
-                continue;
-            }
+        if only_paths.is_some_and(|only_paths| !only_paths.iter().any(|p| p == path)) {
+            continue;
         }
-
-        if path.extension().is_none_or(|ext| ext != "tas") {
+        if path.extension().is_none_or(|ext| ext != "tas") || !entry.file_type().is_file() {
             continue;
         }
-
-        anyhow::ensure!(
-            entry.metadata()?.is_file(),
-            "{} is not a file",
-            path.display()
-        );
-
-        let stem = path
+        if path
             .file_stem()
-            .unwrap()
-            .to_str()
-            .ok_or_else(|| anyhow!("non-UTF8 path: {}", path.display()))?;
-        let node = node_path(stem).ok_or_else(|| anyhow!("invalid filename: {stem}"))?;
-
-        let prefix = prefix.get_or_insert(node.prefix.to_owned());
-        ensure!(
-            node.prefix == prefix,
-            "Lobby prefix '{}' is not the same as '{}'",
-            node.prefix,
-            prefix,
-        );
-
-        let start = node
-            .start
-            .parse::<Location>()
-            .context("failed to parse start node")?;
-        let end = node
-            .end
-            .parse::<Location>()
-            .context("failed to parse end node")?;
-
-        let text = std::fs::read_to_string(path)
-            .with_context(|| format!("could not read {}", path.display()))?;
-        let time = extract_node_time(&text)
-            .with_context(|| format!("could not extract time from {}", path.display()))?;
-
-        ensure!(
-            !text.contains("\n***"),
-            "{} contains a breakpoint",
-            path.display()
-        );
+            .and_then(|stem| stem.to_str())
+            .and_then(node_path)
+            .is_none()
+        {
+            continue;
+        }
 
-        match (start, end) {
-            (Location::Map(start), Location::Map(end)) => {
-                let node = Node { start, end, time };
-                nodes.push(node);
-            }
-            (Location::Bench(start), Location::Map(end)) => {
-                benches.push(BenchNode::From { start, end, time });
-            }
-            (Location::Map(start), Location::Bench(end)) => {
-                benches.push(BenchNode::To { start, end, time });
+        let tas = match resolver.expand(path) {
+            Ok(tas) => tas,
+            Err(e) => {
+                problems.push(e.to_string());
+                continue;
             }
-            (Location::Bench(_), Location::Bench(_)) => {
-                // are these useful?
+        };
+        // files without `console load` start where the previous one stopped, so all inputs count
+        let actual = tas
+            .frames_since_load()
+            .unwrap_or_else(|| tas.input_frames());
+
+        let file = resolver.load(path)?;
+        let recorded = [
+            ("time comment", file.time_comment()),
+            ("ChapterTime", file.chapter_time()),
+        ];
+        for (what, recorded) in recorded {
+            if let Some(recorded) = recorded.filter(|recorded| recorded.frames != actual) {
+                problems.push(format!(
+                    "{}: {what} {recorded} does not match input frames {}",
+                    path.display(),
+                    TasTime::from_frames(actual)
+                ));
             }
         }
     }
 
-    let n: u32 = nodes
-        .iter()
-        .map(|node| node.start.max(node.end))
-        .max()
-        .ok_or_else(|| anyhow::anyhow!("no nodes present"))?;
-
-    let mut map = BTreeMap::<u32, BTreeMap<u32, u32>>::new();
-    for node in nodes {
-        map.entry(node.start)
-            .or_default()
-            .insert(node.end, node.time);
-    }
-
-    if include_benches {
-        let bench_connections: Vec<_> = benches
-            .iter()
-            .filter_map(|bench| match bench {
-                BenchNode::To { start, end, time } => Some((start, end, time)),
-                _ => None,
-            })
-            .flat_map(|(start, end, time)| {
-                let indirect_connections =
-                    benches
-                        .iter()
-                        .filter_map(move |bench_node| match bench_node {
-                            BenchNode::From {
-                                start: other_start,
-                                end: other_end,
-                                time: other_time,
-                            } => {
-                                if IGNORE_BENCH_TARGETS.contains(&other_start.as_str()) {
-                                    return None;
-                                }
-
-                                if other_start != end && *other_end != *start {
-                                    Some((*start, end, other_start, *other_end, time + other_time))
-                                } else {
-                                    None
-                                }
-                            }
-                            BenchNode::To { .. } => None,
-                        });
-
-                let saving_time = indirect_connections
-                    .filter(|(start, _, _, end, time)| {
-                        let time_with_menuing = *time; // TODO
-                        let direct_time = map.get(start).and_then(|targets| targets.get(end));
-                        match direct_time {
-                            Some(direct_time) if time_with_menuing < *direct_time => true,
-                            Some(_) => false,
-                            None => true,
-                        }
-                    })
-                    .inspect(|(start, via1, via2, end, time)| {
-                        eprintln!("using {start}-{via1}-{via2}-{end}: {time}");
-                    })
-                    .map(|(start, _, _, end, time)| (start, end, time));
-
-                saving_time
-            })
-            .collect();
-
-        for (start, end, time) in bench_connections {
-            map.entry(start).or_default().insert(end, time);
-        }
-    }
-
-    Ok((n, map, benches, prefix))
+    Ok(problems)
 }
 
 fn extract_node_time(text: &str) -> Result<u32> {
-    let last_line = text
-        .lines()
-        .rev()
-        .find(|line| {
-            // TODO: use regex?
-            !line.is_empty()
-                && line.starts_with('#')
-                && line.contains(':')
-                && line.contains('.')
-                && line.ends_with(')')
-        })
+    let (tas, _) = TasFile::parse_lenient(text);
+    let time = tas
+        .time_comment()
         .ok_or_else(|| anyhow!("could not find time comment"))?;
 
-    let (_, frames) = last_line
-        .trim_end_matches(')')
-        .rsplit_once('(')
-        .ok_or_else(|| anyhow!("last line '{last_line}' does not contain time"))?;
-    let frames = frames.parse()?;
-
-    Ok(frames)
-}
-
-#[derive(Debug)]
-struct NodePath<'a> {
-    prefix: &'a str,
-    start: &'a str,
-    end: &'a str,
-}
-impl std::fmt::Display for NodePath<'_> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}_{}-{}", self.prefix, self.start, self.end)
-    }
-}
-
-fn node_path(stem: &str) -> Option<NodePath<'_>> {
-    let (prefix, rest) = stem.split_once('_')?;
-    let (from, to) = rest.split_once('-')?;
-
-    if !from.chars().all(char::is_alphanumeric) || !to.chars().all(char::is_alphanumeric) {
-        return None;
-    }
-
-    Some(NodePath {
-        prefix,
-        start: from,
-        end: to,
-    })
-}
-
-#[derive(Debug, Clone, Copy)]
-struct Node {
-    start: u32,
-    end: u32,
-    time: u32,
-}
-
-#[derive(Debug, Clone)]
-enum Location {
-    Bench(String),
-    Map(u32),
-}
-impl FromStr for Location {
-    type Err = std::convert::Infallible;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.parse::<u32>() {
-            Ok(id) => Ok(Location::Map(id)),
-            Err(_) => Ok(Location::Bench(s.to_owned())),
-        }
-    }
-}
-
-#[derive(Debug)]
-enum BenchNode {
-    From { start: String, end: u32, time: u32 },
-    To { start: u32, end: String, time: u32 },
+    Ok(time.frames)
 }
@@ -1,162 +1,124 @@
-use anyhow::{Context, Result};
-use celesteloader::{dialog::Dialog, map::decode::Element, CelesteInstallation};
+//! Writes the entrances and benches of every lobby in a collab as CSV, for use with `annotate_celeste_map --lobby-entrances`.
+//!
+//! Usage: `read_lobby [--lang English] [--output dir] <mod name or zip path>`
+//!
+//! For every lobby a `<lobby>.csv` is written with one `i,"name",x,y` line per node, where `0` is the spawn,
+//! maps are numbered from `1` and benches are named `bench_A`, `bench_B`, ...
+
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context, Result};
+use celesteloader::{archive::ModArchive, lobby::Lobby, CelesteInstallation};
+
+struct Args {
+    mod_name: String,
+    lang: String,
+    output: PathBuf,
+}
 
-fn main() -> Result<()> {
-    let celeste = CelesteInstallation::detect()?;
-    let m = "catfish";
+fn parse_args() -> Result<Args> {
+    use lexopt::prelude::*;
+
+    let mut mod_name = None;
+    let mut lang = "English".to_owned();
+    let mut output = PathBuf::from(".");
+
+    let mut parser = lexopt::Parser::from_env();
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Long("lang") => lang = parser.value()?.string()?,
+            Long("output") | Short('o') => output = parser.value()?.parse()?,
+            Long("help") | Short('h') => {
+                println!(
+                    "Usage: read_lobby [--lang English] [--output dir] <mod name or zip path>"
+                );
+                std::process::exit(0);
+            }
+            Value(val) if mod_name.is_none() => mod_name = Some(val.string()?),
+            _ => return Err(arg.unexpected().into()),
+        }
+    }
+
+    Ok(Args {
+        mod_name: mod_name.ok_or_else(|| anyhow!("missing mod name or path"))?,
+        lang,
+        output,
+    })
+}
 
-    let mut archive = celeste
+fn find_mod(name: &str) -> Result<ModArchive> {
+    let path = PathBuf::from(name);
+    if path.is_file() {
+        return ModArchive::read(&path).with_context(|| format!("failed to read {name}"));
+    }
+
+    let celeste = CelesteInstallation::detect()?;
+    let archive = celeste
         .find_mod_with(|modname, archive| {
             Ok(modname
                 .to_lowercase()
-                .contains(&m.to_lowercase())
+                .contains(&name.to_lowercase())
                 .then_some(archive))
         })?
-        .with_context(|| format!("could not find {m}"))?;
-
-    let dialog = archive.get_dialog("English")?;
-    let lobbies = archive
-        .list_files()
-        .filter(|name| {
-            name.ends_with(".bin")
-                && name
-                    .rfind('/')
-                    .is_some_and(|idx| name[..idx].ends_with("0-Lobbies"))
-        })
-        .filter(|name| name.contains("Maps"))
-        .map(String::from)
-        .collect::<Vec<_>>();
-
-    for lobby in lobbies {
-        let data = archive.read_file(&lobby)?;
-        let map = celesteloader::map::decode::decode_map(&data)?;
-
-        let lobby_maps = gen_lobby(map, &dialog)?;
-
-        if lobby_maps.len() <= 1 {
-            continue; // e.g. Prologue
-        }
+        .with_context(|| format!("could not find mod {name}"))?;
+    Ok(archive)
+}
 
-        for (i, (name, x, y)) in lobby_maps.iter().enumerate() {
-            println!("{i},\"{}\",{x},{y}", name);
-        }
+fn format_entrances(lobby: &Lobby) -> String {
+    let spawn = lobby
+        .spawn
+        .iter()
+        .map(|spawn| ("Start".to_owned(), spawn.position));
+    let maps = lobby
+        .entrances
+        .iter()
+        .map(|entrance| (entrance.display_name().to_owned(), entrance.position));
+    let benches = lobby
+        .benches
+        .iter()
+        .map(|bench| (format!("bench_{}", bench.name), bench.position));
+
+    let mut csv = String::new();
+    for (i, (name, (x, y))) in spawn.chain(maps).chain(benches).enumerate() {
+        let name = name.replace('"', "\"\"");
+        csv.push_str(&format!("{i},\"{name}\",{x},{y}\n"));
     }
-
-    Ok(())
+    csv
 }
 
-fn gen_lobby<'a>(map: Element<'_>, dialog: &'a Dialog) -> Result<Vec<(&'a str, i32, i32)>> {
-    let rooms = map.child_with_name("levels")?;
-
-    let mut maps = Vec::new();
-    let mut benches = Vec::new();
-
-    let mut default_spawn = None;
-    let mut first_spawn = None;
-    let mut _heart_door = None;
-
-    for room in &rooms.children {
-        let room_pos = (room.get_attr_int("x")?, room.get_attr_int("y")?);
-        let room_name = room.get_attr::<&str>("name")?;
-
-        let Some(triggers) = room.find_child_with_name("triggers") else {
-            continue;
-        };
-
-        let entities = room.child_with_name("entities")?;
-
-        for entity in &entities.children {
-            if entity.name.contains("CollabUtils2/LobbyMapWarp") {
-                let pos = (entity.get_attr_int("x")?, entity.get_attr_int("y")?);
-                let warp_id = entity.get_attr::<&str>("warpId")?;
-
-                let x = room_pos.0 + pos.0;
-                let y = room_pos.1 + pos.1;
-                dbg!(pos);
-                benches.push((warp_id, x, y));
-            }
-            match entity.name {
-                "CollabUtils2/MiniHeartDoor" => {
-                    let pos = (entity.get_attr_int("x")?, entity.get_attr_int("y")?);
-                    let size = (
-                        entity.get_attr_int("height")?,
-                        entity.get_attr_int("width")?,
-                    );
-
-                    _heart_door =
-                        Some((room_pos.0 + pos.0 + size.0 / 2, room_pos.1 + pos.1 + size.1));
-                }
-                "player" => {
-                    let entity_pos = (entity.get_attr_int("x")?, entity.get_attr_int("y")?);
-                    let pos = (room_pos.0 + entity_pos.0, room_pos.1 + entity_pos.1);
-
-                    if first_spawn.is_none() {
-                        first_spawn = Some(pos);
-                    }
-
-                    let is_default_spawn = entity
-                        .attributes
-                        .get("isDefaultSpawn")
-                        .and_then(|a| a.get::<bool>())
-                        .unwrap_or(false);
-
-                    if is_default_spawn && default_spawn.is_none() {
-                        default_spawn = Some(pos);
-                    }
-                }
-                _ => {}
-            }
-        }
-
-        for trigger in &triggers.children {
-            if trigger.name == "CollabUtils2/ChapterPanelTrigger" {
-                let trigger_pos = (trigger.get_attr_int("x")?, trigger.get_attr_int("y")?);
-                let trigger_size = (
-                    trigger.get_attr_int("width")?,
-                    trigger.get_attr_int("height")?,
-                );
-                let map = trigger.get_attr::<&str>("map")?;
-
-                let name = dialog
-                    .get(map)
-                    .with_context(|| format!("getting name of {map} from dialog"))?;
-
-                let room_size = (room.get_attr_int("width")?, room.get_attr_int("height")?);
-                if trigger_pos.0 > room_size.0 || trigger_pos.1 > room_size.1 {
-                    continue;
-                }
+fn main() -> Result<()> {
+    let args = parse_args()?;
 
-                let x = room_pos.0 + trigger_pos.0 + trigger_size.0 / 2;
-                let y = room_pos.1 + trigger_pos.1 + trigger_size.1;
-                maps.push((name, room_name, x, y));
-            }
-        }
+    let mut archive = find_mod(&args.mod_name)?;
+    let dialog = archive.try_get_dialog(&args.lang)?;
+    if dialog.is_none() {
+        eprintln!("warning: no {} dialog, using map SIDs as names", args.lang);
     }
-    maps.sort_by_key(|&(_, room, x, y)| (room, y, x));
-    benches.sort_by_key(|&(id, ..)| id);
 
-    let mut results = Vec::with_capacity(maps.len() + 2);
-
-    if let Some((x, y)) = default_spawn.or(first_spawn) {
-        results.push(("Start", x, y));
-    }
-    for (name, _, x, y) in maps {
-        results.push((name, x, y));
+    let lobbies = celesteloader::lobby::read_lobbies(&mut archive, dialog.as_ref())?;
+    if lobbies.is_empty() {
+        eprintln!("{} has no lobbies", args.mod_name);
     }
 
-    for (warp_id, x, y) in benches {
-        let name = match warp_id.parse::<u8>() {
-            Ok(warp_id) => {
-                let name = (b'A' + warp_id) as char;
-                format!("bench_{}", name).leak()
-            }
-            Err(_) => format!("bench_{}", warp_id).leak(),
-        };
-        results.push((name, x, y));
+    std::fs::create_dir_all(&args.output)?;
+    for lobby in lobbies {
+        if lobby.entrances.is_empty() {
+            continue; // e.g. Prologue
+        }
+
+        let file_name = lobby.sid.rsplit('/').next().unwrap_or(&lobby.sid);
+        let path = args.output.join(format!("{file_name}.csv"));
+        std::fs::write(&path, format_entrances(&lobby))
+            .with_context(|| format!("failed to write {}", path.display()))?;
+
+        eprintln!(
+            "{}: {} maps, {} benches -> {}",
+            lobby.name.as_deref().unwrap_or(&lobby.sid),
+            lobby.entrances.len(),
+            lobby.benches.len(),
+            path.display()
+        );
     }
-    /*if let Some((x, y)) = heart_door {
-        results.push(("Heartside", x, y));
-    }*/
 
-    Ok(results)
+    Ok(())
 }
@@ -1 +1,2 @@
-
+pub mod lobby;
+pub mod route;
@@ -0,0 +1,234 @@
+//! Positions of the maps and benches in a lobby map, for estimating connections which haven't been TASed yet.
+//!
+//! Maps are numbered in the order of the lobby's entrances (sorted by room, then y and x),
+//! benches are named `A`, `B`, ... after their `LobbyMapWarp` warp id.
+
+use celesteloader::lobby::Lobby;
+
+use super::Location;
+
+/// Pixels per frame, roughly running speed with the occasional dash
+pub const DEFAULT_SPEED: f32 = 2.0;
+
+#[derive(Debug)]
+pub struct LobbyGeometry {
+    pub lobby: Lobby,
+}
+
+impl LobbyGeometry {
+    pub fn new(lobby: Lobby) -> LobbyGeometry {
+        LobbyGeometry { lobby }
+    }
+
+    /// Number of maps entered from the lobby
+    pub fn maps(&self) -> usize {
+        self.lobby.entrances.len()
+    }
+
+    /// Position of a node. `0` is the spawn, maps are numbered from `1`
+    pub fn position(&self, location: &Location) -> Option<(i32, i32)> {
+        match location {
+            Location::Map(0) => self.lobby.spawn.as_ref().map(|spawn| spawn.position),
+            Location::Map(i) => self
+                .lobby
+                .entrances
+                .get(*i as usize - 1)
+                .map(|entrance| entrance.position),
+            Location::Bench(name) => self
+                .lobby
+                .benches
+                .iter()
+                .find(|bench| bench.name == *name)
+                .map(|bench| bench.position),
+        }
+    }
+
+    fn room_of(&self, (x, y): (f32, f32)) -> Option<usize> {
+        self.lobby
+            .rooms
+            .iter()
+            .position(|room| room.bounds.contains(x, y))
+    }
+
+    /// Centers of the borders shared by neighbouring rooms
+    fn doors(&self) -> Vec<((f32, f32), [usize; 2])> {
+        let mut doors = Vec::new();
+        let rooms = self.lobby.rooms.iter().map(|room| &room.bounds);
+        for (i, a) in rooms.clone().enumerate() {
+            for (j, b) in rooms.clone().enumerate().skip(i + 1) {
+                let overlap_x = (a.position.x.max(b.position.x), a.r().min(b.r()));
+                let overlap_y = (a.position.y.max(b.position.y), a.b().min(b.b()));
+
+                let door = if a.r() == b.position.x || b.r() == a.position.x {
+                    let x = if a.r() == b.position.x {
+                        a.r()
+                    } else {
+                        a.position.x
+                    };
+                    (overlap_y.0 < overlap_y.1)
+                        .then(|| (x as f32, (overlap_y.0 + overlap_y.1) as f32 / 2.0))
+                } else if a.b() == b.position.y || b.b() == a.position.y {
+                    let y = if a.b() == b.position.y {
+                        a.b()
+                    } else {
+                        a.position.y
+                    };
+                    (overlap_x.0 < overlap_x.1)
+                        .then(|| ((overlap_x.0 + overlap_x.1) as f32 / 2.0, y as f32))
+                } else {
+                    None
+                };
+
+                if let Some(door) = door {
+                    doors.push((door, [i, j]));
+                }
+            }
+        }
+        doors
+    }
+
+    /// Length of the shortest path between two points, going through the centers of room borders
+    pub fn path_distance(&self, from: (i32, i32), to: (i32, i32)) -> Option<f32> {
+        let from = (from.0 as f32, from.1 as f32);
+        let to = (to.0 as f32, to.1 as f32);
+        let from_room = self.room_of(from)?;
+        let to_room = self.room_of(to)?;
+
+        // points: from, to, doors. Two points are connected if they share a room.
+        let mut points = vec![(from, vec![from_room]), (to, vec![to_room])];
+        points.extend(
+            self.doors()
+                .into_iter()
+                .map(|(door, rooms)| (door, rooms.to_vec())),
+        );
+
+        let distance =
+            |a: (f32, f32), b: (f32, f32)| ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt();
+
+        let mut dist = vec![f32::INFINITY; points.len()];
+        let mut done = vec![false; points.len()];
+        dist[0] = 0.0;
+        loop {
+            let current = (0..points.len())
+                .filter(|&i| !done[i] && dist[i].is_finite())
+                .min_by(|&a, &b| dist[a].total_cmp(&dist[b]))?;
+            if current == 1 {
+                return Some(dist[1]);
+            }
+            done[current] = true;
+
+            let (point, rooms) = &points[current];
+            for (next, (other, other_rooms)) in points.iter().enumerate() {
+                if done[next] || !rooms.iter().any(|room| other_rooms.contains(room)) {
+                    continue;
+                }
+                dist[next] = dist[next].min(dist[current] + distance(*point, *other));
+            }
+        }
+    }
+
+    /// Estimated frames from one node to another when moving at `speed` pixels per frame
+    pub fn estimate_frames(&self, from: &Location, to: &Location, speed: f32) -> Option<u32> {
+        let from = self.position(from)?;
+        let to = self.position(to)?;
+        let distance = self.path_distance(from, to)?;
+        Some((distance / speed).ceil() as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use celesteloader::{
+        lobby::{LobbyEntrance, LobbyRoom, LobbySpawn},
+        map::{Bounds, Pos},
+    };
+
+    use super::*;
+
+    fn room(name: &str, position: (i32, i32), size: (u32, u32)) -> LobbyRoom {
+        LobbyRoom {
+            name: name.to_owned(),
+            bounds: Bounds {
+                position: Pos {
+                    x: position.0,
+                    y: position.1,
+                },
+                size,
+            },
+        }
+    }
+
+    fn entrance(room: &str, position: (i32, i32)) -> LobbyEntrance {
+        LobbyEntrance {
+            sid: String::new(),
+            name: None,
+            room: room.to_owned(),
+            position,
+        }
+    }
+
+    /// `a` and `b` side by side with a door at (100, 50), `c` below `b` with a door at (150, 100).
+    /// `a` and `c` only touch at a corner.
+    fn geometry() -> LobbyGeometry {
+        LobbyGeometry::new(Lobby {
+            sid: String::new(),
+            name: None,
+            spawn: Some(LobbySpawn {
+                room: "a".into(),
+                position: (50, 90),
+            }),
+            entrances: vec![
+                entrance("a", (20, 50)),
+                entrance("b", (180, 50)),
+                entrance("c", (150, 140)),
+            ],
+            benches: Vec::new(),
+            heart_door: None,
+            rooms: vec![
+                room("a", (0, 0), (100, 100)),
+                room("b", (100, 0), (100, 100)),
+                room("c", (100, 100), (100, 50)),
+            ],
+        })
+    }
+
+    #[test]
+    fn distances_through_doors() {
+        let geometry = geometry();
+        let close = |a: Option<f32>, b: f32| (a.unwrap() - b).abs() < 1e-3;
+
+        // same room
+        assert!(close(geometry.path_distance((20, 50), (50, 90)), 50.0));
+        // through the a-b door
+        assert!(close(geometry.path_distance((20, 50), (180, 50)), 160.0));
+        // through both doors, not the corner a and c share
+        let a_to_c = 80.0 + 50.0 * 2f32.sqrt() + 40.0;
+        assert!(close(geometry.path_distance((20, 50), (150, 140)), a_to_c));
+        assert!(close(geometry.path_distance((150, 140), (20, 50)), a_to_c));
+        // outside of every room
+        assert_eq!(geometry.path_distance((20, 50), (300, 50)), None);
+    }
+
+    #[test]
+    fn estimated_frames() {
+        let geometry = geometry();
+        assert_eq!(geometry.maps(), 3);
+        assert_eq!(
+            geometry.estimate_frames(&Location::Map(1), &Location::Map(2), 2.0),
+            Some(80)
+        );
+        // 95.36 frames, rounded up
+        assert_eq!(
+            geometry.estimate_frames(&Location::Map(1), &Location::Map(3), 2.0),
+            Some(96)
+        );
+        assert_eq!(
+            geometry.estimate_frames(&Location::Map(0), &Location::Map(1), 1.0),
+            Some(50)
+        );
+        assert_eq!(
+            geometry.estimate_frames(&Location::Map(1), &Location::Map(4), 2.0),
+            None
+        );
+    }
+}
@@ -0,0 +1,495 @@
+//! Connection times between the maps and benches of a collab lobby, collected from a folder of TAS files.
+//!
+//! Files are named `{prefix}_{start}-{end}.tas`, where `start` and `end` are either map numbers
+//! (`0` being the lobby start) or bench names.
+
+pub mod geometry;
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, ensure, Context, Result};
+use celesteloader::tas::TasFile;
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+/// Frames for each `start -> end` map connection
+pub type Connections = BTreeMap<u32, BTreeMap<u32, u32>>;
+pub type RoutedConnections = BTreeMap<u32, BTreeMap<u32, Connection>>;
+
+#[derive(Debug)]
+pub struct NodePath<'a> {
+    pub prefix: &'a str,
+    pub start: &'a str,
+    pub end: &'a str,
+}
+impl std::fmt::Display for NodePath<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}_{}-{}", self.prefix, self.start, self.end)
+    }
+}
+
+pub fn node_path(stem: &str) -> Option<NodePath<'_>> {
+    let (prefix, rest) = stem.split_once('_')?;
+    let (from, to) = rest.split_once('-')?;
+
+    if !from.chars().all(char::is_alphanumeric) || !to.chars().all(char::is_alphanumeric) {
+        return None;
+    }
+
+    Some(NodePath {
+        prefix,
+        start: from,
+        end: to,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Location {
+    Bench(String),
+    Map(u32),
+}
+impl FromStr for Location {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<u32>() {
+            Ok(id) => Ok(Location::Map(id)),
+            Err(_) => Ok(Location::Bench(s.to_owned())),
+        }
+    }
+}
+
+/// How warping between benches is priced
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct BenchSettings {
+    /// Frames spent in the menu when warping to a bench without an entry in `costs`
+    pub warp_cost: u32,
+    /// Warp cost per target bench
+    pub costs: BTreeMap<String, u32>,
+    /// Benches which can't be warped to
+    pub ignore: Vec<String>,
+}
+
+impl BenchSettings {
+    /// Frames it takes to warp to `bench`, or `None` if it can't be warped to
+    pub fn warp_cost(&self, bench: &str) -> Option<u32> {
+        if self.ignore.iter().any(|ignored| ignored == bench) {
+            return None;
+        }
+        Some(self.costs.get(bench).copied().unwrap_or(self.warp_cost))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Via {
+    Direct,
+    /// Through these benches in order, walking or warping between them
+    Bench {
+        benches: Vec<String>,
+    },
+    /// Not TASed, guessed from the lobby layout
+    Estimated,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Connection {
+    pub time: u32,
+    pub via: Via,
+}
+
+#[derive(Debug, Default)]
+pub struct Lobby {
+    pub prefix: Option<String>,
+    /// Highest map number
+    pub maps: u32,
+    /// TASed map to map connections
+    pub connections: Connections,
+    /// Frames from a map to a bench
+    pub to_bench: BTreeMap<u32, BTreeMap<String, u32>>,
+    /// Frames from a bench to a map
+    pub from_bench: BTreeMap<String, BTreeMap<u32, u32>>,
+    /// Frames for walking from one bench to another
+    pub between_benches: BTreeMap<String, BTreeMap<String, u32>>,
+}
+
+impl Lobby {
+    /// Reads the time comments of all lobby files in `path`, or only of those in `only_paths`
+    pub fn collect(path: &Path, only_paths: Option<&[PathBuf]>) -> Result<Lobby> {
+        let mut lobby = Lobby::default();
+
+        for entry in WalkDir::new(path) {
+            let entry = entry?;
+            let path = entry.path();
+
+            if only_paths.is_some_and(|only_paths| !only_paths.iter().any(|p| p == path)) {
+                continue;
+            }
+
+            if path.extension().is_none_or(|ext| ext != "tas") {
+                continue;
+            }
+
+            ensure!(
+                entry.metadata()?.is_file(),
+                "{} is not a file",
+                path.display()
+            );
+
+            let stem = path
+                .file_stem()
+                .unwrap()
+                .to_str()
+                .ok_or_else(|| anyhow!("non-UTF8 path: {}", path.display()))?;
+            // lines we can't parse don't matter for the time comment
+            let text = std::fs::read_to_string(path)?;
+            let (tas, _) = TasFile::parse_lenient(&text);
+            let time = tas
+                .time_comment()
+                .ok_or_else(|| anyhow!("could not find time comment"))
+                .with_context(|| format!("could not extract time from {}", path.display()))?
+                .frames;
+
+            ensure!(
+                !tas.has_breakpoint(),
+                "{} contains a breakpoint",
+                path.display()
+            );
+
+            lobby.insert(stem, time)?;
+        }
+
+        ensure!(!lobby.connections.is_empty(), "no nodes present");
+
+        Ok(lobby)
+    }
+
+    /// Adds the time of the lobby file named `stem`
+    pub fn insert(&mut self, stem: &str, time: u32) -> Result<()> {
+        let node = node_path(stem).ok_or_else(|| anyhow!("invalid filename: {stem}"))?;
+
+        let prefix = self.prefix.get_or_insert(node.prefix.to_owned());
+        ensure!(
+            node.prefix == prefix,
+            "Lobby prefix '{}' is not the same as '{}'",
+            node.prefix,
+            prefix,
+        );
+
+        let start = node
+            .start
+            .parse::<Location>()
+            .context("failed to parse start node")?;
+        let end = node
+            .end
+            .parse::<Location>()
+            .context("failed to parse end node")?;
+
+        match (start, end) {
+            (Location::Map(start), Location::Map(end)) => {
+                self.maps = self.maps.max(start).max(end);
+                self.connections.entry(start).or_default().insert(end, time);
+            }
+            (Location::Bench(start), Location::Map(end)) => {
+                self.maps = self.maps.max(end);
+                self.from_bench.entry(start).or_default().insert(end, time);
+            }
+            (Location::Map(start), Location::Bench(end)) => {
+                self.maps = self.maps.max(start);
+                self.to_bench.entry(start).or_default().insert(end, time);
+            }
+            (Location::Bench(start), Location::Bench(end)) => {
+                self.between_benches
+                    .entry(start)
+                    .or_default()
+                    .insert(end, time);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn benches(&self) -> BTreeSet<&str> {
+        self.to_bench
+            .values()
+            .flat_map(|benches| benches.keys())
+            .chain(self.from_bench.keys())
+            .chain(self.between_benches.keys())
+            .chain(
+                self.between_benches
+                    .values()
+                    .flat_map(|benches| benches.keys()),
+            )
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Maps and, if `benches` is given, benches connected by the lobby files and warps
+    pub fn graph(&self, benches: Option<&BenchSettings>) -> LobbyGraph {
+        let mut graph = LobbyGraph {
+            maps: self.maps,
+            edges: BTreeMap::new(),
+        };
+        for (&start, targets) in &self.connections {
+            for (&end, &time) in targets {
+                graph.add_edge(
+                    Location::Map(start),
+                    Location::Map(end),
+                    time,
+                    EdgeKind::Tas,
+                );
+            }
+        }
+
+        let Some(settings) = benches else {
+            return graph;
+        };
+
+        for (&start, targets) in &self.to_bench {
+            for (end, &time) in targets {
+                graph.add_edge(
+                    Location::Map(start),
+                    Location::Bench(end.clone()),
+                    time,
+                    EdgeKind::Tas,
+                );
+            }
+        }
+        for (start, targets) in &self.from_bench {
+            for (&end, &time) in targets {
+                graph.add_edge(
+                    Location::Bench(start.clone()),
+                    Location::Map(end),
+                    time,
+                    EdgeKind::Tas,
+                );
+            }
+        }
+        for (start, targets) in &self.between_benches {
+            for (end, &time) in targets {
+                graph.add_edge(
+                    Location::Bench(start.clone()),
+                    Location::Bench(end.clone()),
+                    time,
+                    EdgeKind::Tas,
+                );
+            }
+        }
+
+        let benches = self.benches();
+        for &start in &benches {
+            for &end in &benches {
+                if let Some(warp) = settings.warp_cost(end).filter(|_| start != end) {
+                    graph.add_edge(
+                        Location::Bench(start.to_owned()),
+                        Location::Bench(end.to_owned()),
+                        warp,
+                        EdgeKind::Warp,
+                    );
+                }
+            }
+        }
+
+        graph
+    }
+}
+
+/// How an edge of a [`LobbyGraph`] is travelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// A lobby file
+    Tas,
+    /// Warping to a bench from the menu
+    Warp,
+    /// Not TASed, guessed from the lobby layout
+    Estimated,
+}
+
+/// The maps and benches of a lobby, with the fastest known way along each edge.
+#[derive(Debug, Clone, Default)]
+pub struct LobbyGraph {
+    /// Highest map number
+    pub maps: u32,
+    pub edges: BTreeMap<Location, BTreeMap<Location, (u32, EdgeKind)>>,
+}
+
+impl LobbyGraph {
+    /// Adds the edge unless a faster one is already present
+    pub fn add_edge(&mut self, from: Location, to: Location, time: u32, kind: EdgeKind) {
+        let targets = self.edges.entry(from).or_default();
+        if targets.get(&to).is_none_or(|&(other, _)| time < other) {
+            targets.insert(to, (time, kind));
+        }
+    }
+
+    pub fn edge(&self, from: &Location, to: &Location) -> Option<(u32, EdgeKind)> {
+        self.edges.get(from)?.get(to).copied()
+    }
+
+    pub fn benches(&self) -> BTreeSet<&str> {
+        self.edges
+            .iter()
+            .flat_map(|(from, targets)| std::iter::once(from).chain(targets.keys()))
+            .filter_map(|location| match location {
+                Location::Bench(name) => Some(name.as_str()),
+                Location::Map(_) => None,
+            })
+            .collect()
+    }
+
+    /// The fastest way between each pair of maps, either through the direct TAS or through benches.
+    /// Paths through other maps are left to the route solver.
+    pub fn routed_connections(&self) -> RoutedConnections {
+        let mut routed = RoutedConnections::new();
+
+        for start in 0..=self.maps {
+            let start = Location::Map(start);
+
+            // Dijkstra, only continuing from benches
+            let mut best: BTreeMap<&Location, (u32, Option<&Location>)> = BTreeMap::new();
+            let mut queue = BTreeSet::from([(0, &start)]);
+            best.insert(&start, (0, None));
+            while let Some((time, node)) = queue.pop_first() {
+                if node != &start && matches!(node, Location::Map(_)) {
+                    continue;
+                }
+                for (next, &(edge, _)) in self.edges.get(node).into_iter().flatten() {
+                    let next_time = time + edge;
+                    if best.get(next).is_none_or(|&(other, _)| next_time < other) {
+                        best.insert(next, (next_time, Some(node)));
+                        queue.insert((next_time, next));
+                    }
+                }
+            }
+
+            for (&end, &(time, previous)) in &best {
+                let Location::Map(end_map) = *end else {
+                    continue;
+                };
+                if end == &start {
+                    continue;
+                }
+
+                let mut benches = Vec::new();
+                let mut previous = previous;
+                while let Some(node @ Location::Bench(name)) = previous {
+                    benches.push(name.clone());
+                    previous = best[node].1;
+                }
+                benches.reverse();
+
+                let via = match self.edge(&start, end) {
+                    _ if !benches.is_empty() => Via::Bench { benches },
+                    Some((_, EdgeKind::Estimated)) => Via::Estimated,
+                    _ => Via::Direct,
+                };
+                let Location::Map(start) = start else {
+                    unreachable!()
+                };
+                routed
+                    .entry(start)
+                    .or_default()
+                    .insert(end_map, Connection { time, via });
+            }
+        }
+
+        routed
+    }
+
+    /// Adds estimated edges between maps `0..=maps` which can't be reached through TASes or benches
+    pub fn estimate_missing(
+        &mut self,
+        maps: u32,
+        mut estimate: impl FnMut(u32, u32) -> Option<u32>,
+    ) {
+        self.maps = self.maps.max(maps);
+        let routed = self.routed_connections();
+
+        for start in 0..=self.maps {
+            for end in 1..=self.maps {
+                if start == end
+                    || routed
+                        .get(&start)
+                        .is_some_and(|targets| targets.contains_key(&end))
+                {
+                    continue;
+                }
+                if let Some(time) = estimate(start, end) {
+                    self.add_edge(
+                        Location::Map(start),
+                        Location::Map(end),
+                        time,
+                        EdgeKind::Estimated,
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lobby(files: &[(&str, u32)]) -> Lobby {
+        let mut lobby = Lobby::default();
+        for &(stem, time) in files {
+            lobby.insert(stem, time).unwrap();
+        }
+        lobby
+    }
+
+    #[test]
+    fn routes_through_bench_chains() {
+        let lobby = lobby(&[
+            ("lvl_0-1", 100),
+            ("lvl_1-2", 500),
+            ("lvl_1-A", 10),
+            ("lvl_B-C", 5),
+            ("lvl_C-2", 30),
+            ("lvl_2-C", 5),
+            ("lvl_C-1", 5),
+        ]);
+        let settings = BenchSettings {
+            warp_cost: 50,
+            costs: BTreeMap::from([("B".to_owned(), 15)]),
+            ignore: vec!["C".to_owned()],
+        };
+
+        let routed = lobby.graph(None).routed_connections();
+        assert_eq!(routed[&1][&2].time, 500);
+        assert!(!routed.contains_key(&2));
+
+        let routed = lobby.graph(Some(&settings)).routed_connections();
+        // walk to A, warp to B, walk to C and on to 2, as C can't be warped to
+        assert_eq!(
+            routed[&1][&2],
+            Connection {
+                time: 10 + 15 + 5 + 30,
+                via: Via::Bench {
+                    benches: vec!["A".into(), "B".into(), "C".into()]
+                }
+            }
+        );
+        // C is reused on the way back
+        assert_eq!(routed[&2][&1].time, 10);
+        assert_eq!(routed[&0][&1].via, Via::Direct);
+    }
+
+    #[test]
+    fn estimates_only_unreachable_connections() {
+        let mut graph = lobby(&[("lvl_0-1", 100), ("lvl_1-A", 10), ("lvl_A-2", 10)])
+            .graph(Some(&BenchSettings::default()));
+        graph.estimate_missing(2, |_, _| Some(1000));
+
+        let routed = graph.routed_connections();
+        assert_eq!(routed[&1][&2].time, 20);
+        assert_eq!(routed[&0][&2].via, Via::Estimated);
+        assert_eq!(routed[&2][&1].time, 1000);
+    }
+}
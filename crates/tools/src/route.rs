@@ -0,0 +1,591 @@
+//! Finding the fastest order to visit every map of a lobby.
+//!
+//! Node `0` is the lobby start. Connections which aren't TASed directly may still be reachable through other nodes,
+//! e.g. by restarting (going back to `0`), by walking through another map's entrance or by warping between benches,
+//! so the solver works on the shortest-path closure of the connection matrix.
+//! Benches are waypoints: routes may pass through them any number of times, but don't have to visit them.
+//!
+//! Small lobbies are solved exactly using a DP over subsets, large ones with nearest neighbour construction
+//! followed by 2-opt and Or-opt improvements from many random starts.
+
+use anyhow::{bail, ensure, Result};
+
+/// Lobbies with at most this many maps are solved exactly
+pub const EXACT_LIMIT: usize = 13;
+
+const RESTARTS: usize = 200;
+
+/// Directed travel times between lobby nodes
+#[derive(Debug, Clone)]
+pub struct Matrix {
+    size: usize,
+    /// Nodes `required..size` are waypoints
+    required: usize,
+    costs: Vec<Option<u32>>,
+}
+
+impl Matrix {
+    pub fn new(size: usize) -> Self {
+        Matrix::with_waypoints(size, 0)
+    }
+
+    /// A matrix of `required` nodes every route has to visit, followed by `waypoints` nodes it may pass through
+    pub fn with_waypoints(required: usize, waypoints: usize) -> Self {
+        let size = required + waypoints;
+        Matrix {
+            size,
+            required,
+            costs: vec![None; size * size],
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Number of nodes which have to be visited, including the start
+    pub fn required(&self) -> usize {
+        self.required
+    }
+
+    pub fn get(&self, from: usize, to: usize) -> Option<u32> {
+        if from == to {
+            return Some(0);
+        }
+        self.costs[from * self.size + to]
+    }
+
+    pub fn set(&mut self, from: usize, to: usize, cost: u32) {
+        self.costs[from * self.size + to] = Some(cost);
+    }
+
+    /// Sets the connection unless a faster one is already present
+    pub fn set_min(&mut self, from: usize, to: usize, cost: u32) {
+        let current = &mut self.costs[from * self.size + to];
+        *current = Some(current.map_or(cost, |current| current.min(cost)));
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RouteSettings {
+    /// Node the route has to end on
+    pub end: Option<usize>,
+    /// How many routes to return, the fastest one first
+    pub routes: usize,
+    /// Whether the route may go back to the start (node `0`) by restarting
+    pub restarts: bool,
+}
+
+impl Default for RouteSettings {
+    fn default() -> Self {
+        RouteSettings {
+            end: None,
+            routes: 3,
+            restarts: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    /// Order in which the maps are visited, starting with `0`
+    pub order: Vec<usize>,
+    /// For every step in `order`, the nodes passed through on the way including the target, e.g. benches
+    pub legs: Vec<Vec<usize>>,
+    pub frames: u32,
+}
+
+/// Shortest paths between all nodes
+struct Closure {
+    size: usize,
+    /// Nodes `1..required` have to be visited
+    required: usize,
+    dist: Vec<Option<u32>>,
+    next: Vec<usize>,
+}
+
+impl Closure {
+    fn new(matrix: &Matrix) -> Closure {
+        let size = matrix.size;
+        let required = matrix.required;
+        let mut dist = matrix.costs.clone();
+        let mut next = vec![usize::MAX; size * size];
+        for i in 0..size {
+            dist[i * size + i] = Some(0);
+            for j in 0..size {
+                if dist[i * size + j].is_some() {
+                    next[i * size + j] = j;
+                }
+            }
+        }
+
+        for k in 0..size {
+            for i in 0..size {
+                let Some(ik) = dist[i * size + k] else {
+                    continue;
+                };
+                for j in 0..size {
+                    let Some(kj) = dist[k * size + j] else {
+                        continue;
+                    };
+                    if dist[i * size + j].is_none_or(|ij| ik + kj < ij) {
+                        dist[i * size + j] = Some(ik + kj);
+                        next[i * size + j] = next[i * size + k];
+                    }
+                }
+            }
+        }
+
+        Closure {
+            size,
+            required,
+            dist,
+            next,
+        }
+    }
+
+    fn dist(&self, from: usize, to: usize) -> Option<u32> {
+        self.dist[from * self.size + to]
+    }
+
+    fn path(&self, from: usize, to: usize) -> Vec<usize> {
+        let mut path = Vec::new();
+        let mut current = from;
+        while current != to {
+            current = self.next[current * self.size + to];
+            path.push(current);
+        }
+        path
+    }
+}
+
+/// Finds the fastest routes starting at `0` and visiting every other required node at least once.
+pub fn solve(matrix: &Matrix, settings: &RouteSettings) -> Result<Vec<Route>> {
+    let size = matrix.size();
+    let required = matrix.required();
+    ensure!(required >= 2, "lobby has no maps");
+    if let Some(end) = settings.end {
+        ensure!(
+            (1..required).contains(&end),
+            "end node {end} is not part of the lobby"
+        );
+    }
+
+    let closure = if settings.restarts {
+        Closure::new(matrix)
+    } else {
+        let mut matrix = matrix.clone();
+        for from in 1..size {
+            matrix.costs[from * size] = None;
+        }
+        Closure::new(&matrix)
+    };
+    for node in 1..required {
+        if closure.dist(0, node).is_none() {
+            bail!("map {node} can not be reached from the start");
+        }
+    }
+
+    // different visiting orders can walk the same path, so look for some extra candidates
+    let candidates = settings.routes.max(1) * 4;
+    let orders = if required - 1 <= EXACT_LIMIT {
+        solve_exact(&closure, settings.end, candidates)
+    } else {
+        solve_heuristic(&closure, settings.end, candidates)
+    };
+    ensure!(
+        !orders.is_empty(),
+        "no route visits every map (is the end node reachable?)"
+    );
+
+    let mut routes: Vec<Route> = Vec::new();
+    for (frames, order) in orders {
+        let legs = order
+            .windows(2)
+            .map(|step| closure.path(step[0], step[1]))
+            .collect::<Vec<_>>();
+        if routes
+            .iter()
+            .any(|route| route.legs.concat() == legs.concat())
+        {
+            continue;
+        }
+        routes.push(Route {
+            order,
+            legs,
+            frames,
+        });
+    }
+    routes.truncate(settings.routes.max(1));
+
+    Ok(routes)
+}
+
+fn route_cost(closure: &Closure, order: &[usize]) -> Option<u32> {
+    order
+        .windows(2)
+        .try_fold(0, |acc, step| Some(acc + closure.dist(step[0], step[1])?))
+}
+
+/// Held-Karp over the maps `1..size`, keeping the `k` best partial routes per state.
+fn solve_exact(closure: &Closure, end: Option<usize>, k: usize) -> Vec<(u32, Vec<usize>)> {
+    let maps = closure.required - 1;
+    let full = (1usize << maps) - 1;
+
+    // (cost, previous map, rank of the entry in the previous state)
+    type Entry = (u32, usize, usize);
+    let mut dp: Vec<Vec<Entry>> = vec![Vec::new(); (full + 1) * maps];
+    let state = |mask: usize, last: usize| mask * maps + last;
+
+    for last in 0..maps {
+        if let Some(cost) = closure.dist(0, last + 1) {
+            dp[state(1 << last, last)].push((cost, usize::MAX, 0));
+        }
+    }
+
+    for mask in 1..=full {
+        for last in 0..maps {
+            if mask & (1 << last) == 0 || dp[state(mask, last)].is_empty() {
+                continue;
+            }
+            if Some(last + 1) == end && mask != full {
+                // the end node may only be visited last
+                continue;
+            }
+
+            for next in 0..maps {
+                if mask & (1 << next) != 0 {
+                    continue;
+                }
+                let Some(step) = closure.dist(last + 1, next + 1) else {
+                    continue;
+                };
+                let next_state = state(mask | (1 << next), next);
+                for rank in 0..dp[state(mask, last)].len() {
+                    let cost = dp[state(mask, last)][rank].0 + step;
+                    insert_k_best(&mut dp[next_state], (cost, last, rank), k);
+                }
+            }
+        }
+    }
+
+    let mut finals = Vec::new();
+    for last in 0..maps {
+        if end.is_some_and(|end| end != last + 1) {
+            continue;
+        }
+        for (rank, &(cost, ..)) in dp[state(full, last)].iter().enumerate() {
+            finals.push((cost, last, rank));
+        }
+    }
+    finals.sort();
+    finals.truncate(k);
+
+    finals
+        .into_iter()
+        .map(|(cost, last, rank)| {
+            let mut order = Vec::with_capacity(maps + 1);
+            let (mut mask, mut last, mut rank) = (full, last, rank);
+            loop {
+                order.push(last + 1);
+                let (_, prev, prev_rank) = dp[state(mask, last)][rank];
+                if prev == usize::MAX {
+                    break;
+                }
+                mask &= !(1 << last);
+                last = prev;
+                rank = prev_rank;
+            }
+            order.push(0);
+            order.reverse();
+            (cost, order)
+        })
+        .collect()
+}
+
+fn insert_k_best(entries: &mut Vec<(u32, usize, usize)>, entry: (u32, usize, usize), k: usize) {
+    if entries.len() == k && entries.last().is_some_and(|last| entry.0 >= last.0) {
+        return;
+    }
+    let index = entries.partition_point(|other| other.0 <= entry.0);
+    entries.insert(index, entry);
+    entries.truncate(k);
+}
+
+/// Randomized nearest neighbour followed by local search, keeping the best distinct local optima
+fn solve_heuristic(closure: &Closure, end: Option<usize>, k: usize) -> Vec<(u32, Vec<usize>)> {
+    let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+    let mut found: Vec<(u32, Vec<usize>)> = Vec::new();
+
+    for restart in 0..RESTARTS {
+        let randomness = if restart == 0 { 0 } else { 3 };
+        let Some(mut order) = nearest_neighbour(closure, end, &mut rng, randomness) else {
+            continue;
+        };
+        local_search(closure, end, &mut order);
+
+        let Some(cost) = route_cost(closure, &order) else {
+            continue;
+        };
+        if !found.iter().any(|(_, other)| *other == order) {
+            found.push((cost, order));
+        }
+    }
+
+    found.sort();
+    found.truncate(k);
+    found
+}
+
+/// Builds a route by repeatedly going to one of the `randomness + 1` closest unvisited nodes
+fn nearest_neighbour(
+    closure: &Closure,
+    end: Option<usize>,
+    rng: &mut XorShift,
+    randomness: usize,
+) -> Option<Vec<usize>> {
+    let size = closure.required;
+    let mut visited = vec![false; size];
+    visited[0] = true;
+    let mut order = vec![0];
+
+    for remaining in (1..size).rev() {
+        let current = *order.last().unwrap();
+        let mut candidates = (1..size)
+            .filter(|&node| !visited[node])
+            .filter(|&node| remaining == 1 || end != Some(node))
+            .filter_map(|node| Some((closure.dist(current, node)?, node)))
+            .collect::<Vec<_>>();
+        candidates.sort();
+
+        let pick = rng.below(candidates.len().min(randomness + 1));
+        let (_, node) = *candidates.get(pick)?;
+        visited[node] = true;
+        order.push(node);
+    }
+
+    Some(order)
+}
+
+/// 2-opt (segment reversal) and Or-opt (moving segments of up to three nodes) until no move improves the route.
+/// The first node stays fixed, and so does the last one if an end node is required.
+fn local_search(closure: &Closure, end: Option<usize>, order: &mut Vec<usize>) {
+    let fixed_end = usize::from(end.is_some());
+    let cost = |order: &[usize]| route_cost(closure, order).unwrap_or(u32::MAX);
+
+    let mut best = cost(order);
+    let mut improved = true;
+    while improved {
+        improved = false;
+        let len = order.len();
+
+        // 2-opt
+        for i in 1..len - fixed_end {
+            for j in i + 1..len - fixed_end {
+                order[i..=j].reverse();
+                let new = cost(order);
+                if new < best {
+                    best = new;
+                    improved = true;
+                } else {
+                    order[i..=j].reverse();
+                }
+            }
+        }
+
+        // Or-opt
+        for segment_len in 1..=3 {
+            let mut i = 1;
+            while i + segment_len <= len - fixed_end {
+                let segment = order[i..i + segment_len].to_vec();
+                let mut rest = order.clone();
+                rest.drain(i..i + segment_len);
+
+                let mut best_move = None;
+                for insert_at in 1..=rest.len() - fixed_end {
+                    if insert_at == i {
+                        continue;
+                    }
+                    let mut candidate = rest.clone();
+                    candidate.splice(insert_at..insert_at, segment.iter().copied());
+                    let new = cost(&candidate);
+                    if new < best && best_move.as_ref().is_none_or(|(c, _)| new < *c) {
+                        best_move = Some((new, candidate));
+                    }
+                }
+
+                if let Some((new, candidate)) = best_move {
+                    best = new;
+                    *order = candidate;
+                    improved = true;
+                }
+                i += 1;
+            }
+        }
+    }
+}
+
+struct XorShift(u64);
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        if n == 0 {
+            return 0;
+        }
+        (self.next() % n as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn random_matrix(rng: &mut XorShift, size: usize) -> Matrix {
+        let mut matrix = Matrix::new(size);
+        for from in 0..size {
+            for to in 0..size {
+                // leave some connections out, but always allow going back to the start
+                if from != to && (to == 0 || rng.below(4) != 0) {
+                    matrix.set(from, to, 1 + rng.below(300) as u32);
+                }
+            }
+        }
+        matrix
+    }
+
+    fn permutations(items: &[usize]) -> Vec<Vec<usize>> {
+        if items.is_empty() {
+            return vec![Vec::new()];
+        }
+        let mut out = Vec::new();
+        for (i, &first) in items.iter().enumerate() {
+            let mut rest = items.to_vec();
+            rest.remove(i);
+            for mut permutation in permutations(&rest) {
+                permutation.insert(0, first);
+                out.push(permutation);
+            }
+        }
+        out
+    }
+
+    /// Costs of all orders visiting every map, sorted
+    fn brute_force(closure: &Closure, end: Option<usize>) -> Vec<u32> {
+        let maps = (1..closure.required).collect::<Vec<_>>();
+        let mut costs = permutations(&maps)
+            .into_iter()
+            .filter(|order| end.is_none() || order.last() == end.as_ref())
+            .filter_map(|order| route_cost(closure, &[&[0], order.as_slice()].concat()))
+            .collect::<Vec<_>>();
+        costs.sort();
+        costs
+    }
+
+    #[test]
+    fn exact_matches_brute_force() {
+        let mut rng = XorShift(0x1234_5678_9abc_def1);
+        for size in 2..=7 {
+            for _ in 0..20 {
+                let closure = Closure::new(&random_matrix(&mut rng, size));
+                for end in [None, Some(size - 1)] {
+                    let expected = brute_force(&closure, end);
+                    let found = solve_exact(&closure, end, 5);
+
+                    let costs = found.iter().map(|(cost, _)| *cost).collect::<Vec<_>>();
+                    assert_eq!(costs, expected[..expected.len().min(5)]);
+                    for (cost, order) in &found {
+                        assert_eq!(route_cost(&closure, order), Some(*cost));
+                        assert_eq!(order[0], 0);
+                        if let Some(end) = end {
+                            assert_eq!(order.last(), Some(&end));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn heuristic_never_beats_exact() {
+        let mut rng = XorShift(0x0fed_cba9_8765_4321);
+        for size in 3..=9 {
+            for _ in 0..10 {
+                let closure = Closure::new(&random_matrix(&mut rng, size));
+                for end in [None, Some(1)] {
+                    let (optimum, _) = solve_exact(&closure, end, 1)[0];
+                    for (cost, order) in solve_heuristic(&closure, end, 3) {
+                        assert!(cost >= optimum, "{cost} < optimum {optimum}");
+                        assert_eq!(route_cost(&closure, &order), Some(cost));
+
+                        let mut visited = order.clone();
+                        visited.sort();
+                        assert_eq!(visited, (0..size).collect::<Vec<_>>());
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn restarts_are_optional() {
+        // 1 and 2 are only connected through the start
+        let mut matrix = Matrix::new(3);
+        matrix.set(0, 1, 10);
+        matrix.set(0, 2, 10);
+        matrix.set(1, 0, 5);
+        matrix.set(2, 0, 5);
+
+        let routes = solve(&matrix, &RouteSettings::default()).unwrap();
+        assert_eq!(routes[0].frames, 25);
+        assert_eq!(routes[0].legs[1][0], 0);
+
+        let settings = RouteSettings {
+            restarts: false,
+            ..Default::default()
+        };
+        assert!(solve(&matrix, &settings).is_err());
+
+        matrix.set(1, 2, 40);
+        let routes = solve(&matrix, &settings).unwrap();
+        assert_eq!(routes[0].order, [0, 1, 2]);
+        assert_eq!(routes[0].frames, 50);
+    }
+
+    #[test]
+    fn waypoints_are_optional() {
+        // 3 and 4 are benches: 1 -> 3 -> warp -> 4 -> 2 is faster than 1 -> 2, and 4 can be reused
+        let mut matrix = Matrix::with_waypoints(3, 2);
+        matrix.set(0, 1, 10);
+        matrix.set(1, 2, 100);
+        matrix.set(1, 3, 5);
+        matrix.set(3, 4, 20);
+        matrix.set(4, 2, 5);
+        matrix.set(2, 4, 5);
+        matrix.set(4, 1, 5);
+
+        let routes = solve(&matrix, &RouteSettings::default()).unwrap();
+        assert_eq!(routes[0].order, [0, 1, 2]);
+        assert_eq!(routes[0].legs, [vec![1], vec![3, 4, 2]]);
+        assert_eq!(routes[0].frames, 40);
+
+        let settings = RouteSettings {
+            end: Some(1),
+            ..Default::default()
+        };
+        let routes = solve(&matrix, &settings).unwrap();
+        assert_eq!(routes[0].order, [0, 2, 1]);
+        assert_eq!(routes[0].legs[1], [4, 1]);
+
+        let settings = RouteSettings {
+            end: Some(3),
+            ..Default::default()
+        };
+        assert!(solve(&matrix, &settings).is_err());
+    }
+}